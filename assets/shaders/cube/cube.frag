@@ -3,15 +3,25 @@
 in VS_OUTPUT {
     vec2 tex_coord;
     vec3 normal;
+    vec4 tangent;
     vec3 frag_pos;
+    vec4 color;
 } IN;
 
 out vec4 Color;
 
+#define ALPHA_OPAQUE 0
+#define ALPHA_MASK 1
+#define ALPHA_BLEND 2
+
 struct Material {
     sampler2D diffuse;
     sampler2D specular;
     float shininess;
+
+    int alpha_mode;
+    float alpha_cutoff;
+    float opacity;
 };
 
 struct MaterialColor {
@@ -38,6 +48,20 @@ struct PointLight {
     float attn_quadratic;
 };
 
+// Values of debug_view, see ShadingView in debug_view.rs
+#define VIEW_LIT 0
+#define VIEW_ALBEDO 1
+#define VIEW_SPECULAR 2
+#define VIEW_ROUGHNESS 3
+#define VIEW_METALLIC 4
+#define VIEW_NORMALS 5
+#define VIEW_CHECKERBOARD 6
+#define VIEW_DEPTH 7
+#define VIEW_WIREFRAME 8
+
+uniform int debug_view;
+uniform float far_plane;
+
 uniform Material material;
 uniform DirectionalLight directional_light;
 #define NUM_POINT_LIGHTS 4
@@ -85,12 +109,53 @@ vec3 calc_point_light(PointLight light, vec3 normal, vec3 frag_pos,
     return (ambient + diffuse + specular) * attenuation;
 }
 
+vec3 debug_color(vec3 normal, MaterialColor mat_color)
+{
+    switch (debug_view) {
+    case VIEW_ALBEDO:
+        return mat_color.diffuse;
+    case VIEW_SPECULAR:
+        return mat_color.specular;
+    case VIEW_ROUGHNESS:
+        return vec3(sqrt(2.0 / (material.shininess + 2.0)));
+    case VIEW_METALLIC:
+        // Dielectrics reflect about 4%, metals reflect their own color much more
+        float reflectance = max(mat_color.specular.r, max(mat_color.specular.g, mat_color.specular.b));
+        return vec3(clamp((reflectance - 0.04) / 0.96, 0.0, 1.0));
+    case VIEW_NORMALS:
+        return normal * 0.5 + 0.5;
+    case VIEW_CHECKERBOARD:
+        vec2 cell = floor(IN.tex_coord * 8.0);
+        float checker = mod(cell.x + cell.y, 2.0);
+        return mix(vec3(0.2), vec3(0.9), checker) * vec3(IN.tex_coord, 1.0);
+    case VIEW_DEPTH:
+        return vec3(-IN.frag_pos.z / far_plane);
+    case VIEW_WIREFRAME:
+        return vec3(0.2, 1.0, 0.4);
+    default:
+        return vec3(1.0, 0.0, 1.0);
+    }
+}
+
 void main() {
     vec3 normal = normalize(IN.normal);
     vec3 view_direction = normalize(-IN.frag_pos);
 
+    vec4 diffuse = texture(material.diffuse, IN.tex_coord);
+    float alpha = diffuse.a * material.opacity;
+    if (material.alpha_mode == ALPHA_MASK) {
+        if (alpha < material.alpha_cutoff) {
+            discard;
+        }
+        alpha = 1.0;
+    } else if (material.alpha_mode == ALPHA_OPAQUE) {
+        alpha = 1.0;
+    }
+    // The node's color can still fade out any material
+    alpha *= IN.color.a;
+
     MaterialColor mat_color;
-    mat_color.diffuse = texture(material.diffuse, IN.tex_coord).xyz;
+    mat_color.diffuse = diffuse.rgb * IN.color.rgb;
     mat_color.specular = texture(material.specular, IN.tex_coord).xyz;
 
     vec3 result_color = vec3(0.0);
@@ -103,5 +168,8 @@ void main() {
         result_color += calc_point_light(point_lights[i], normal, IN.frag_pos, view_direction, mat_color);
     }
 
-    Color = vec4(result_color, 1.0);
+    Color = vec4(result_color, alpha);
+    if (debug_view != VIEW_LIT) {
+        Color.rgb = debug_color(normal, mat_color);
+    }
 }
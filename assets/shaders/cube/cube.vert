@@ -1,22 +1,54 @@
 #version 330 core
 
+#define MAX_MORPH_TARGETS 8
+
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec2 TexCoord;
 layout (location = 2) in vec3 Normal;
+// Zero for meshes without tangents
+layout (location = 5) in vec4 Tangent;
+// Per instance, locations 6 to 9
+layout (location = 6) in mat4 InstanceModel;
+layout (location = 10) in vec4 InstanceColor;
 
 uniform mat4 proj;
 uniform mat4 view;
+// Used instead of the instance attributes unless drawing instanced
+uniform bool instanced;
 uniform mat4 model;
+uniform vec4 color;
+
+// Position, normal and tangent displacements of every vertex of every target
+uniform samplerBuffer morph_targets;
+uniform int morph_target_count;
+uniform int morph_vertex_count;
+uniform float morph_weights[MAX_MORPH_TARGETS];
 
 out VS_OUTPUT {
     vec2 tex_coord;
     vec3 normal;
+    // With the sign of the bitangent in w
+    vec4 tangent;
     vec3 frag_pos;
+    vec4 color;
 } OUT;
 
 void main() {
-    gl_Position = proj * view * model * vec4(Position, 1.0);
+    vec3 position = Position;
+    vec3 normal = Normal;
+    vec3 tangent = Tangent.xyz;
+    for (int i = 0; i < morph_target_count; i++) {
+        int texel = (i * morph_vertex_count + gl_VertexID) * 3;
+        position += morph_weights[i] * texelFetch(morph_targets, texel).xyz;
+        normal += morph_weights[i] * texelFetch(morph_targets, texel + 1).xyz;
+        tangent += morph_weights[i] * texelFetch(morph_targets, texel + 2).xyz;
+    }
+
+    mat4 model_view = view * (instanced ? InstanceModel : model);
+    gl_Position = proj * model_view * vec4(position, 1.0);
     OUT.tex_coord = TexCoord;
-    OUT.normal = mat3(transpose(inverse(view * model))) * Normal;
-    OUT.frag_pos = (view * model * vec4(Position, 1.0)).xyz;
+    OUT.normal = mat3(transpose(inverse(model_view))) * normal;
+    OUT.tangent = vec4(mat3(model_view) * tangent, Tangent.w);
+    OUT.frag_pos = (model_view * vec4(position, 1.0)).xyz;
+    OUT.color = instanced ? InstanceColor : color;
 }
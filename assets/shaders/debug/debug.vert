@@ -0,0 +1,14 @@
+#version 330 core
+
+layout (location = 0) in vec3 Position;
+layout (location = 1) in vec4 Color;
+
+uniform mat4 proj;
+uniform mat4 view;
+
+out vec4 color;
+
+void main() {
+    gl_Position = proj * view * vec4(Position, 1.0);
+    color = Color;
+}
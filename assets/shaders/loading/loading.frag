@@ -0,0 +1,30 @@
+#version 330 core
+
+in vec2 tex_coord;
+
+out vec4 Color;
+
+uniform float progress;
+uniform vec3 bar_color;
+uniform vec3 background_color;
+uniform sampler2DArray tiles;
+uniform int tile_count;
+uniform sampler3D noise;
+uniform float time;
+
+// Tiles along the bar
+const float TILES_ACROSS = 32.0;
+
+void main() {
+    vec3 color;
+    if (tex_coord.x <= progress) {
+        float column = floor(tex_coord.x * TILES_ACROSS);
+        vec2 uv = vec2(fract(tex_coord.x * TILES_ACROSS), tex_coord.y);
+        float layer = mod(column, float(tile_count));
+        color = bar_color * texture(tiles, vec3(uv, layer)).rgb;
+    } else {
+        vec3 position = vec3(tex_coord * vec2(TILES_ACROSS, 1.0) / 8.0, time * 0.2);
+        color = background_color * (0.75 + 0.5 * texture(noise, position).r);
+    }
+    Color = vec4(color, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 330 core
+
+layout (location = 0) in vec2 Position;
+
+out vec2 tex_coord;
+
+void main() {
+    // Unit quad stretched into a thin bar in the middle of the screen
+    vec2 bar_min = vec2(-0.5, -0.02);
+    vec2 bar_max = vec2(0.5, 0.02);
+    gl_Position = vec4(mix(bar_min, bar_max, Position), 0.0, 1.0);
+    tex_coord = Position;
+}
@@ -0,0 +1,60 @@
+#version 330 core
+
+#define MAX_JOINTS 64
+#define MAX_MORPH_TARGETS 8
+
+layout (location = 0) in vec3 Position;
+layout (location = 1) in vec2 TexCoord;
+layout (location = 2) in vec3 Normal;
+layout (location = 3) in vec4 Joints;
+layout (location = 4) in vec4 Weights;
+// Zero for meshes without tangents
+layout (location = 5) in vec4 Tangent;
+
+uniform mat4 proj;
+uniform mat4 view;
+uniform mat4 model;
+uniform vec4 color;
+uniform mat4 joint_matrices[MAX_JOINTS];
+
+// Position, normal and tangent displacements of every vertex of every target
+uniform samplerBuffer morph_targets;
+uniform int morph_target_count;
+uniform int morph_vertex_count;
+uniform float morph_weights[MAX_MORPH_TARGETS];
+
+out VS_OUTPUT {
+    vec2 tex_coord;
+    vec3 normal;
+    // With the sign of the bitangent in w
+    vec4 tangent;
+    vec3 frag_pos;
+    vec4 color;
+} OUT;
+
+void main() {
+    // Morph targets are applied in bind pose, before skinning
+    vec3 position = Position;
+    vec3 normal = Normal;
+    vec3 tangent = Tangent.xyz;
+    for (int i = 0; i < morph_target_count; i++) {
+        int texel = (i * morph_vertex_count + gl_VertexID) * 3;
+        position += morph_weights[i] * texelFetch(morph_targets, texel).xyz;
+        normal += morph_weights[i] * texelFetch(morph_targets, texel + 1).xyz;
+        tangent += morph_weights[i] * texelFetch(morph_targets, texel + 2).xyz;
+    }
+
+    mat4 skin =
+        Weights.x * joint_matrices[int(Joints.x)] +
+        Weights.y * joint_matrices[int(Joints.y)] +
+        Weights.z * joint_matrices[int(Joints.z)] +
+        Weights.w * joint_matrices[int(Joints.w)];
+    mat4 model_view = view * model * skin;
+
+    gl_Position = proj * model_view * vec4(position, 1.0);
+    OUT.tex_coord = TexCoord;
+    OUT.normal = mat3(transpose(inverse(model_view))) * normal;
+    OUT.tangent = vec4(mat3(model_view) * tangent, Tangent.w);
+    OUT.frag_pos = (model_view * vec4(position, 1.0)).xyz;
+    OUT.color = color;
+}
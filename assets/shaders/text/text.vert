@@ -0,0 +1,18 @@
+#version 330 core
+
+// In pixels from the top left corner of the screen
+layout (location = 0) in vec2 Position;
+layout (location = 1) in vec2 TexCoord;
+layout (location = 2) in vec4 Color;
+
+uniform vec2 screen_size;
+
+out vec2 tex_coord;
+out vec4 color;
+
+void main() {
+    vec2 ndc = Position / screen_size * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    tex_coord = TexCoord;
+    color = Color;
+}
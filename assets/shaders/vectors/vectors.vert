@@ -0,0 +1,27 @@
+#version 330 core
+
+layout (location = 0) in vec3 Position;
+layout (location = 2) in vec3 Normal;
+// Zero for meshes without tangents
+layout (location = 5) in vec4 Tangent;
+// Per instance, locations 6 to 9
+layout (location = 6) in mat4 InstanceModel;
+
+uniform mat4 view;
+// Used instead of the instance attribute unless drawing instanced
+uniform bool instanced;
+uniform mat4 model;
+
+out VS_OUTPUT {
+    vec3 normal;
+    vec3 tangent;
+} OUT;
+
+void main() {
+    mat4 model_view = view * (instanced ? InstanceModel : model);
+    // View space, projected once the lines are built
+    gl_Position = model_view * vec4(Position, 1.0);
+    OUT.normal = normalize(mat3(transpose(inverse(model_view))) * Normal);
+    vec3 tangent = mat3(model_view) * Tangent.xyz;
+    OUT.tangent = length(tangent) > 0.0 ? normalize(tangent) : vec3(0.0);
+}
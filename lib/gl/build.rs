@@ -28,7 +28,12 @@ fn main() {
         (4, 1),
         Profile::Core,
         Fallbacks::All,
-        ["GL_NV_command_list"],
+        [
+            "GL_NV_command_list",
+            "GL_EXT_texture_compression_s3tc",
+            "GL_ARB_texture_compression_bptc",
+            "GL_ARB_buffer_storage",
+        ],
     )
     .write_bindings(GlobalGenerator, &mut file)
     .unwrap();
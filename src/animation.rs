@@ -0,0 +1,699 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use glm::{Mat4, Quat, Vec3};
+
+use crate::scene::{NodeId, Scene};
+
+/// Joints a skin can have. Has to match the skinned shader
+pub const MAX_JOINTS: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Every keyframe has an in-tangent, a value and an out-tangent
+    CubicSpline,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+/// Keyframes for one property of one node.
+/// Values are stored as [x, y, z, w], with w unused for translation and scale
+#[derive(Debug, Clone)]
+pub struct Channel<N> {
+    pub target: N,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<[f32; 4]>,
+}
+
+/// Keyframes for the morph target weights of one node
+#[derive(Debug, Clone)]
+pub struct WeightChannel<N> {
+    pub target: N,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    /// Weights of all morph targets, one keyframe after another
+    pub values: Vec<f32>,
+}
+
+/// An animation clip. Targets are glTF node indices (usize) right after loading,
+/// and scene nodes (NodeId) once the model is added to a scene
+#[derive(Debug, Clone)]
+pub struct Clip<N> {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel<N>>,
+    pub weight_channels: Vec<WeightChannel<N>>,
+}
+
+/// Joints of a skinned mesh with their inverse bind matrices
+#[derive(Debug, Clone)]
+pub struct Skin<N> {
+    pub joints: Vec<N>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+/// A sampled value of a channel
+pub enum Sample {
+    Translation(Vec3),
+    Rotation(Quat),
+    Scale(Vec3),
+}
+
+/// Reads all skins of a glTF document. Joints are glTF node indices
+pub fn load_skins(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<Skin<usize>> {
+    document
+        .skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|node| node.index()).collect();
+            let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices.map(|m| glm::make_mat4(&flatten(&m))).collect(),
+                None => vec![glm::identity(); joints.len()],
+            };
+            Skin {
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect()
+}
+
+fn flatten(m: &[[f32; 4]; 4]) -> [f32; 16] {
+    let mut out = [0.0; 16];
+    for (i, column) in m.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(column);
+    }
+    out
+}
+
+/// Reads translation/rotation/scale and morph target weight channels
+/// of all animations in a glTF document
+pub fn load_clips(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<Clip<usize>> {
+    use gltf::animation::util::ReadOutputs;
+
+    document
+        .animations()
+        .enumerate()
+        .map(|(i, animation)| {
+            let mut channels = Vec::new();
+            let mut weight_channels = Vec::new();
+            for channel in animation.channels() {
+                let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                let times: Vec<f32> = match reader.read_inputs() {
+                    Some(inputs) => inputs.collect(),
+                    None => continue,
+                };
+                let interpolation = interpolation(channel.sampler().interpolation());
+                // Broken samplers are reported by validation::check_buffers
+                let value_count = output_count(&reader);
+                if sampler_problem(&times, value_count, interpolation).is_some() {
+                    continue;
+                }
+                let (property, values): (Property, Vec<[f32; 4]>) = match reader.read_outputs() {
+                    Some(ReadOutputs::Translations(t)) => (
+                        Property::Translation,
+                        t.map(|v| [v[0], v[1], v[2], 0.0]).collect(),
+                    ),
+                    Some(ReadOutputs::Rotations(r)) => (Property::Rotation, r.into_f32().collect()),
+                    Some(ReadOutputs::Scales(s)) => (
+                        Property::Scale,
+                        s.map(|v| [v[0], v[1], v[2], 0.0]).collect(),
+                    ),
+                    Some(ReadOutputs::MorphTargetWeights(w)) => {
+                        weight_channels.push(WeightChannel {
+                            target: channel.target().node().index(),
+                            interpolation,
+                            times,
+                            values: w.into_f32().collect(),
+                        });
+                        continue;
+                    }
+                    None => continue,
+                };
+                channels.push(Channel {
+                    target: channel.target().node().index(),
+                    property,
+                    interpolation,
+                    times,
+                    values,
+                });
+            }
+            let duration = channels
+                .iter()
+                .map(|c| &c.times)
+                .chain(weight_channels.iter().map(|c| &c.times))
+                .filter_map(|times| times.last().cloned())
+                .fold(0.0, f32::max);
+            Clip {
+                name: animation
+                    .name()
+                    .map(|name| name.to_owned())
+                    .unwrap_or_else(|| format!("animation {}", i)),
+                duration,
+                channels,
+                weight_channels,
+            }
+        })
+        .collect()
+}
+
+pub fn interpolation(interpolation: gltf::animation::Interpolation) -> Interpolation {
+    match interpolation {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    }
+}
+
+/// Number of values of a sampler, whatever their type
+pub fn output_count<'a, 's, F>(reader: &gltf::animation::util::Reader<'a, 's, F>) -> usize
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    use gltf::animation::util::ReadOutputs;
+
+    match reader.read_outputs() {
+        Some(ReadOutputs::Translations(t)) => t.count(),
+        Some(ReadOutputs::Rotations(r)) => r.into_f32().count(),
+        Some(ReadOutputs::Scales(s)) => s.count(),
+        Some(ReadOutputs::MorphTargetWeights(w)) => w.into_f32().count(),
+        None => 0,
+    }
+}
+
+/// Why keyframes can't be sampled, if they can't. Times have to be finite and
+/// increasing, and each keyframe needs the same number of values
+pub fn sampler_problem(
+    times: &[f32],
+    value_count: usize,
+    interpolation: Interpolation,
+) -> Option<String> {
+    if times.is_empty() {
+        return Some("has no keyframes".to_owned());
+    }
+    if times.iter().any(|t| !t.is_finite()) {
+        return Some("has keyframe times that aren't numbers".to_owned());
+    }
+    if times.windows(2).any(|pair| pair[1] <= pair[0]) {
+        return Some("has keyframe times that aren't increasing".to_owned());
+    }
+    let values_per_keyframe = match interpolation {
+        Interpolation::CubicSpline => 3,
+        _ => 1,
+    };
+    if value_count == 0 || value_count % (times.len() * values_per_keyframe) != 0 {
+        return Some(format!(
+            "has {} values for {} keyframes",
+            value_count,
+            times.len()
+        ));
+    }
+    None
+}
+
+impl<N> Channel<N> {
+    /// Value of the channel at the given time, clamped to the first and last keyframes
+    pub fn sample(&self, time: f32) -> Sample {
+        let value = self.sample_raw(time);
+        match self.property {
+            Property::Translation => Sample::Translation(glm::vec3(value[0], value[1], value[2])),
+            Property::Scale => Sample::Scale(glm::vec3(value[0], value[1], value[2])),
+            Property::Rotation => Sample::Rotation(glm::quat_normalize(&glm::quat(
+                value[0], value[1], value[2], value[3],
+            ))),
+        }
+    }
+
+    /// Keyframe value, skipping the tangents for cubic splines
+    fn keyframe(&self, i: usize) -> [f32; 4] {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[i * 3 + 1],
+            _ => self.values[i],
+        }
+    }
+
+    fn sample_raw(&self, time: f32) -> [f32; 4] {
+        let (i, t, dt) = match find_keyframe(&self.times, time) {
+            Keyframe::Clamped(i) => return self.keyframe(i),
+            Keyframe::Between(i, t, dt) => (i, t, dt),
+        };
+
+        match self.interpolation {
+            Interpolation::Step => self.keyframe(i),
+            Interpolation::Linear => {
+                let (a, b) = (self.keyframe(i), self.keyframe(i + 1));
+                if self.property == Property::Rotation {
+                    let q = glm::quat_slerp(
+                        &glm::quat(a[0], a[1], a[2], a[3]),
+                        &glm::quat(b[0], b[1], b[2], b[3]),
+                        t,
+                    );
+                    [q.coords.x, q.coords.y, q.coords.z, q.coords.w]
+                } else {
+                    lerp(a, b, t)
+                }
+            }
+            Interpolation::CubicSpline => {
+                // Hermite spline between value i with its out-tangent
+                // and value i + 1 with its in-tangent
+                let p0 = self.values[i * 3 + 1];
+                let m0 = self.values[i * 3 + 2];
+                let m1 = self.values[(i + 1) * 3];
+                let p1 = self.values[(i + 1) * 3 + 1];
+                let mut out = [0.0; 4];
+                for k in 0..4 {
+                    out[k] = hermite(p0[k], m0[k], p1[k], m1[k], t, dt);
+                }
+                out
+            }
+        }
+    }
+}
+
+impl<N> WeightChannel<N> {
+    /// Number of morph targets
+    fn count(&self) -> usize {
+        let values_per_keyframe = match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        self.values.len() / (self.times.len() * values_per_keyframe).max(1)
+    }
+
+    /// Weights at the given time, clamped to the first and last keyframes
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let n = self.count();
+        // Values of the keyframe, tangents included for cubic splines
+        let (values, interpolation) = (&self.values, self.interpolation);
+        let keyframe = move |i: usize, part: usize| match interpolation {
+            Interpolation::CubicSpline => &values[(i * 3 + part) * n..(i * 3 + part + 1) * n],
+            _ => &values[i * n..(i + 1) * n],
+        };
+        let (i, t, dt) = match find_keyframe(&self.times, time) {
+            Keyframe::Clamped(i) => return keyframe(i, 1).to_vec(),
+            Keyframe::Between(i, t, dt) => (i, t, dt),
+        };
+
+        match self.interpolation {
+            Interpolation::Step => keyframe(i, 1).to_vec(),
+            Interpolation::Linear => keyframe(i, 1)
+                .iter()
+                .zip(keyframe(i + 1, 1))
+                .map(|(a, b)| a + (b - a) * t)
+                .collect(),
+            Interpolation::CubicSpline => (0..n)
+                .map(|k| {
+                    let p0 = keyframe(i, 1)[k];
+                    let m0 = keyframe(i, 2)[k];
+                    let m1 = keyframe(i + 1, 0)[k];
+                    let p1 = keyframe(i + 1, 1)[k];
+                    hermite(p0, m0, p1, m1, t, dt)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Where a time falls between keyframes
+enum Keyframe {
+    /// Before the first or after the last keyframe
+    Clamped(usize),
+    /// Between keyframe i and i + 1, with normalized position and keyframe distance
+    Between(usize, f32, f32),
+}
+
+/// Times must have passed sampler_problem
+fn find_keyframe(times: &[f32], time: f32) -> Keyframe {
+    let last = times.len() - 1;
+    // Written so that a NaN time clamps to the first keyframe
+    if !(time > times[0]) {
+        return Keyframe::Clamped(0);
+    }
+    if time >= times[last] {
+        return Keyframe::Clamped(last);
+    }
+
+    // Keyframe right before the time
+    let i = match times.binary_search_by(|t| t.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    };
+    let dt = times[i + 1] - times[i];
+    Keyframe::Between(i, (time - times[i]) / dt, dt)
+}
+
+/// Cubic Hermite spline between p0 with out-tangent m0 and p1 with in-tangent m1
+fn hermite(p0: f32, m0: f32, p1: f32, m1: f32, t: f32, dt: f32) -> f32 {
+    let (t2, t3) = (t * t, t * t * t);
+    (2.0 * t3 - 3.0 * t2 + 1.0) * p0
+        + (t3 - 2.0 * t2 + t) * dt * m0
+        + (-2.0 * t3 + 3.0 * t2) * p1
+        + (t3 - t2) * dt * m1
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for k in 0..4 {
+        out[k] = a[k] + (b[k] - a[k]) * t;
+    }
+    out
+}
+
+impl Clip<usize> {
+    /// Points channels to scene nodes. Channels of nodes that aren't in the scene are dropped
+    pub fn retarget(&self, nodes: &[Option<NodeId>]) -> Clip<NodeId> {
+        let channels = self
+            .channels
+            .iter()
+            .filter_map(|channel| {
+                let target = nodes.get(channel.target).cloned().flatten()?;
+                Some(Channel {
+                    target,
+                    property: channel.property,
+                    interpolation: channel.interpolation,
+                    times: channel.times.clone(),
+                    values: channel.values.clone(),
+                })
+            })
+            .collect();
+        let weight_channels = self
+            .weight_channels
+            .iter()
+            .filter_map(|channel| {
+                let target = nodes.get(channel.target).cloned().flatten()?;
+                Some(WeightChannel {
+                    target,
+                    interpolation: channel.interpolation,
+                    times: channel.times.clone(),
+                    values: channel.values.clone(),
+                })
+            })
+            .collect();
+        Clip {
+            name: self.name.clone(),
+            duration: self.duration,
+            channels,
+            weight_channels,
+        }
+    }
+}
+
+impl Clip<NodeId> {
+    /// Poses the scene nodes as they are at the given time
+    pub fn apply(&self, time: f32, scene: &mut Scene) {
+        for channel in self.channels.iter() {
+            match channel.sample(time) {
+                Sample::Translation(t) => scene.set_translation(channel.target, t),
+                Sample::Rotation(r) => scene.set_rotation(channel.target, r),
+                Sample::Scale(s) => scene.set_scale(channel.target, s),
+            }
+        }
+        for channel in self.weight_channels.iter() {
+            scene.node_mut(channel.target).morph_weights = channel.sample(time);
+        }
+    }
+
+    /// Nodes moved or morphed by the clip
+    pub fn targets(&self) -> impl Iterator<Item = NodeId> + '_ {
+        let channels = self.channels.iter().map(|channel| channel.target);
+        channels.chain(self.weight_channels.iter().map(|channel| channel.target))
+    }
+
+    /// Samples the clip on top of the given pose.
+    /// Properties the clip doesn't animate keep their values
+    pub fn sample_pose(&self, time: f32, base: &Pose) -> Pose {
+        let mut pose = base.clone();
+        for channel in self.channels.iter() {
+            let transform = pose
+                .transforms
+                .entry(channel.target)
+                .or_insert_with(Transform::identity);
+            match channel.sample(time) {
+                Sample::Translation(t) => transform.translation = t,
+                Sample::Rotation(r) => transform.rotation = r,
+                Sample::Scale(s) => transform.scale = s,
+            }
+        }
+        for channel in self.weight_channels.iter() {
+            pose.weights.insert(channel.target, channel.sample(time));
+        }
+        pose
+    }
+}
+
+/// Local transform of a node
+#[derive(Debug, Clone)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            translation: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Interpolates towards the other transform, taking the shortest path for rotations
+    pub fn blend(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: glm::lerp(&self.translation, &other.translation, t),
+            rotation: nlerp(&self.rotation, &other.rotation, t),
+            scale: glm::lerp(&self.scale, &other.scale, t),
+        }
+    }
+}
+
+/// Normalized lerp between rotations along the shortest path
+fn nlerp(a: &Quat, b: &Quat, t: f32) -> Quat {
+    let b = if glm::quat_dot(a, b) < 0.0 { -*b } else { *b };
+    glm::quat_normalize(&glm::quat_lerp(a, &b, t))
+}
+
+/// Local transforms and morph target weights of a set of nodes
+#[derive(Debug, Clone, Default)]
+pub struct Pose {
+    pub transforms: HashMap<NodeId, Transform>,
+    pub weights: HashMap<NodeId, Vec<f32>>,
+}
+
+impl Pose {
+    /// Current transforms of the given scene nodes
+    pub fn from_scene(scene: &Scene, nodes: impl Iterator<Item = NodeId>) -> Self {
+        let mut pose = Pose::default();
+        for id in nodes {
+            let node = scene.node(id);
+            let transform = Transform {
+                translation: *node.translation(),
+                rotation: *node.rotation(),
+                scale: *node.scale(),
+            };
+            pose.transforms.insert(id, transform);
+            if !node.morph_weights.is_empty() {
+                pose.weights.insert(id, node.morph_weights.clone());
+            }
+        }
+        pose
+    }
+
+    /// Cross-fades towards the other pose. Nodes missing from one of the poses
+    /// are taken as is from the other
+    pub fn blend(&self, other: &Pose, t: f32) -> Pose {
+        let mut pose = other.clone();
+        for (id, transform) in self.transforms.iter() {
+            let blended = match other.transforms.get(id) {
+                Some(other) => transform.blend(other, t),
+                None => transform.clone(),
+            };
+            pose.transforms.insert(*id, blended);
+        }
+        for (id, weights) in self.weights.iter() {
+            let blended = match other.weights.get(id) {
+                Some(other) => weights
+                    .iter()
+                    .zip(other.iter())
+                    .map(|(a, b)| a + (b - a) * t)
+                    .collect(),
+                None => weights.clone(),
+            };
+            pose.weights.insert(*id, blended);
+        }
+        pose
+    }
+
+    /// Adds the difference between the additive pose and its reference pose,
+    /// scaled by the weight
+    pub fn add(&mut self, additive: &Pose, reference: &Pose, weight: f32) {
+        for (id, delta) in additive.transforms.iter() {
+            let (reference, transform) =
+                match (reference.transforms.get(id), self.transforms.get_mut(id)) {
+                    (Some(reference), Some(transform)) => (reference, transform),
+                    _ => continue,
+                };
+            let rotation = glm::quat_inverse(&reference.rotation) * delta.rotation;
+            let rotation = nlerp(&glm::quat_identity(), &rotation, weight);
+            transform.translation += (delta.translation - reference.translation) * weight;
+            transform.rotation = glm::quat_normalize(&(transform.rotation * rotation));
+            let scale = delta.scale.component_div(&reference.scale);
+            transform.scale = transform.scale.component_mul(&glm::lerp(
+                &glm::vec3(1.0, 1.0, 1.0),
+                &scale,
+                weight,
+            ));
+        }
+        for (id, delta) in additive.weights.iter() {
+            let (reference, weights) = match (reference.weights.get(id), self.weights.get_mut(id)) {
+                (Some(reference), Some(weights)) => (reference, weights),
+                _ => continue,
+            };
+            for (w, (d, r)) in weights.iter_mut().zip(delta.iter().zip(reference.iter())) {
+                *w += (d - r) * weight;
+            }
+        }
+    }
+
+    /// Sets local transforms of the scene nodes
+    pub fn apply(&self, scene: &mut Scene) {
+        for (&id, transform) in self.transforms.iter() {
+            scene.set_translation(id, transform.translation);
+            scene.set_rotation(id, transform.rotation);
+            scene.set_scale(id, transform.scale);
+        }
+        for (&id, weights) in self.weights.iter() {
+            scene.node_mut(id).morph_weights = weights.clone();
+        }
+    }
+}
+
+impl Skin<usize> {
+    pub fn retarget(&self, nodes: &[Option<NodeId>]) -> Option<Skin<NodeId>> {
+        let joints = self
+            .joints
+            .iter()
+            .map(|&joint| nodes.get(joint).cloned().flatten())
+            .collect::<Option<Vec<NodeId>>>()?;
+        Some(Skin {
+            joints,
+            inverse_bind_matrices: self.inverse_bind_matrices.clone(),
+        })
+    }
+}
+
+impl Skin<NodeId> {
+    /// Matrices that move vertices from bind pose into the current pose,
+    /// relative to the node the skinned mesh is attached to.
+    /// The scene's world matrices must be up to date
+    pub fn joint_matrices(&self, scene: &Scene, mesh_world: &Mat4) -> Vec<Mat4> {
+        let mesh_world_inverse = glm::inverse(mesh_world);
+        self.joints
+            .iter()
+            .zip(self.inverse_bind_matrices.iter())
+            .map(|(&joint, inverse_bind)| {
+                mesh_world_inverse * scene.node(joint).world_matrix() * inverse_bind
+            })
+            .collect()
+    }
+}
+
+/// Plays one of several clips
+pub struct AnimationPlayer {
+    pub clips: Vec<Clip<NodeId>>,
+    pub current: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<Clip<NodeId>>) -> Self {
+        AnimationPlayer {
+            clips,
+            current: 0,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    /// Switches to the next clip and starts it from the beginning
+    pub fn next_clip(&mut self) {
+        if !self.clips.is_empty() {
+            self.current = (self.current + 1) % self.clips.len();
+            self.time = 0.0;
+        }
+    }
+
+    /// Advances the time and poses the scene accordingly
+    pub fn update(&mut self, delta_time: f32, scene: &mut Scene) {
+        let clip = match self.clips.get(self.current) {
+            Some(clip) => clip,
+            None => return,
+        };
+        if self.playing {
+            self.time += delta_time * self.speed;
+            if self.looping && clip.duration > 0.0 {
+                self.time = self.time.rem_euclid(clip.duration);
+            } else {
+                self.time = self.time.max(0.0).min(clip.duration);
+            }
+        }
+        clip.apply(self.time, scene);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_samplers_are_caught() {
+        let linear = Interpolation::Linear;
+        assert!(sampler_problem(&[], 0, linear).is_some());
+        assert!(sampler_problem(&[0.0, std::f32::NAN], 2, linear).is_some());
+        assert!(sampler_problem(&[0.0, 1.0, 1.0], 3, linear).is_some());
+        assert!(sampler_problem(&[0.0, 1.0], 3, linear).is_some());
+        assert!(sampler_problem(&[0.0, 1.0], 2, Interpolation::CubicSpline).is_some());
+        assert!(sampler_problem(&[0.0, 1.0], 6, Interpolation::CubicSpline).is_none());
+        // Morph target weights have several values per keyframe
+        assert!(sampler_problem(&[0.0, 1.0], 4, linear).is_none());
+    }
+
+    #[test]
+    fn times_outside_the_keyframes_are_clamped() {
+        let times = [0.0, 1.0, 2.0];
+        match find_keyframe(&times, std::f32::NAN) {
+            Keyframe::Clamped(0) => {}
+            _ => panic!("NaN should clamp to the first keyframe"),
+        }
+        match find_keyframe(&times, 5.0) {
+            Keyframe::Clamped(2) => {}
+            _ => panic!("late times should clamp to the last keyframe"),
+        }
+        match find_keyframe(&times, 1.5) {
+            Keyframe::Between(1, t, dt) => {
+                assert_eq!(t, 0.5);
+                assert_eq!(dt, 1.0);
+            }
+            _ => panic!("expected a time between keyframes"),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Animation state machine: states play clips, transitions cross-fade between them
+//! when their conditions are met, and additive layers go on top. Described in scene files:
+//!
+//! ```ron
+//! animator: Some(Animator(
+//!     initial: "idle",
+//!     states: [
+//!         State(name: "idle", clip: "Idle"),
+//!         State(name: "walk", clip: "Walk", speed: 1.2),
+//!         State(name: "attack", clip: "Attack", looping: false),
+//!     ],
+//!     transitions: [
+//!         Transition(from: "idle", to: "walk", condition: Greater("moving", 0.5), duration: 0.3),
+//!         Transition(from: "walk", to: "idle", condition: Less("moving", 0.5), duration: 0.3),
+//!         Transition(from: "*", to: "attack", condition: Trigger("attack"), duration: 0.1),
+//!         Transition(from: "attack", to: "idle", condition: Finished, duration: 0.2),
+//!     ],
+//!     layers: [
+//!         Layer(clip: "Breathe", weight: 0.5),
+//!     ],
+//! )),
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+use crate::animation::{Clip, Pose};
+use crate::scene::{NodeId, Scene};
+
+#[derive(Debug, Fail)]
+pub enum AnimatorError {
+    #[fail(display = "Unknown clip '{}'", name)]
+    UnknownClip { name: String },
+    #[fail(display = "Unknown state '{}'", name)]
+    UnknownState { name: String },
+}
+
+pub type Result<T> = std::result::Result<T, AnimatorError>;
+
+/// Source of a transition that can leave any state
+const ANY_STATE: &str = "*";
+
+#[derive(Deserialize)]
+#[serde(rename = "Animator")]
+pub struct AnimatorDescription {
+    pub initial: String,
+    pub states: Vec<StateDescription>,
+    #[serde(default)]
+    pub transitions: Vec<TransitionDescription>,
+    #[serde(default)]
+    pub layers: Vec<LayerDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "State")]
+pub struct StateDescription {
+    pub name: String,
+    pub clip: String,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    #[serde(default = "default_looping")]
+    pub looping: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Transition")]
+pub struct TransitionDescription {
+    /// State name or "*" for any state
+    pub from: String,
+    pub to: String,
+    pub condition: Condition,
+    /// Cross-fade time in seconds
+    #[serde(default)]
+    pub duration: f32,
+}
+
+/// Additive clip played on top of the states
+#[derive(Deserialize)]
+#[serde(rename = "Layer")]
+pub struct LayerDescription {
+    pub clip: String,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum Condition {
+    /// Parameter is above the value
+    Greater(String, f32),
+    /// Parameter is below the value
+    Less(String, f32),
+    /// Trigger has been set. Triggers are consumed by the transition they fire
+    Trigger(String),
+    /// Clip of a non-looping state has reached its end
+    Finished,
+    Always,
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_looping() -> bool {
+    true
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+struct State {
+    clip: usize,
+    speed: f32,
+    looping: bool,
+}
+
+struct Transition {
+    from: Option<usize>,
+    to: usize,
+    condition: Condition,
+    duration: f32,
+}
+
+struct Layer {
+    clip: usize,
+    weight: f32,
+    time: f32,
+    /// First frame of the clip, which the layer adds the difference to
+    reference: Pose,
+}
+
+/// A state being played
+#[derive(Clone)]
+struct Playback {
+    state: usize,
+    time: f32,
+}
+
+/// What a cross-fade blends away from
+enum FadeSource {
+    /// A state that keeps playing while it fades out
+    Playback(Playback),
+    /// Blended pose of a cross-fade that another transition interrupted, held still
+    Pose(Pose),
+}
+
+struct Fade {
+    from: FadeSource,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// Runtime of an animator description for one model instance
+pub struct Animator {
+    clips: Vec<Clip<NodeId>>,
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+    layers: Vec<Layer>,
+    parameters: HashMap<String, f32>,
+    triggers: HashSet<String>,
+    /// Transforms of animated nodes before any clip touched them
+    rest: Pose,
+    current: Playback,
+    fade: Option<Fade>,
+}
+
+impl Animator {
+    /// Resolves clip and state names. The scene must hold the model in its rest pose
+    pub fn new(
+        description: &AnimatorDescription,
+        clips: Vec<Clip<NodeId>>,
+        scene: &Scene,
+    ) -> Result<Self> {
+        let clip_index = |name: &str| {
+            clips
+                .iter()
+                .position(|clip| clip.name == name)
+                .ok_or_else(|| AnimatorError::UnknownClip {
+                    name: name.to_owned(),
+                })
+        };
+        let state_index = |name: &str| {
+            description
+                .states
+                .iter()
+                .position(|state| state.name == name)
+                .ok_or_else(|| AnimatorError::UnknownState {
+                    name: name.to_owned(),
+                })
+        };
+
+        let states = description
+            .states
+            .iter()
+            .map(|state| {
+                Ok(State {
+                    clip: clip_index(&state.clip)?,
+                    speed: state.speed,
+                    looping: state.looping,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let transitions = description
+            .transitions
+            .iter()
+            .map(|transition| {
+                let from = if transition.from == ANY_STATE {
+                    None
+                } else {
+                    Some(state_index(&transition.from)?)
+                };
+                Ok(Transition {
+                    from,
+                    to: state_index(&transition.to)?,
+                    condition: transition.condition.clone(),
+                    duration: transition.duration,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let rest = Pose::from_scene(scene, clips.iter().flat_map(|clip| clip.targets()));
+        let layers = description
+            .layers
+            .iter()
+            .map(|layer| {
+                let clip = clip_index(&layer.clip)?;
+                Ok(Layer {
+                    clip,
+                    weight: layer.weight,
+                    time: 0.0,
+                    reference: clips[clip].sample_pose(0.0, &rest),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Animator {
+            current: Playback {
+                state: state_index(&description.initial)?,
+                time: 0.0,
+            },
+            clips,
+            states,
+            transitions,
+            layers,
+            parameters: HashMap::new(),
+            triggers: HashSet::new(),
+            rest,
+            fade: None,
+        })
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_owned(), value);
+    }
+
+    /// Sets a trigger until a transition uses it
+    pub fn trigger(&mut self, name: &str) {
+        self.triggers.insert(name.to_owned());
+    }
+
+    /// Advances clips and transitions and poses the scene accordingly
+    pub fn update(&mut self, delta_time: f32, scene: &mut Scene) {
+        self.advance(delta_time);
+        self.check_transitions();
+
+        let mut pose = self.blended_pose();
+        for layer in self.layers.iter() {
+            let additive = self.clips[layer.clip].sample_pose(layer.time, &self.rest);
+            pose.add(&additive, &layer.reference, layer.weight);
+        }
+        pose.apply(scene);
+    }
+
+    fn advance(&mut self, delta_time: f32) {
+        let current = self.current.clone();
+        self.current.time = self.advance_playback(&current, delta_time);
+        if let Some(mut fade) = self.fade.take() {
+            if let FadeSource::Playback(from) = &mut fade.from {
+                from.time = self.advance_playback(from, delta_time);
+            }
+            fade.elapsed += delta_time;
+            if fade.elapsed < fade.duration {
+                self.fade = Some(fade);
+            }
+        }
+        for layer in self.layers.iter_mut() {
+            let duration = self.clips[layer.clip].duration;
+            layer.time += delta_time;
+            if duration > 0.0 {
+                layer.time = layer.time.rem_euclid(duration);
+            }
+        }
+    }
+
+    /// Pose of the current state, cross-faded from the previous one if a fade is running
+    fn blended_pose(&self) -> Pose {
+        let pose = self.sample(&self.current);
+        match &self.fade {
+            Some(fade) => {
+                let t = (fade.elapsed / fade.duration).min(1.0);
+                match &fade.from {
+                    FadeSource::Playback(playback) => self.sample(playback).blend(&pose, t),
+                    FadeSource::Pose(from) => from.blend(&pose, t),
+                }
+            }
+            None => pose,
+        }
+    }
+
+    /// New time of the playback
+    fn advance_playback(&self, playback: &Playback, delta_time: f32) -> f32 {
+        let state = &self.states[playback.state];
+        let duration = self.clips[state.clip].duration;
+        let time = playback.time + delta_time * state.speed;
+        if state.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.max(0.0).min(duration)
+        }
+    }
+
+    /// Starts the first transition out of the current state whose condition holds
+    fn check_transitions(&mut self) {
+        let current = self.current.state;
+        let fired = self.transitions.iter().find(|transition| {
+            transition.to != current
+                && transition.from.map_or(true, |from| from == current)
+                && self.condition_holds(&transition.condition)
+        });
+        let (to, condition, duration) = match fired {
+            Some(t) => (t.to, t.condition.clone(), t.duration),
+            None => return,
+        };
+        if let Condition::Trigger(name) = condition {
+            self.triggers.remove(&name);
+        }
+
+        // Fading from the state alone would make an unfinished cross-fade jump
+        let from = match self.fade {
+            Some(_) => FadeSource::Pose(self.blended_pose()),
+            None => FadeSource::Playback(self.current.clone()),
+        };
+        self.current = Playback {
+            state: to,
+            time: 0.0,
+        };
+        self.fade = if duration > 0.0 {
+            Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration,
+            })
+        } else {
+            None
+        };
+    }
+
+    fn condition_holds(&self, condition: &Condition) -> bool {
+        let parameter = |name: &str| self.parameters.get(name).cloned().unwrap_or(0.0);
+        match condition {
+            Condition::Greater(name, value) => parameter(name) > *value,
+            Condition::Less(name, value) => parameter(name) < *value,
+            Condition::Trigger(name) => self.triggers.contains(name),
+            Condition::Finished => {
+                let state = &self.states[self.current.state];
+                !state.looping && self.current.time >= self.clips[state.clip].duration
+            }
+            Condition::Always => true,
+        }
+    }
+
+    fn sample(&self, playback: &Playback) -> Pose {
+        let clip = &self.clips[self.states[playback.state].clip];
+        clip.sample_pose(playback.time, &self.rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Channel, Interpolation, Property};
+
+    /// A clip that holds the node at x
+    fn hold(name: &str, node: NodeId, x: f32) -> Clip<NodeId> {
+        Clip {
+            name: name.to_owned(),
+            duration: 1.0,
+            channels: vec![Channel {
+                target: node,
+                property: Property::Translation,
+                interpolation: Interpolation::Step,
+                times: vec![0.0],
+                values: vec![[x, 0.0, 0.0, 0.0]],
+            }],
+            weight_channels: Vec::new(),
+        }
+    }
+
+    fn state(name: &str) -> StateDescription {
+        StateDescription {
+            name: name.to_owned(),
+            clip: name.to_owned(),
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    fn on_trigger(to: &str) -> TransitionDescription {
+        TransitionDescription {
+            from: ANY_STATE.to_owned(),
+            to: to.to_owned(),
+            condition: Condition::Trigger(to.to_owned()),
+            duration: 1.0,
+        }
+    }
+
+    #[test]
+    fn interrupted_fades_continue_from_the_blended_pose() {
+        let mut scene = Scene::new();
+        let node = scene.add_node("node", None);
+        let description = AnimatorDescription {
+            initial: "a".to_owned(),
+            states: vec![state("a"), state("b"), state("c")],
+            transitions: vec![on_trigger("b"), on_trigger("c")],
+            layers: Vec::new(),
+        };
+        let clips = vec![
+            hold("a", node, 0.0),
+            hold("b", node, 10.0),
+            hold("c", node, 20.0),
+        ];
+        let mut animator = Animator::new(&description, clips, &scene).unwrap();
+        let x = |scene: &Scene| scene.node(node).translation().x;
+
+        animator.trigger("b");
+        animator.update(0.0, &mut scene);
+        animator.update(0.5, &mut scene);
+        assert!((x(&scene) - 5.0).abs() < 1e-4);
+
+        // Halfway to b, a fade to c starts where the first one was
+        animator.trigger("c");
+        animator.update(0.0, &mut scene);
+        assert!((x(&scene) - 5.0).abs() < 1e-4);
+        animator.update(0.5, &mut scene);
+        assert!((x(&scene) - 12.5).abs() < 1e-4);
+    }
+}
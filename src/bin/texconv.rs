@@ -0,0 +1,130 @@
+// Offline texture converter.
+// Walks a directory (assets/textures by default) and writes a .dds file
+// with a full mip chain next to every .png/.jpg it finds.
+//
+// Usage: texconv [directory] [--uncompressed]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use stb_image::image::{self, LoadResult};
+
+#[macro_use]
+extern crate failure;
+
+#[allow(dead_code)]
+#[path = "../dds.rs"]
+mod dds;
+use dds::{DdsFormat, DdsImage, DdsLevel};
+#[path = "../dds_write.rs"]
+mod dds_write;
+
+fn main() {
+    let mut dir = PathBuf::from("assets/textures");
+    let mut compress = true;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--uncompressed" => compress = false,
+            _ => dir = PathBuf::from(arg),
+        }
+    }
+
+    if let Err(error) = convert_dir(&dir, compress) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+fn convert_dir(dir: &Path, compress: bool) -> Result<(), failure::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            convert_dir(&path, compress)?;
+            continue;
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("png") | Some("jpg") | Some("jpeg") => {
+                let target = path.with_extension("dds");
+                convert_file(&path, &target, compress)?;
+                println!("Converted {:?} to {:?}", path, target);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn convert_file(source: &Path, target: &Path, compress: bool) -> Result<(), failure::Error> {
+    // Same orientation as texture::decode_image, so that both paths look identical in GL
+    unsafe {
+        stb_image::stb_image::bindgen::stbi_set_flip_vertically_on_load(1);
+    }
+    let source_name = source.to_string_lossy();
+    let (rgba, width, height) = match image::load_with_depth(source_name.as_ref(), 4, false) {
+        LoadResult::ImageU8(image) => (image.data, image.width as u32, image.height as u32),
+        LoadResult::ImageF32(_) => bail!("{}: F32 images are not supported", source_name),
+        LoadResult::Error(msg) => bail!("{}: {}", source_name, msg),
+    };
+    let has_alpha = rgba.chunks(4).any(|pixel| pixel[3] < 255);
+
+    let format = match (compress, has_alpha) {
+        (false, _) => DdsFormat::Rgba8,
+        (true, false) => DdsFormat::Bc1,
+        (true, true) => DdsFormat::Bc3,
+    };
+    let levels = build_mip_chain(rgba, width, height)
+        .into_iter()
+        .map(|level| DdsLevel {
+            data: if compress {
+                dds_write::compress(&level.data, level.width, level.height, has_alpha)
+            } else {
+                level.data
+            },
+            ..level
+        })
+        .collect();
+
+    fs::write(target, dds_write::write(&DdsImage { format, levels })?)?;
+    Ok(())
+}
+
+/// Halves the image with a box filter until it's 1x1
+fn build_mip_chain(rgba: Vec<u8>, width: u32, height: u32) -> Vec<DdsLevel> {
+    let mut levels = vec![DdsLevel {
+        width,
+        height,
+        data: rgba,
+    }];
+    loop {
+        let prev = levels.last().unwrap();
+        if prev.width == 1 && prev.height == 1 {
+            break;
+        }
+        let (w, h) = ((prev.width / 2).max(1), (prev.height / 2).max(1));
+        let mut data = vec![0u8; (w * h * 4) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                for c in 0..4 {
+                    let mut sum = 0u32;
+                    for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (x * 2 + dx).min(prev.width - 1);
+                        let sy = (y * 2 + dy).min(prev.height - 1);
+                        sum += prev.data[((sy * prev.width + sx) * 4 + c) as usize] as u32;
+                    }
+                    data[((y * w + x) * 4 + c) as usize] = (sum / 4) as u8;
+                }
+            }
+        }
+        levels.push(DdsLevel {
+            width: w,
+            height: h,
+            data,
+        });
+    }
+    levels
+}
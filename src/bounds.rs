@@ -0,0 +1,205 @@
+//! Bounding volumes
+
+use glm::{Mat4, Vec3};
+
+/// Axis-aligned bounding box. An empty box has min above max
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: glm::vec3(std::f32::MAX, std::f32::MAX, std::f32::MAX),
+            max: glm::vec3(std::f32::MIN, std::f32::MIN, std::f32::MIN),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a [f32; 3]>) -> Self {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.extend(&glm::make_vec3(point));
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extend(&mut self, point: &Vec3) {
+        self.min = glm::min2(&self.min, point);
+        self.max = glm::max2(&self.max, point);
+    }
+
+    pub fn merge(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Half the size along each axis
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) / 2.0
+    }
+
+    /// Box around the transformed box, which is usually bigger than
+    /// the box around the transformed contents
+    pub fn transform(&self, transform: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = transform * glm::vec4(self.center().x, self.center().y, self.center().z, 1.0);
+        let extents = self.extents();
+        // Every axis of the result gets the absolute contribution of every source axis
+        let mut new_extents = glm::vec3(0.0, 0.0, 0.0);
+        for row in 0..3 {
+            for column in 0..3 {
+                new_extents[row] += transform[(row, column)].abs() * extents[column];
+            }
+        }
+        let center = glm::vec3(center.x, center.y, center.z);
+        Aabb {
+            min: center - new_extents,
+            max: center + new_extents,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Ritter's approximation, within a few percent of the smallest sphere
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let points: Vec<Vec3> = points.iter().map(|p| glm::make_vec3(p)).collect();
+        let first = match points.first() {
+            Some(first) => *first,
+            None => {
+                return BoundingSphere {
+                    center: glm::vec3(0.0, 0.0, 0.0),
+                    radius: 0.0,
+                }
+            }
+        };
+        let farthest_from = |from: &Vec3| {
+            *points
+                .iter()
+                .max_by(|a, b| {
+                    glm::distance2(a, from)
+                        .partial_cmp(&glm::distance2(b, from))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap_or(from)
+        };
+        let a = farthest_from(&first);
+        let b = farthest_from(&a);
+        let mut sphere = BoundingSphere {
+            center: (a + b) / 2.0,
+            radius: glm::distance(&a, &b) / 2.0,
+        };
+        for point in points.iter() {
+            sphere.extend(point);
+        }
+        sphere
+    }
+
+    /// Grows the sphere just enough to hold the point
+    pub fn extend(&mut self, point: &Vec3) {
+        let distance = glm::distance(&self.center, point);
+        if distance > self.radius {
+            let radius = (self.radius + distance) / 2.0;
+            self.center += (point - self.center) * ((radius - self.radius) / distance);
+            self.radius = radius;
+        }
+    }
+
+    /// Sphere around the transformed sphere, scaled by the largest axis scale
+    pub fn transform(&self, transform: &Mat4) -> BoundingSphere {
+        let center = transform * glm::vec4(self.center.x, self.center.y, self.center.z, 1.0);
+        let scale = (0..3)
+            .map(|c| glm::vec3(transform[(0, c)], transform[(1, c)], transform[(2, c)]).norm())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: glm::vec3(center.x, center.y, center.z),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINTS: [[f32; 3]; 4] = [
+        [-1.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 2.0, 0.0],
+        [0.0, -2.0, 0.5],
+    ];
+
+    #[test]
+    fn aabb_of_points() {
+        let aabb = Aabb::from_points(POINTS.iter());
+        assert_eq!(aabb.min, glm::vec3(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, glm::vec3(1.0, 2.0, 0.5));
+        assert_eq!(aabb.center(), glm::vec3(0.0, 0.0, 0.25));
+        assert_eq!(aabb.extents(), glm::vec3(1.0, 2.0, 0.25));
+    }
+
+    #[test]
+    fn aabb_of_nothing_is_empty() {
+        let empty = Aabb::from_points(std::iter::empty());
+        assert!(empty.is_empty());
+        assert!(empty
+            .transform(&glm::scaling(&glm::vec3(2.0, 2.0, 2.0)))
+            .is_empty());
+
+        let aabb = Aabb::from_points(POINTS.iter());
+        assert_eq!(empty.merge(&aabb), aabb);
+        assert_eq!(aabb.merge(&empty), aabb);
+    }
+
+    #[test]
+    fn merged_aabb_holds_both() {
+        let a = Aabb::from_points([[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]].iter());
+        let b = Aabb::from_points([[-1.0, 0.5, 2.0]].iter());
+        let merged = a.merge(&b);
+        assert_eq!(merged.min, glm::vec3(-1.0, 0.0, 0.0));
+        assert_eq!(merged.max, glm::vec3(1.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn sphere_holds_every_point_and_is_close_to_the_smallest() {
+        let sphere = BoundingSphere::from_points(&POINTS);
+        for point in POINTS.iter() {
+            assert!(glm::distance(&sphere.center, &glm::make_vec3(point)) <= sphere.radius + 1e-5);
+        }
+        // The smallest sphere has a radius of about 2.02
+        assert!(sphere.radius < 2.02 * 1.05);
+    }
+
+    #[test]
+    fn sphere_of_nothing_has_no_radius() {
+        let sphere = BoundingSphere::from_points(&[]);
+        assert_eq!(sphere.radius, 0.0);
+        assert_eq!(sphere.center, glm::vec3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sphere_of_one_point_is_that_point() {
+        let sphere = BoundingSphere::from_points(&[[1.0, 2.0, 3.0]]);
+        assert_eq!(sphere.center, glm::vec3(1.0, 2.0, 3.0));
+        assert_eq!(sphere.radius, 0.0);
+    }
+}
@@ -1,10 +1,36 @@
-#![allow(dead_code)]
-
 use gl::types::*;
 
+use crate::extensions;
+use crate::texture::Texture;
+
+/// How often the contents of a buffer change, a hint for where the driver keeps it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+    /// Uploaded once and drawn many times
+    Static,
+    /// Changed now and then and drawn many times
+    Dynamic,
+    /// Changed about as often as it's drawn
+    Stream,
+}
+
+impl BufferUsage {
+    fn to_gl(self) -> GLenum {
+        match self {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
+        }
+    }
+}
+
+/// Methods that change the contents expect the buffer to be bound
 pub struct VertexBuffer {
     id: GLuint,
     num_vertices: usize,
+    /// Floats the storage has room for
+    capacity: usize,
+    usage: BufferUsage,
 }
 
 impl VertexBuffer {
@@ -16,6 +42,8 @@ impl VertexBuffer {
         VertexBuffer {
             id,
             num_vertices: 0,
+            capacity: 0,
+            usage: BufferUsage::Static,
         }
     }
 
@@ -32,17 +60,87 @@ impl VertexBuffer {
     }
 
     pub fn set_static_data(&mut self, vertex_data: &Vec<f32>, stride: usize) {
+        self.set_data(vertex_data, stride, BufferUsage::Static);
+    }
+
+    /// Replaces the storage with new storage holding the data
+    pub fn set_data(&mut self, vertex_data: &[f32], stride: usize, usage: BufferUsage) {
         self.num_vertices = vertex_data.len() / stride;
+        self.capacity = vertex_data.len();
+        self.usage = usage;
         unsafe {
             gl::BufferData(
                 gl::ARRAY_BUFFER,
                 (vertex_data.len() * std::mem::size_of::<f32>()) as isize,
                 vertex_data.as_ptr() as *const GLvoid,
-                gl::STATIC_DRAW,
+                usage.to_gl(),
+            );
+        }
+    }
+
+    fn allocate_floats(&mut self, capacity: usize, usage: BufferUsage) {
+        self.capacity = capacity;
+        self.usage = usage;
+        unsafe {
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (capacity * std::mem::size_of::<f32>()) as isize,
+                std::ptr::null(),
+                usage.to_gl(),
             );
         }
     }
 
+    /// Hands the storage over to the driver, which frees it once draws still reading it
+    /// are done, and continues with fresh storage of the same size. Writing to the
+    /// buffer right after a draw doesn't have to wait for the draw this way
+    pub fn orphan(&mut self) {
+        let (capacity, usage) = (self.capacity, self.usage);
+        self.allocate_floats(capacity, usage);
+    }
+
+    /// Overwrites part of the storage, starting `offset` floats in
+    pub fn update(&mut self, offset: usize, data: &[f32]) {
+        assert!(
+            offset + data.len() <= self.capacity,
+            "Update of {} floats at {} past the end of a buffer of {}",
+            data.len(),
+            offset,
+            self.capacity
+        );
+        unsafe {
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                (offset * std::mem::size_of::<f32>()) as isize,
+                (data.len() * std::mem::size_of::<f32>()) as isize,
+                data.as_ptr() as *const GLvoid,
+            );
+        }
+    }
+
+    /// Replaces the data with data that changes now and then
+    pub fn set_dynamic_data(&mut self, vertex_data: &[f32], stride: usize) {
+        self.replace_data(vertex_data, stride, BufferUsage::Dynamic);
+    }
+
+    /// Replaces the data with data that changes about every time it's drawn
+    pub fn set_stream_data(&mut self, vertex_data: &[f32], stride: usize) {
+        self.replace_data(vertex_data, stride, BufferUsage::Stream);
+    }
+
+    /// Orphans the storage and reuses its size while the data fits
+    fn replace_data(&mut self, vertex_data: &[f32], stride: usize, usage: BufferUsage) {
+        if usage != self.usage || vertex_data.len() > self.capacity {
+            // Grows geometrically so that slowly growing data doesn't reallocate every time
+            let capacity = vertex_data.len().max(self.capacity * 2);
+            self.allocate_floats(capacity, usage);
+        } else {
+            self.orphan();
+        }
+        self.update(0, vertex_data);
+        self.num_vertices = vertex_data.len() / stride;
+    }
+
     pub fn num_vertices(&self) -> usize {
         self.num_vertices
     }
@@ -52,6 +150,165 @@ impl VertexBuffer {
             gl::DrawArrays(gl::TRIANGLES, 0, self.num_vertices as i32);
         }
     }
+
+    pub fn draw_lines(&self) {
+        unsafe {
+            gl::DrawArrays(gl::LINES, 0, self.num_vertices as i32);
+        }
+    }
+
+    pub fn draw_triangles_instanced(&self, instances: usize) {
+        unsafe {
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, self.num_vertices as i32, instances as i32);
+        }
+    }
+}
+
+impl Drop for VertexBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/// Segments of a ring buffer. The CPU writes one while the GPU may still be reading
+/// the others, so up to this many frames can be in flight
+const RING_SEGMENTS: usize = 3;
+
+/// Vertex buffer that stays mapped for its whole life, so that data written every frame
+/// is copied once, straight into the storage the GPU reads. Needs GL_ARB_buffer_storage
+pub struct RingBuffer {
+    id: GLuint,
+    data: *mut f32,
+    /// Floats in each segment
+    segment_size: usize,
+    segment: usize,
+    /// Floats written to the current segment
+    used: usize,
+    /// Signalled once the GPU is done with the draws that read a segment
+    fences: [GLsync; RING_SEGMENTS],
+}
+
+impl RingBuffer {
+    pub fn is_supported() -> bool {
+        gl::BufferStorage::is_loaded() && extensions::is_supported("GL_ARB_buffer_storage")
+    }
+
+    /// Room for `segment_size` floats each frame. None where persistent mapping
+    /// isn't available
+    pub fn new(segment_size: usize) -> Option<Self> {
+        if !RingBuffer::is_supported() {
+            return None;
+        }
+        let size = (segment_size * RING_SEGMENTS * std::mem::size_of::<f32>()) as isize;
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        let mut id: GLuint = 0;
+        let data = unsafe {
+            gl::GenBuffers(1, &mut id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, id);
+            gl::BufferStorage(gl::ARRAY_BUFFER, size, std::ptr::null(), flags);
+            let data = gl::MapBufferRange(gl::ARRAY_BUFFER, 0, size, flags);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            data as *mut f32
+        };
+        if data.is_null() {
+            unsafe {
+                gl::DeleteBuffers(1, &id);
+            }
+            return None;
+        }
+        Some(RingBuffer {
+            id,
+            data,
+            segment_size,
+            segment: 0,
+            used: 0,
+            fences: [std::ptr::null(); RING_SEGMENTS],
+        })
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.id);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    /// Copies vertices into the current segment and returns the index of the first one,
+    /// for drawing. None if the segment has no room left this frame
+    pub fn push(&mut self, vertex_data: &[f32], stride: usize) -> Option<usize> {
+        // Starts on a whole vertex so that draws can index from the start of the buffer
+        let segment_start = self.segment * self.segment_size;
+        let start = (segment_start + self.used + stride - 1) / stride * stride;
+        if start + vertex_data.len() > segment_start + self.segment_size {
+            return None;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                vertex_data.as_ptr(),
+                self.data.add(start),
+                vertex_data.len(),
+            );
+        }
+        self.used = start + vertex_data.len() - segment_start;
+        Some(start / stride)
+    }
+
+    /// Moves on to the next segment once the draws reading the current one have been
+    /// issued. Waits for the GPU if it's still reading the next segment
+    pub fn next_segment(&mut self) {
+        unsafe {
+            self.fences[self.segment] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+        self.segment = (self.segment + 1) % RING_SEGMENTS;
+        self.used = 0;
+
+        let fence = std::mem::replace(&mut self.fences[self.segment], std::ptr::null());
+        if fence.is_null() {
+            return;
+        }
+        unsafe {
+            loop {
+                let status = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000);
+                if status != gl::TIMEOUT_EXPIRED {
+                    break;
+                }
+            }
+            gl::DeleteSync(fence);
+        }
+    }
+
+    pub fn draw_lines(&self, first: usize, count: usize) {
+        unsafe {
+            gl::DrawArrays(gl::LINES, first as i32, count as i32);
+        }
+    }
+
+    pub fn draw_triangles(&self, first: usize, count: usize) {
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, first as i32, count as i32);
+        }
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            for fence in self.fences.iter().filter(|fence| !fence.is_null()) {
+                gl::DeleteSync(*fence);
+            }
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.id);
+            gl::UnmapBuffer(gl::ARRAY_BUFFER);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
 }
 
 pub struct ElementBuffer {
@@ -103,6 +360,26 @@ impl ElementBuffer {
             );
         }
     }
+
+    pub fn draw_triangles_instanced(&self, instances: usize) {
+        unsafe {
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES,
+                self.num_elements as i32,
+                gl::UNSIGNED_INT,
+                std::ptr::null(),
+                instances as i32,
+            );
+        }
+    }
+}
+
+impl Drop for ElementBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
 }
 
 pub struct VertexArray {
@@ -143,4 +420,78 @@ impl VertexArray {
             gl::EnableVertexAttribArray(location);
         }
     }
+
+    /// Attribute that advances once per instance instead of once per vertex
+    pub fn set_instance_attrib(&self, location: u32, count: i32, stride: usize, offset: usize) {
+        self.set_attrib(location, count, stride, offset);
+        unsafe {
+            gl::VertexAttribDivisor(location, 1);
+        }
+    }
+
+    /// For shaders that make their vertices from gl_VertexID, with no attributes
+    pub fn draw_triangles(&self, vertices: usize) {
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, vertices as i32);
+        }
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.id);
+        }
+    }
+}
+
+/// Buffer of floats read by shaders through a samplerBuffer
+pub struct TextureBuffer {
+    buffer: GLuint,
+    texture: GLuint,
+}
+
+impl TextureBuffer {
+    /// Every texel is made of `components` floats (1 to 4)
+    pub fn new(data: &[f32], components: usize) -> Self {
+        let format = match components {
+            1 => gl::R32F,
+            2 => gl::RG32F,
+            3 => gl::RGB32F,
+            _ => gl::RGBA32F,
+        };
+        let (mut buffer, mut texture): (GLuint, GLuint) = (0, 0);
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, buffer);
+            gl::BufferData(
+                gl::TEXTURE_BUFFER,
+                (data.len() * std::mem::size_of::<f32>()) as isize,
+                data.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW,
+            );
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_BUFFER, texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, format, buffer);
+            gl::BindTexture(gl::TEXTURE_BUFFER, 0);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        }
+        TextureBuffer { buffer, texture }
+    }
+
+    pub fn bind(&self, unit: i32) {
+        unsafe {
+            gl::ActiveTexture(Texture::unit_to_gl_const(unit));
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.texture);
+        }
+    }
+}
+
+impl Drop for TextureBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+    }
 }
@@ -1,12 +1,17 @@
 use glm::{Mat4, Vec3};
 use std::f32::consts::PI;
 
+use crate::frustum::Frustum;
+
 const FOV_MIN: f32 = 0.01 * PI;
 const FOV_MAX: f32 = 0.5 * PI;
 
 const ZOOM_MIN: f32 = 1.0;
 const ZOOM_MAX: f32 = 100.0;
 
+pub const NEAR: f32 = 0.1;
+pub const FAR: f32 = 100.0;
+
 const PITCH_MIN: f32 = -0.49 * PI;
 const PITCH_MAX: f32 = 0.49 * PI;
 
@@ -26,8 +31,8 @@ pub struct Camera {
     yaw: f32,
     pitch: f32,
 
-    movement_speed: f32,
-    sensitivity: f32,
+    pub movement_speed: f32,
+    pub sensitivity: f32,
     zoom: f32,
     pub aspect_ratio: f32,
 }
@@ -111,7 +116,12 @@ impl Camera {
     }
 
     pub fn get_projection_matrix(&self) -> Mat4 {
-        glm::perspective(self.aspect_ratio, self.fov(), 0.1, 100.0)
+        glm::perspective(self.aspect_ratio, self.fov(), NEAR, FAR)
+    }
+
+    /// What the camera sees, in world space
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&(self.get_projection_matrix() * self.get_view_matrix()))
     }
 }
 
@@ -0,0 +1,737 @@
+//! Reading of DDS texture containers, plus a CPU fallback for block compressed formats.
+//! Doesn't touch GL, so it's shared with the offline texture converter.
+
+use std::fs;
+use std::io;
+
+#[derive(Debug, Fail)]
+pub enum DdsError {
+    #[fail(display = "I/O Error ({})", path)]
+    IoError {
+        path: String,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "Not a DDS file")]
+    BadMagic,
+    #[fail(display = "DDS file is truncated")]
+    Truncated,
+    #[fail(
+        display = "Unsupported DDS pixel format (fourcc {:#x}, dxgi {})",
+        fourcc, dxgi
+    )]
+    UnsupportedFormat { fourcc: u32, dxgi: u32 },
+    #[fail(display = "DDS image has no mip levels")]
+    NoLevels,
+}
+
+pub type Result<T> = std::result::Result<T, DdsError>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DdsFormat {
+    Rgba8,
+    Bgra8,
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc6h,
+    Bc7,
+}
+
+impl DdsFormat {
+    pub fn is_compressed(self) -> bool {
+        match self {
+            DdsFormat::Rgba8 | DdsFormat::Bgra8 => false,
+            _ => true,
+        }
+    }
+
+    /// Bytes per 4x4 block for compressed formats, per pixel otherwise
+    fn unit_size(self) -> usize {
+        match self {
+            DdsFormat::Bc1 | DdsFormat::Bc4 => 8,
+            DdsFormat::Rgba8 | DdsFormat::Bgra8 => 4,
+            _ => 16,
+        }
+    }
+
+    /// Number of bytes one mip level of the given size takes
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        if self.is_compressed() {
+            let blocks_x = ((width + 3) / 4).max(1) as usize;
+            let blocks_y = ((height + 3) / 4).max(1) as usize;
+            blocks_x * blocks_y * self.unit_size()
+        } else {
+            width as usize * height as usize * self.unit_size()
+        }
+    }
+}
+
+pub struct DdsLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// A 2D texture with its whole precomputed mip chain
+pub struct DdsImage {
+    pub format: DdsFormat,
+    pub levels: Vec<DdsLevel>,
+}
+
+pub(crate) const MAGIC: &[u8; 4] = b"DDS ";
+pub(crate) const HEADER_SIZE: usize = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+pub(crate) const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+
+pub(crate) const DXGI_R8G8B8A8_UNORM: u32 = 28;
+pub(crate) const DXGI_B8G8R8A8_UNORM: u32 = 87;
+pub(crate) const DXGI_BC6H_UF16: u32 = 95;
+pub(crate) const DXGI_BC7_UNORM: u32 = 98;
+
+pub(crate) fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(DdsError::Truncated),
+    }
+}
+
+/// Reads a DDS file from disk
+pub fn load(path: &str) -> Result<DdsImage> {
+    let bytes = fs::read(path).map_err(|e| DdsError::IoError {
+        path: path.to_owned(),
+        inner: e,
+    })?;
+    parse(&bytes)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<DdsImage> {
+    if bytes.len() < 4 || &bytes[0..4] != MAGIC {
+        return Err(DdsError::BadMagic);
+    }
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    // A broken count could otherwise run past 1x1 or reserve a huge vector
+    let max_mip_count = 32 - width.max(height).max(1).leading_zeros();
+    let mip_count = read_u32(bytes, 28)?.max(1).min(max_mip_count);
+
+    let pf_flags = read_u32(bytes, 80)?;
+    let code = read_u32(bytes, 84)?;
+    let r_mask = read_u32(bytes, 92)?;
+    let mut data_offset = 4 + HEADER_SIZE;
+
+    let format = if pf_flags & DDPF_FOURCC != 0 {
+        let format = match &code.to_le_bytes() {
+            b"DXT1" => Some(DdsFormat::Bc1),
+            b"DXT2" | b"DXT3" => Some(DdsFormat::Bc2),
+            b"DXT4" | b"DXT5" => Some(DdsFormat::Bc3),
+            b"ATI1" | b"BC4U" => Some(DdsFormat::Bc4),
+            b"ATI2" | b"BC5U" => Some(DdsFormat::Bc5),
+            _ => None,
+        };
+        match format {
+            Some(format) => format,
+            None if code == fourcc(b"DX10") => {
+                let dxgi = read_u32(bytes, data_offset)?;
+                data_offset += DX10_HEADER_SIZE;
+                match dxgi {
+                    DXGI_R8G8B8A8_UNORM | 29 => DdsFormat::Rgba8,
+                    DXGI_B8G8R8A8_UNORM | 91 => DdsFormat::Bgra8,
+                    71 | 72 => DdsFormat::Bc1,
+                    74 | 75 => DdsFormat::Bc2,
+                    77 | 78 => DdsFormat::Bc3,
+                    80 => DdsFormat::Bc4,
+                    83 => DdsFormat::Bc5,
+                    DXGI_BC6H_UF16 => DdsFormat::Bc6h,
+                    DXGI_BC7_UNORM | 99 => DdsFormat::Bc7,
+                    _ => return Err(DdsError::UnsupportedFormat { fourcc: code, dxgi }),
+                }
+            }
+            None => {
+                return Err(DdsError::UnsupportedFormat {
+                    fourcc: code,
+                    dxgi: 0,
+                })
+            }
+        }
+    } else if pf_flags & DDPF_RGB != 0 && read_u32(bytes, 88)? == 32 {
+        if r_mask == 0x0000_00ff {
+            DdsFormat::Rgba8
+        } else {
+            DdsFormat::Bgra8
+        }
+    } else {
+        return Err(DdsError::UnsupportedFormat { fourcc: 0, dxgi: 0 });
+    };
+
+    let mut levels = Vec::with_capacity(mip_count as usize);
+    let (mut w, mut h) = (width, height);
+    for _ in 0..mip_count {
+        let size = format.level_size(w, h);
+        let data = bytes
+            .get(data_offset..data_offset + size)
+            .ok_or(DdsError::Truncated)?;
+        levels.push(DdsLevel {
+            width: w,
+            height: h,
+            data: data.to_vec(),
+        });
+        data_offset += size;
+        w = (w / 2).max(1);
+        h = (h / 2).max(1);
+    }
+
+    Ok(DdsImage { format, levels })
+}
+
+/// Decodes a level into tightly packed RGBA8 pixels.
+/// Returns None for BC6H, which is HDR and only supported through the driver
+pub fn decompress(format: DdsFormat, level: &DdsLevel) -> Option<Vec<u8>> {
+    let (width, height) = (level.width as usize, level.height as usize);
+    let mut pixels = vec![0u8; width * height * 4];
+
+    if !format.is_compressed() {
+        let len = pixels.len();
+        pixels.copy_from_slice(&level.data[..len]);
+        if format == DdsFormat::Bgra8 {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        return Some(pixels);
+    }
+
+    let block_size = format.unit_size();
+    let blocks_x = ((width + 3) / 4).max(1);
+    for (i, block) in level.data.chunks(block_size).enumerate() {
+        let texels = match format {
+            DdsFormat::Bc1 => decode_bc1(block, true),
+            DdsFormat::Bc2 => decode_bc2(block),
+            DdsFormat::Bc3 => decode_bc3(block),
+            DdsFormat::Bc4 => decode_bc4(block),
+            DdsFormat::Bc5 => decode_bc5(block),
+            DdsFormat::Bc7 => decode_bc7(block),
+            _ => return None,
+        };
+        let (bx, by) = ((i % blocks_x) * 4, (i / blocks_x) * 4);
+        for (t, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + t % 4, by + t / 4);
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Some(pixels)
+}
+
+fn unpack_565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1f) as u32;
+    let g = ((color >> 5) & 0x3f) as u32;
+    let b = (color & 0x1f) as u32;
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+    ]
+}
+
+fn lerp_color(a: [u8; 3], b: [u8; 3], weight_a: u32, weight_b: u32) -> [u8; 3] {
+    let total = weight_a + weight_b;
+    let mut out = [0u8; 3];
+    for i in 0..3 {
+        out[i] = ((a[i] as u32 * weight_a + b[i] as u32 * weight_b) / total) as u8;
+    }
+    out
+}
+
+pub(crate) fn bc1_palette(c0: u16, c1: u16, allow_transparent: bool) -> [[u8; 4]; 4] {
+    let (a, b) = (unpack_565(c0), unpack_565(c1));
+    let with_alpha = |c: [u8; 3], alpha: u8| [c[0], c[1], c[2], alpha];
+    if c0 > c1 || !allow_transparent {
+        [
+            with_alpha(a, 255),
+            with_alpha(b, 255),
+            with_alpha(lerp_color(a, b, 2, 1), 255),
+            with_alpha(lerp_color(a, b, 1, 2), 255),
+        ]
+    } else {
+        [
+            with_alpha(a, 255),
+            with_alpha(b, 255),
+            with_alpha(lerp_color(a, b, 1, 1), 255),
+            [0, 0, 0, 0],
+        ]
+    }
+}
+
+fn decode_bc1(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let palette = bc1_palette(c0, c1, allow_transparent);
+    let mut texels = [[0u8; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (2 * i)) & 0x3) as usize];
+    }
+    texels
+}
+
+fn decode_bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut texels = decode_bc1(&block[8..16], false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let nibble = (block[i / 2] >> (4 * (i % 2))) & 0xf;
+        texel[3] = nibble * 17;
+    }
+    texels
+}
+
+/// Decodes the 8-byte interpolated channel block used by BC3 alpha, BC4 and BC5
+fn decode_channel_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits: u64 = 0;
+    for (i, byte) in block[2..8].iter().enumerate() {
+        bits |= (*byte as u64) << (8 * i);
+    }
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((bits >> (3 * i)) & 0x7) as usize];
+    }
+    values
+}
+
+fn decode_bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = decode_channel_block(&block[0..8]);
+    let mut texels = decode_bc1(&block[8..16], false);
+    for (texel, a) in texels.iter_mut().zip(alpha.iter()) {
+        texel[3] = *a;
+    }
+    texels
+}
+
+fn decode_bc4(block: &[u8]) -> [[u8; 4]; 16] {
+    let red = decode_channel_block(&block[0..8]);
+    let mut texels = [[0u8; 4]; 16];
+    for (texel, r) in texels.iter_mut().zip(red.iter()) {
+        *texel = [*r, 0, 0, 255];
+    }
+    texels
+}
+
+fn decode_bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let red = decode_channel_block(&block[0..8]);
+    let green = decode_channel_block(&block[8..16]);
+    let mut texels = [[0u8; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = [red[i], green[i], 0, 255];
+    }
+    texels
+}
+
+/// Reads a block from the lowest bit up
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&block[..16]);
+        BitReader {
+            bits: u128::from_le_bytes(bytes),
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u8 {
+        let value = (self.bits & ((1 << count) - 1)) as u8;
+        self.bits >>= count;
+        value
+    }
+}
+
+/// Layout of one of the eight BC7 modes
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint
+    endpoint_pbits: bool,
+    /// One p-bit per subset, shared by both endpoints
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Separate alpha (or colour, depending on index selection) indices of modes 4 and 5
+    index_bits2: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+/// Subset of each texel for two subsets, one bit per texel
+#[rustfmt::skip]
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80,
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a,
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c,
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+/// Subset of each texel for three subsets, two bits per texel
+#[rustfmt::skip]
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// Texel whose index has its top bit dropped, for the second subset of two
+#[rustfmt::skip]
+const BC7_ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor texels of the second and third subset of three
+#[rustfmt::skip]
+const BC7_ANCHORS_3: [[usize; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_interpolate(e0: u8, e1: u8, index: u8, index_bits: u32) -> u8 {
+    let weight = match index_bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    };
+    (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+}
+
+/// Widens a value of the given bit count to 8 bits by repeating its top bits
+fn expand_bits(value: u8, bits: u32) -> u8 {
+    let value = (value as u32) << (8 - bits);
+    (value | (value >> bits)) as u8
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bits = BitReader::new(block);
+    let mode = match (0..8).find(|_| bits.read(1) == 1) {
+        Some(mode) => &BC7_MODES[mode],
+        // Reserved mode, decoders output transparent black
+        None => return [[0u8; 4]; 16],
+    };
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // Endpoints are stored channel by channel, both ends of each subset in turn
+    let mut endpoints = [[[0u8; 4]; 2]; 3];
+    let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+    for channel in 0..channels {
+        let channel_bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            for endpoint in subset.iter_mut() {
+                endpoint[channel] = bits.read(channel_bits);
+            }
+        }
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_pbits || mode.shared_pbits {
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            let shared = if mode.shared_pbits { bits.read(1) } else { 0 };
+            for endpoint in subset.iter_mut() {
+                let pbit = if mode.endpoint_pbits {
+                    bits.read(1)
+                } else {
+                    shared
+                };
+                for value in endpoint.iter_mut().take(channels) {
+                    *value = (*value << 1) | pbit;
+                }
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for subset in endpoints.iter_mut().take(mode.subsets) {
+        for endpoint in subset.iter_mut() {
+            for value in endpoint.iter_mut().take(3) {
+                *value = expand_bits(*value, color_bits);
+            }
+            endpoint[3] = if alpha_bits > 0 {
+                expand_bits(endpoint[3], alpha_bits)
+            } else {
+                255
+            };
+        }
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => ((BC7_PARTITIONS_2[partition] >> texel) & 1) as usize,
+        3 => ((BC7_PARTITIONS_3[partition] >> (2 * texel)) & 3) as usize,
+        _ => 0,
+    };
+    let is_anchor = |texel: usize| {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS_2[partition],
+                3 => BC7_ANCHORS_3[partition].contains(&texel),
+                _ => false,
+            }
+    };
+    // Anchor texels have their top index bit dropped since it's always 0
+    let mut indices = [0u8; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index_bits - is_anchor(texel) as u32);
+    }
+    let mut indices2 = [0u8; 16];
+    if mode.index_bits2 > 0 {
+        for (texel, index) in indices2.iter_mut().enumerate() {
+            *index = bits.read(mode.index_bits2 - (texel == 0) as u32);
+        }
+    }
+
+    let mut texels = [[0u8; 4]; 16];
+    for (t, texel) in texels.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[subset_of(t)];
+        // Modes 4 and 5 have separate colour and alpha indices, swapped by index selection
+        let ((color_index, color_index_bits), (alpha_index, alpha_index_bits)) =
+            match (mode.index_bits2, index_selection) {
+                (0, _) => ((indices[t], mode.index_bits), (indices[t], mode.index_bits)),
+                (_, 0) => (
+                    (indices[t], mode.index_bits),
+                    (indices2[t], mode.index_bits2),
+                ),
+                _ => (
+                    (indices2[t], mode.index_bits2),
+                    (indices[t], mode.index_bits),
+                ),
+            };
+        for c in 0..3 {
+            texel[c] = bc7_interpolate(e0[c], e1[c], color_index, color_index_bits);
+        }
+        texel[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_index_bits);
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => {}
+        }
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs fields from the lowest bit up, the way blocks are read
+    #[derive(Default)]
+    struct BitWriter {
+        bits: u128,
+        len: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, count: u32) {
+            self.bits |= (value as u128) << self.len;
+            self.len += count;
+        }
+
+        fn finish(self) -> [u8; 16] {
+            assert_eq!(self.len, 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn header(width: u32, height: u32, mip_count: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; 4 + HEADER_SIZE];
+        bytes[0..4].copy_from_slice(MAGIC);
+        let mut put = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        put(12, height);
+        put(16, width);
+        put(28, mip_count);
+        put(80, DDPF_FOURCC);
+        put(84, fourcc(b"DXT1"));
+        bytes
+    }
+
+    #[test]
+    fn mip_count_is_clamped_to_the_chain_length() {
+        let mut bytes = header(4, 2, 1000);
+        // 4x2, 2x1 and 1x1 are a block each
+        bytes.extend_from_slice(&[0u8; 3 * 8]);
+        let image = parse(&bytes).unwrap();
+        assert_eq!(image.levels.len(), 3);
+        assert_eq!((image.levels[2].width, image.levels[2].height), (1, 1));
+    }
+
+    #[test]
+    fn missing_level_data_is_truncated() {
+        let mut bytes = header(8, 8, 2);
+        bytes.extend_from_slice(&[0u8; 4 * 8]);
+        match parse(&bytes) {
+            Err(DdsError::Truncated) => {}
+            _ => panic!("expected a truncated file"),
+        }
+    }
+
+    #[test]
+    fn level_size_doesnt_overflow() {
+        assert_eq!(
+            DdsFormat::Rgba8.level_size(70_000, 70_000),
+            70_000 * 70_000 * 4
+        );
+        assert_eq!(DdsFormat::Bc1.level_size(1, 1), 8);
+        assert_eq!(DdsFormat::Bc7.level_size(5, 4), 32);
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_16_steps() {
+        let mut block = BitWriter::default();
+        block.write(1 << 6, 7);
+        // Black to white with alpha from 254 to 255, p-bits give the lowest bit
+        for _ in 0..3 {
+            block.write(0, 7);
+            block.write(127, 7);
+        }
+        block.write(127, 7);
+        block.write(127, 7);
+        block.write(0, 1);
+        block.write(1, 1);
+        // Texel i gets index i, the anchor has one bit less
+        block.write(0, 3);
+        for i in 1..16 {
+            block.write(i, 4);
+        }
+        let texels = decode_bc7(&block.finish());
+        for (i, texel) in texels.iter().enumerate() {
+            let value = ((BC7_WEIGHTS_4[i] * 255 + 32) >> 6) as u8;
+            assert_eq!(texel[..3], [value; 3], "texel {}", i);
+        }
+        assert_eq!(texels[0][3], 254);
+        assert_eq!(texels[15][3], 255);
+    }
+
+    #[test]
+    fn bc7_mode_1_splits_the_block_into_subsets() {
+        let mut block = BitWriter::default();
+        block.write(0b10, 2);
+        // Partition 0 puts the two right columns in the second subset
+        block.write(0, 6);
+        let colors = [[63, 0, 0], [0, 0, 63]];
+        for channel in 0..3 {
+            for color in colors.iter() {
+                block.write(color[channel], 6);
+                block.write(color[channel], 6);
+            }
+        }
+        block.write(1, 1);
+        block.write(1, 1);
+        block.write(0, 16 * 3 - 2);
+        let texels = decode_bc7(&block.finish());
+        for (i, texel) in texels.iter().enumerate() {
+            // The p-bit is the lowest bit of zero channels too, which expands to 2
+            let expected = if i % 4 < 2 {
+                [255, 2, 2, 255]
+            } else {
+                [2, 2, 255, 255]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_5_rotates_alpha_into_red() {
+        let mut block = BitWriter::default();
+        block.write(1 << 5, 6);
+        // Swap red and alpha
+        block.write(1, 2);
+        for value in [10, 20, 30].iter() {
+            block.write(*value, 7);
+            block.write(*value, 7);
+        }
+        block.write(200, 8);
+        block.write(200, 8);
+        block.write(0, 31);
+        block.write(0, 31);
+        let texels = decode_bc7(&block.finish());
+        let expand = |v: u8| expand_bits(v, 7);
+        assert_eq!(texels[5], [200, expand(20), expand(30), expand(10)]);
+    }
+
+    #[test]
+    fn bc6h_has_no_cpu_decoder() {
+        let level = DdsLevel {
+            width: 4,
+            height: 4,
+            data: vec![0; 16],
+        };
+        assert!(decompress(DdsFormat::Bc6h, &level).is_none());
+        assert!(decompress(DdsFormat::Bc7, &level).is_some());
+    }
+}
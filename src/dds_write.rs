@@ -0,0 +1,247 @@
+//! Writing of DDS files and BC1/BC3 compression. Only the offline texture converter
+//! needs these, so they're kept apart from the reading side in dds.rs
+
+use crate::dds::{
+    bc1_palette, fourcc, DdsError, DdsFormat, DdsImage, Result, DDPF_FOURCC, DXGI_B8G8R8A8_UNORM,
+    DXGI_BC6H_UF16, DXGI_BC7_UNORM, DXGI_R8G8B8A8_UNORM, HEADER_SIZE, MAGIC,
+};
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDSD_PITCH: u32 = 0x8;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+/// Serializes an image into a DDS file. BC6H, BC7 and RGBA8 are written with a DX10 header
+pub fn write(image: &DdsImage) -> Result<Vec<u8>> {
+    let top = image.levels.first().ok_or(DdsError::NoLevels)?;
+    let mut out = Vec::new();
+    fn put(out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let (code, dxgi) = match image.format {
+        DdsFormat::Bc1 => (fourcc(b"DXT1"), None),
+        DdsFormat::Bc2 => (fourcc(b"DXT3"), None),
+        DdsFormat::Bc3 => (fourcc(b"DXT5"), None),
+        DdsFormat::Bc4 => (fourcc(b"ATI1"), None),
+        DdsFormat::Bc5 => (fourcc(b"ATI2"), None),
+        DdsFormat::Bc6h => (fourcc(b"DX10"), Some(DXGI_BC6H_UF16)),
+        DdsFormat::Bc7 => (fourcc(b"DX10"), Some(DXGI_BC7_UNORM)),
+        DdsFormat::Rgba8 => (fourcc(b"DX10"), Some(DXGI_R8G8B8A8_UNORM)),
+        DdsFormat::Bgra8 => (fourcc(b"DX10"), Some(DXGI_B8G8R8A8_UNORM)),
+    };
+    let size_flag = if image.format.is_compressed() {
+        DDSD_LINEARSIZE
+    } else {
+        DDSD_PITCH
+    };
+    let pitch_or_size = if image.format.is_compressed() {
+        top.data.len() as u32
+    } else {
+        top.width * 4
+    };
+
+    out.extend_from_slice(MAGIC);
+    put(&mut out, HEADER_SIZE as u32);
+    put(
+        &mut out,
+        DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT | size_flag,
+    );
+    put(&mut out, top.height);
+    put(&mut out, top.width);
+    put(&mut out, pitch_or_size);
+    put(&mut out, 0); // depth
+    put(&mut out, image.levels.len() as u32);
+    for _ in 0..11 {
+        put(&mut out, 0);
+    }
+
+    // Pixel format
+    put(&mut out, 32);
+    put(&mut out, DDPF_FOURCC);
+    put(&mut out, code);
+    for _ in 0..5 {
+        put(&mut out, 0);
+    }
+
+    put(&mut out, DDSCAPS_TEXTURE | DDSCAPS_MIPMAP | DDSCAPS_COMPLEX);
+    for _ in 0..4 {
+        put(&mut out, 0);
+    }
+
+    if let Some(dxgi) = dxgi {
+        put(&mut out, dxgi);
+        put(&mut out, 3); // D3D10_RESOURCE_DIMENSION_TEXTURE2D
+        put(&mut out, 0);
+        put(&mut out, 1); // array size
+        put(&mut out, 0);
+    }
+
+    for level in image.levels.iter() {
+        out.extend_from_slice(&level.data);
+    }
+    Ok(out)
+}
+
+/// Compresses RGBA8 pixels into BC1 (no alpha) or BC3 (with alpha).
+/// Uses the bounding box of the block colours as endpoints, which is fast and good enough
+pub fn compress(rgba: &[u8], width: u32, height: u32, with_alpha: bool) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = ((width + 3) / 4).max(1);
+    let blocks_y = ((height + 3) / 4).max(1);
+    let mut out = Vec::with_capacity(blocks_x * blocks_y * if with_alpha { 16 } else { 8 });
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            // Gather the block, clamping at the edges
+            let mut texels = [[0u8; 4]; 16];
+            for (t, texel) in texels.iter_mut().enumerate() {
+                let x = (bx * 4 + t % 4).min(width - 1);
+                let y = (by * 4 + t / 4).min(height - 1);
+                let offset = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+            if with_alpha {
+                encode_channel_block(&texels, 3, &mut out);
+            }
+            encode_color_block(&texels, &mut out);
+        }
+    }
+    out
+}
+
+fn color_distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    (0..3)
+        .map(|i| {
+            let d = a[i] as i32 - b[i] as i32;
+            (d * d) as u32
+        })
+        .sum()
+}
+
+fn encode_color_block(texels: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for texel in texels.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(texel[i]);
+            max[i] = max[i].max(texel[i]);
+        }
+    }
+
+    let (mut c0, mut c1) = (pack_565(max), pack_565(min));
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let mut indices: u32 = 0;
+    if c0 != c1 {
+        let palette = bc1_palette(c0, c1, false);
+        for (i, texel) in texels.iter().enumerate() {
+            let best = (0..4)
+                .min_by_key(|&p| color_distance(*texel, palette[p]))
+                .unwrap();
+            indices |= (best as u32) << (2 * i);
+        }
+    }
+
+    out.extend_from_slice(&c0.to_le_bytes());
+    out.extend_from_slice(&c1.to_le_bytes());
+    out.extend_from_slice(&indices.to_le_bytes());
+}
+
+fn encode_channel_block(texels: &[[u8; 4]; 16], channel: usize, out: &mut Vec<u8>) {
+    let a0 = texels.iter().map(|t| t[channel]).max().unwrap();
+    let a1 = texels.iter().map(|t| t[channel]).min().unwrap();
+    let mut bits: u64 = 0;
+    if a0 != a1 {
+        // a0 > a1 selects the 8-value mode
+        let mut palette = [0i32; 8];
+        palette[0] = a0 as i32;
+        palette[1] = a1 as i32;
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * a0 as i32 + i as i32 * a1 as i32) / 7;
+        }
+        for (i, texel) in texels.iter().enumerate() {
+            let value = texel[channel] as i32;
+            let best = (0..8).min_by_key(|&p| (palette[p] - value).abs()).unwrap();
+            bits |= (best as u64) << (3 * i);
+        }
+    }
+    out.push(a0);
+    out.push(a1);
+    out.extend_from_slice(&bits.to_le_bytes()[0..6]);
+}
+
+fn pack_565(color: [u8; 3]) -> u16 {
+    let r = (color[0] as u16 * 31 + 127) / 255;
+    let g = (color[1] as u16 * 63 + 127) / 255;
+    let b = (color[2] as u16 * 31 + 127) / 255;
+    (r << 11) | (g << 5) | b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dds::{self, DdsLevel};
+
+    #[test]
+    fn written_files_parse_back() {
+        let rgba: Vec<u8> = (0..8 * 8 * 4).map(|i| (i % 251) as u8).collect();
+        let levels = vec![
+            DdsLevel {
+                width: 8,
+                height: 8,
+                data: compress(&rgba, 8, 8, true),
+            },
+            DdsLevel {
+                width: 4,
+                height: 4,
+                data: compress(&rgba[..4 * 4 * 4], 4, 4, true),
+            },
+        ];
+        let bytes = write(&DdsImage {
+            format: DdsFormat::Bc3,
+            levels,
+        })
+        .unwrap();
+        let image = dds::parse(&bytes).unwrap();
+        assert_eq!(image.format, DdsFormat::Bc3);
+        assert_eq!(image.levels.len(), 2);
+        assert_eq!(image.levels[0].data.len(), 4 * 16);
+        assert_eq!(image.levels[1].width, 4);
+    }
+
+    #[test]
+    fn images_without_levels_are_an_error() {
+        let image = DdsImage {
+            format: DdsFormat::Rgba8,
+            levels: Vec::new(),
+        };
+        match write(&image) {
+            Err(DdsError::NoLevels) => {}
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn solid_blocks_survive_compression() {
+        let rgba = [200u8, 100, 50, 255].repeat(16);
+        let block = compress(&rgba, 4, 4, false);
+        let level = DdsLevel {
+            width: 4,
+            height: 4,
+            data: block,
+        };
+        let pixels = dds::decompress(DdsFormat::Bc1, &level).unwrap();
+        for (a, b) in pixels.iter().zip(rgba.iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 4);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Immediate-mode debug geometry. Shapes are collected as lines over a frame,
+//! drawn with one call for each depth mode and then forgotten
+
+use glm::{Mat4, Vec3, Vec4};
+
+use crate::bounds::Aabb;
+use crate::buffers::{RingBuffer, VertexArray, VertexBuffer};
+use crate::render_state::{RenderContext, RenderState};
+use crate::shader::{self, Program};
+
+/// Position and color
+const STRIDE: usize = 7;
+
+/// Vertices the mapped buffer has room for each frame. More go through the streamed one
+const MAPPED_VERTICES: usize = 1 << 16;
+
+/// Line segments used for circles and spheres
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Text anchored to a point in the world
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub position: Vec3,
+    pub text: String,
+    pub color: Vec4,
+}
+
+pub struct DebugDraw {
+    program: Program,
+    vao: VertexArray,
+    vbo: VertexBuffer,
+    /// Where persistent mapping is available, with its own vertex array
+    mapped: Option<(VertexArray, RingBuffer)>,
+    /// Whether shapes added from now on are hidden by what's in front of them
+    pub depth_test: bool,
+    tested: Vec<f32>,
+    on_top: Vec<f32>,
+    labels: Vec<Label>,
+}
+
+impl DebugDraw {
+    pub fn new() -> shader::Result<Self> {
+        let program = Program::new()
+            .vertex_shader("assets/shaders/debug/debug.vert")?
+            .fragment_shader("assets/shaders/debug/debug.frag")?
+            .link()?;
+
+        let vbo = VertexBuffer::new();
+        let vao = VertexArray::new();
+        vbo.bind();
+        vao.bind();
+        vao.set_attrib(0, 3, STRIDE, 0);
+        vao.set_attrib(1, 4, STRIDE, 3);
+        vao.unbind();
+        vbo.unbind();
+
+        let mapped = RingBuffer::new(MAPPED_VERTICES * STRIDE).map(|ring| {
+            let vao = VertexArray::new();
+            ring.bind();
+            vao.bind();
+            vao.set_attrib(0, 3, STRIDE, 0);
+            vao.set_attrib(1, 4, STRIDE, 3);
+            vao.unbind();
+            ring.unbind();
+            (vao, ring)
+        });
+
+        Ok(DebugDraw {
+            program,
+            vao,
+            vbo,
+            mapped,
+            depth_test: true,
+            tested: Vec::new(),
+            on_top: Vec::new(),
+            labels: Vec::new(),
+        })
+    }
+
+    pub fn line(&mut self, from: &Vec3, to: &Vec3, color: &Vec4) {
+        let vertices = if self.depth_test {
+            &mut self.tested
+        } else {
+            &mut self.on_top
+        };
+        for point in [from, to].iter() {
+            vertices.extend_from_slice(point.as_slice());
+            vertices.extend_from_slice(color.as_slice());
+        }
+    }
+
+    /// Lines through consecutive points, back to the first one if closed
+    pub fn polyline(&mut self, points: &[Vec3], closed: bool, color: &Vec4) {
+        for pair in points.windows(2) {
+            self.line(&pair[0], &pair[1], color);
+        }
+        if closed && points.len() > 2 {
+            self.line(&points[points.len() - 1], &points[0], color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: &Vec4) {
+        if !aabb.is_empty() {
+            self.oriented_box(aabb, &glm::identity(), color);
+        }
+    }
+
+    /// Box that is axis-aligned in the space the transform goes from
+    pub fn oriented_box(&mut self, aabb: &Aabb, transform: &Mat4, color: &Vec4) {
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { aabb.min.x } else { aabb.max.x };
+            let y = if i & 2 == 0 { aabb.min.y } else { aabb.max.y };
+            let z = if i & 4 == 0 { aabb.min.z } else { aabb.max.z };
+            glm::vec3(x, y, z)
+        };
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| transform_point(transform, &corner(i)))
+            .collect();
+        self.box_edges(&corners, color);
+    }
+
+    pub fn circle(&mut self, center: &Vec3, normal: &Vec3, radius: f32, color: &Vec4) {
+        let (u, v) = perpendicular_axes(normal);
+        let points: Vec<Vec3> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            })
+            .collect();
+        self.polyline(&points, true, color);
+    }
+
+    /// Circles around the three axes
+    pub fn sphere(&mut self, center: &Vec3, radius: f32, color: &Vec4) {
+        self.circle(center, &glm::vec3(1.0, 0.0, 0.0), radius, color);
+        self.circle(center, &glm::vec3(0.0, 1.0, 0.0), radius, color);
+        self.circle(center, &glm::vec3(0.0, 0.0, 1.0), radius, color);
+    }
+
+    /// Line with a head at `to`
+    pub fn arrow(&mut self, from: &Vec3, to: &Vec3, color: &Vec4) {
+        self.line(from, to, color);
+        let direction = to - from;
+        let length = direction.norm();
+        if length == 0.0 {
+            return;
+        }
+        let (u, v) = perpendicular_axes(&direction);
+        let head = length * 0.2;
+        let base = to - direction / length * head;
+        for offset in [u, -u, v, -v].iter() {
+            self.line(to, &(base + offset * (head * 0.5)), color);
+        }
+    }
+
+    /// Square grid on the XZ plane
+    pub fn grid(&mut self, center: &Vec3, size: f32, cells: u32, color: &Vec4) {
+        let half = size / 2.0;
+        for i in 0..=cells {
+            let offset = -half + size * i as f32 / cells.max(1) as f32;
+            self.line(
+                &(center + glm::vec3(offset, 0.0, -half)),
+                &(center + glm::vec3(offset, 0.0, half)),
+                color,
+            );
+            self.line(
+                &(center + glm::vec3(-half, 0.0, offset)),
+                &(center + glm::vec3(half, 0.0, offset)),
+                color,
+            );
+        }
+    }
+
+    /// X, Y and Z axes of the transform as arrows in red, green and blue
+    pub fn axes(&mut self, transform: &Mat4, size: f32) {
+        let origin = transform_point(transform, &glm::vec3(0.0, 0.0, 0.0));
+        let axes = [
+            (glm::vec3(size, 0.0, 0.0), glm::vec4(1.0, 0.2, 0.2, 1.0)),
+            (glm::vec3(0.0, size, 0.0), glm::vec4(0.2, 1.0, 0.2, 1.0)),
+            (glm::vec3(0.0, 0.0, size), glm::vec4(0.2, 0.4, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes.iter() {
+            self.arrow(&origin, &transform_point(transform, axis), color);
+        }
+    }
+
+    /// Edges of what a camera with this projection * view matrix sees
+    pub fn frustum(&mut self, view_projection: &Mat4, color: &Vec4) {
+        let inverse = glm::inverse(view_projection);
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+                let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+                let z = if i & 4 == 0 { -1.0 } else { 1.0 };
+                let corner = inverse * glm::vec4(x, y, z, 1.0);
+                glm::vec3(corner.x, corner.y, corner.z) / corner.w
+            })
+            .collect();
+        self.box_edges(&corners, color);
+    }
+
+    /// Text at a point in the world, always in front of everything
+    pub fn text(&mut self, position: &Vec3, text: &str, color: &Vec4) {
+        self.labels.push(Label {
+            position: *position,
+            text: text.to_owned(),
+            color: *color,
+        });
+    }
+
+    /// Labels added this frame, for whoever draws text
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// Draws every line added since the last call and forgets them and the labels
+    pub fn draw(
+        &mut self,
+        render_context: &mut RenderContext,
+        proj: &Mat4,
+        view: &Mat4,
+    ) -> shader::Result<()> {
+        self.program.set_used();
+        self.program.set_mat4("proj", proj)?;
+        self.program.set_mat4("view", view)?;
+        let passes = [
+            (&self.tested, RenderState::transparent()),
+            (&self.on_top, RenderState::overlay()),
+        ];
+        for (vertices, state) in passes.iter() {
+            if vertices.is_empty() {
+                continue;
+            }
+            render_context.apply(state);
+            let count = vertices.len() / STRIDE;
+            if let Some((vao, ring)) = &mut self.mapped {
+                if let Some(first) = ring.push(vertices, STRIDE) {
+                    vao.bind();
+                    ring.draw_lines(first, count);
+                    continue;
+                }
+            }
+            self.vao.bind();
+            self.vbo.bind();
+            self.vbo.set_stream_data(vertices, STRIDE);
+            self.vbo.draw_lines();
+        }
+        if let Some((_, ring)) = &mut self.mapped {
+            ring.next_segment();
+        }
+        self.vbo.unbind();
+        self.vao.unbind();
+
+        self.tested.clear();
+        self.on_top.clear();
+        self.labels.clear();
+        Ok(())
+    }
+
+    /// The 12 edges between corners numbered by their x, y and z bits
+    fn box_edges(&mut self, corners: &[Vec3], color: &Vec4) {
+        for i in 0..8 {
+            for &bit in [1, 2, 4].iter() {
+                if i & bit == 0 {
+                    self.line(&corners[i], &corners[i | bit], color);
+                }
+            }
+        }
+    }
+}
+
+fn transform_point(transform: &Mat4, point: &Vec3) -> Vec3 {
+    let point = transform * glm::vec4(point.x, point.y, point.z, 1.0);
+    glm::vec3(point.x, point.y, point.z)
+}
+
+/// Two unit vectors perpendicular to the direction and to each other
+fn perpendicular_axes(direction: &Vec3) -> (Vec3, Vec3) {
+    let direction = direction.normalize();
+    let helper = if direction.x.abs() < 0.9 {
+        glm::vec3(1.0, 0.0, 0.0)
+    } else {
+        glm::vec3(0.0, 1.0, 0.0)
+    };
+    let u = direction.cross(&helper).normalize();
+    let v = direction.cross(&u);
+    (u, v)
+}
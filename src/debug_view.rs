@@ -0,0 +1,137 @@
+//! Views for inspecting what models are made of, switched with hotkeys
+
+use std::fmt;
+
+use glm::Mat4;
+
+use crate::scene::PointLight;
+
+/// Attenuation at which a light volume ends, about one step of an 8 bit color
+const LIGHT_CUTOFF: f32 = 1.0 / 256.0;
+
+/// What the lighting shader outputs instead of the lit color.
+/// Values match the VIEW_ constants in cube.frag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadingView {
+    Lit,
+    Albedo,
+    Specular,
+    /// Derived from the shininess, as for glTF export
+    Roughness,
+    /// Derived from the brightest channel of the specular color
+    Metallic,
+    /// In view space
+    Normals,
+    /// Shows how texture coords are stretched and where they're cut
+    Checkerboard,
+    /// Linear, from black at the camera to white at the far plane
+    Depth,
+}
+
+const SHADING_VIEWS: [ShadingView; 8] = [
+    ShadingView::Lit,
+    ShadingView::Albedo,
+    ShadingView::Specular,
+    ShadingView::Roughness,
+    ShadingView::Metallic,
+    ShadingView::Normals,
+    ShadingView::Checkerboard,
+    ShadingView::Depth,
+];
+
+/// Flat color for the wireframe overlay, VIEW_WIREFRAME in cube.frag
+pub const WIREFRAME_VIEW: i32 = 8;
+
+impl ShadingView {
+    pub fn next(self) -> Self {
+        SHADING_VIEWS[(self as usize + 1) % SHADING_VIEWS.len()]
+    }
+
+    /// Value of the `debug_view` uniform
+    pub fn id(self) -> i32 {
+        self as i32
+    }
+}
+
+impl Default for ShadingView {
+    fn default() -> Self {
+        ShadingView::Lit
+    }
+}
+
+impl fmt::Display for ShadingView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ShadingView::Lit => "lit",
+            ShadingView::Albedo => "albedo",
+            ShadingView::Specular => "specular",
+            ShadingView::Roughness => "roughness",
+            ShadingView::Metallic => "metallic",
+            ShadingView::Normals => "normals",
+            ShadingView::Checkerboard => "UV checkerboard",
+            ShadingView::Depth => "depth",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Debug views that are switched on, all off by default
+#[derive(Debug, Default)]
+pub struct DebugViews {
+    pub shading: ShadingView,
+    /// Edges of every triangle over the shaded scene
+    pub wireframe: bool,
+    /// Normal and tangent of every vertex as lines
+    pub vertex_vectors: bool,
+    /// Spheres around point lights as far as they reach
+    pub light_volumes: bool,
+    /// Boxes around meshes, green when drawn and red when culled, a yellow one around
+    /// everything drawn with a grid under it, and axes at lights
+    pub bounds: bool,
+    /// How many times each pixel is drawn, from blue for once to red for five or more
+    pub overdraw: bool,
+    /// Projection * view that culling uses instead of the camera's, drawn as a frustum,
+    /// so that what gets culled can be seen from outside
+    pub frozen_frustum: Option<Mat4>,
+}
+
+impl DebugViews {
+    /// Freezes culling to the given camera matrix or thaws it if it's frozen
+    pub fn toggle_frozen_frustum(&mut self, view_projection: Mat4) {
+        self.frozen_frustum = match self.frozen_frustum {
+            Some(_) => None,
+            None => Some(view_projection),
+        };
+    }
+}
+
+/// Distance at which the light's attenuation falls below LIGHT_CUTOFF
+pub fn light_radius(light: &PointLight) -> f32 {
+    // Solves 1 + linear * d + quadratic * d^2 = 1 / LIGHT_CUTOFF
+    let (linear, quadratic) = (light.attn_linear, light.attn_quadratic);
+    let constant = 1.0 - 1.0 / LIGHT_CUTOFF;
+    if quadratic > 0.0 {
+        (-linear + (linear * linear - 4.0 * quadratic * constant).sqrt()) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        -constant / linear
+    } else {
+        std::f32::INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shading_views_cycle_in_id_order() {
+        let mut view = ShadingView::default();
+        for (i, expected) in SHADING_VIEWS.iter().enumerate() {
+            assert_eq!(view, *expected);
+            assert_eq!(view.id(), i as i32);
+            view = view.next();
+        }
+        assert_eq!(view, ShadingView::Lit);
+        assert_eq!(WIREFRAME_VIEW, SHADING_VIEWS.len() as i32);
+    }
+}
@@ -0,0 +1,14 @@
+use gl::types::*;
+use std::ffi::CStr;
+
+/// Checks whether the current GL context exposes an extension, e.g. "GL_EXT_texture_compression_s3tc"
+pub fn is_supported(name: &str) -> bool {
+    let mut count: GLint = 0;
+    unsafe {
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    }
+    (0..count as GLuint).any(|i| {
+        let extension = unsafe { CStr::from_ptr(gl::GetStringi(gl::EXTENSIONS, i) as *const _) };
+        extension.to_bytes() == name.as_bytes()
+    })
+}
@@ -0,0 +1,176 @@
+//! View frustum for culling objects the camera can't see
+
+use std::fmt;
+
+use glm::{Mat4, Vec3};
+
+use crate::bounds::{Aabb, BoundingSphere};
+
+/// Points with a positive distance are on the side the normal points to
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Plane from the coefficients of ax + by + cz + d = 0
+    fn from_coefficients(a: f32, b: f32, c: f32, d: f32) -> Self {
+        let normal = glm::vec3(a, b, c);
+        let length = normal.norm();
+        Plane {
+            normal: normal / length,
+            distance: d / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: &Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// Left, right, bottom, top, near and far planes, all facing inwards
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection * view matrix (Gribb and Hartmann).
+    /// Planes are in world space, or in model space for projection * view * model
+    pub fn from_matrix(matrix: &Mat4) -> Self {
+        let row = |i: usize| {
+            glm::vec4(
+                matrix[(i, 0)],
+                matrix[(i, 1)],
+                matrix[(i, 2)],
+                matrix[(i, 3)],
+            )
+        };
+        let plane = |p: glm::Vec4| Plane::from_coefficients(p.x, p.y, p.z, p.w);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum {
+            planes: [
+                plane(w + x),
+                plane(w - x),
+                plane(w + y),
+                plane(w - y),
+                plane(w + z),
+                plane(w - z),
+            ],
+        }
+    }
+
+    /// Whether any part of the sphere may be inside
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+
+    /// Whether any part of the box may be inside. Boxes near the frustum's corners
+    /// can pass while being outside, which only costs drawing them
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        let (center, extents) = (aabb.center(), aabb.extents());
+        self.planes.iter().all(|plane| {
+            let radius = plane.normal.abs().dot(&extents);
+            plane.signed_distance(&center) >= -radius
+        })
+    }
+}
+
+/// Objects drawn and skipped in a frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
+    /// In the levels of detail that were drawn
+    pub triangles: usize,
+}
+
+impl fmt::Display for CullStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "drawn: {}, culled: {}, triangles: {}",
+            self.drawn, self.culled, self.triangles
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Camera at z = 5 looking down -z with a 90 degree field of view, so the side
+    /// planes go through x = +-distance from the camera
+    fn frustum() -> Frustum {
+        let proj = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let view = glm::look_at(
+            &glm::vec3(0.0, 0.0, 5.0),
+            &glm::vec3(0.0, 0.0, 0.0),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        Frustum::from_matrix(&(proj * view))
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: glm::vec3(x, y, z),
+            radius,
+        }
+    }
+
+    fn cube(x: f32, y: f32, z: f32, half_size: f32) -> Aabb {
+        let center = glm::vec3(x, y, z);
+        let half = glm::vec3(half_size, half_size, half_size);
+        Aabb {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    #[test]
+    fn planes_face_inwards() {
+        let frustum = frustum();
+        for plane in frustum.planes.iter() {
+            assert!((plane.normal.norm() - 1.0).abs() < 1e-5);
+            assert!(plane.signed_distance(&glm::vec3(0.0, 0.0, 0.0)) > 0.0);
+        }
+        // Near and far planes
+        let near = frustum.planes[4].signed_distance(&glm::vec3(0.0, 0.0, 4.9));
+        let far = frustum.planes[5].signed_distance(&glm::vec3(0.0, 0.0, -95.0));
+        assert!(near.abs() < 1e-3, "{}", near);
+        assert!(far.abs() < 1e-2, "{}", far);
+    }
+
+    #[test]
+    fn spheres() {
+        let frustum = frustum();
+        // In front
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.0, 1.0)));
+        // Behind the camera
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 8.0, 1.0)));
+        // Beyond the far plane
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -200.0, 1.0)));
+        // Straddling the right plane, which is at x = 5 where z = 0
+        assert!(frustum.intersects_sphere(&sphere(5.5, 0.0, 0.0, 1.0)));
+        // Just outside it
+        assert!(!frustum.intersects_sphere(&sphere(7.0, 0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn boxes() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&cube(0.0, 0.0, 0.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 0.0, 8.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 0.0, -200.0, 1.0)));
+        // Straddling the top plane, which is at y = 5 where z = 0
+        assert!(frustum.intersects_aabb(&cube(0.0, 5.5, 0.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 7.0, 0.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&Aabb::empty()));
+    }
+}
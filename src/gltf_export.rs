@@ -0,0 +1,577 @@
+//! Writes scenes to glTF 2.0, either as .gltf with a .bin next to it or as a single .glb.
+//! Node hierarchy, meshes, materials with their textures and point lights are exported.
+//! Skins, animations and morph targets are not. PNG and JPEG files are embedded as they
+//! are, model textures as PNG made from their decoded pixels
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::material::{AlphaMode, Material};
+use crate::mesh::MeshData;
+use crate::render_state::CullMode;
+use crate::scene::{Attachment, Scene};
+use crate::texture::Image;
+
+#[derive(Debug, Fail)]
+pub enum ExportError {
+    #[fail(display = "I/O Error ({})", path)]
+    IoError {
+        path: String,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "Can only export to .gltf or .glb, not {}", path)]
+    UnknownFormat { path: String },
+}
+
+pub type Result<T> = std::result::Result<T, ExportError>;
+
+/// Everything needed to write a scene.
+/// Meshes, materials and textures are indexed the same way as in the scene's attachments
+pub struct SceneExport<'a> {
+    pub scene: &'a Scene,
+    pub meshes: &'a [MeshData],
+    pub materials: &'a [Material],
+    pub textures: &'a [TextureSource],
+}
+
+/// Where the image of a texture is exported from
+pub enum TextureSource {
+    /// Image file, embedded as it is if it's a PNG or JPEG
+    File(String),
+    /// Decoded pixels with the top row first, embedded as PNG
+    Pixels(Image),
+    /// Made up in code, like the plain white default. Not exported
+    Generated,
+}
+
+// glTF constants, same as their GL counterparts
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const LINEAR: u32 = 9729;
+const LINEAR_MIPMAP_LINEAR: u32 = 9987;
+const REPEAT: u32 = 10497;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Binary data together with the buffer views and accessors pointing into it
+#[derive(Default)]
+struct BufferBuilder {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl BufferBuilder {
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Vectors of `components` floats each. Positions need bounds
+    fn push_floats(&mut self, values: &[f32], components: usize, with_bounds: bool) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&bytes, Some(ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len() / components,
+            "type": format!("VEC{}", components),
+        });
+        if with_bounds {
+            let mut min = vec![std::f32::MAX; components];
+            let mut max = vec![std::f32::MIN; components];
+            for vector in values.chunks(components) {
+                for (k, &v) in vector.iter().enumerate() {
+                    min[k] = min[k].min(v);
+                    max[k] = max[k].max(v);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|i| i.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Attributes and indices of a primitive, without the material
+    fn push_mesh(&mut self, mesh: &MeshData) -> Value {
+        let flatten3 = |v: &[[f32; 3]]| v.iter().flat_map(|x| x.to_vec()).collect::<Vec<f32>>();
+        let mut attributes = json!({
+            "POSITION": self.push_floats(&flatten3(&mesh.positions), 3, true),
+        });
+        if mesh.normals.len() == mesh.positions.len() {
+            attributes["NORMAL"] = json!(self.push_floats(&flatten3(&mesh.normals), 3, false));
+        }
+        // glTF puts the origin of texture coords at the top left
+        let flip = !mesh.tex_coords_top_left;
+        if mesh.tex_coords.len() == mesh.positions.len() {
+            let tex_coords: Vec<f32> = mesh
+                .tex_coords
+                .iter()
+                .flat_map(|t| vec![t[0], if flip { 1.0 - t[1] } else { t[1] }])
+                .collect();
+            attributes["TEXCOORD_0"] = json!(self.push_floats(&tex_coords, 2, false));
+        }
+        if mesh.tangents.len() == mesh.positions.len() {
+            // Flipping texture coords flips the bitangent too
+            let tangents: Vec<f32> = mesh
+                .tangents
+                .iter()
+                .flat_map(|t| vec![t[0], t[1], t[2], if flip { -t[3] } else { t[3] }])
+                .collect();
+            attributes["TANGENT"] = json!(self.push_floats(&tangents, 4, false));
+        }
+        let mut primitive = json!({ "attributes": attributes });
+        if !mesh.indices.is_empty() {
+            primitive["indices"] = json!(self.push_indices(&mesh.indices));
+        }
+        primitive
+    }
+}
+
+/// Writes the scene. The format is picked by the file extension
+pub fn export(export: &SceneExport, path: &str) -> Result<()> {
+    let io_error = |path: &str, e| ExportError::IoError {
+        path: path.to_owned(),
+        inner: e,
+    };
+    let glb = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("glb") => true,
+        Some("gltf") => false,
+        _ => {
+            return Err(ExportError::UnknownFormat {
+                path: path.to_owned(),
+            })
+        }
+    };
+
+    let mut buffer = BufferBuilder::default();
+    let mut meshes = Vec::new();
+    let mut lights = Vec::new();
+
+    // Node indices match scene node indices. Every mesh is written once,
+    // however many nodes use it
+    let mut primitive_by_mesh: HashMap<usize, Value> = HashMap::new();
+    let mut nodes = Vec::new();
+    for (_, node) in export.scene.nodes() {
+        let (t, r, s) = (node.translation(), node.rotation(), node.scale());
+        let mut json_node = json!({
+            "name": node.name,
+            "translation": [t.x, t.y, t.z],
+            "rotation": [r.coords.x, r.coords.y, r.coords.z, r.coords.w],
+            "scale": [s.x, s.y, s.z],
+        });
+        if !node.children().is_empty() {
+            let children: Vec<usize> = node.children().iter().map(|c| c.index()).collect();
+            json_node["children"] = json!(children);
+        }
+
+        let mut primitives = Vec::new();
+        for attachment in node.attachments.iter() {
+            match attachment {
+                Attachment::Mesh { mesh, material, .. } => {
+                    let mut primitive = primitive_by_mesh
+                        .entry(*mesh)
+                        .or_insert_with(|| buffer.push_mesh(&export.meshes[*mesh]))
+                        .clone();
+                    primitive["material"] = json!(material);
+                    primitives.push(primitive);
+                }
+                Attachment::Light(light) => {
+                    let c = light.color;
+                    lights.push(json!({
+                        "type": "point",
+                        "color": [c.x, c.y, c.z],
+                        "intensity": 1.0,
+                    }));
+                    json_node["extensions"] = json!({
+                        "KHR_lights_punctual": { "light": lights.len() - 1 },
+                    });
+                }
+                Attachment::Camera => {}
+            }
+        }
+        if !primitives.is_empty() {
+            meshes.push(json!({ "primitives": primitives }));
+            json_node["mesh"] = json!(meshes.len() - 1);
+        }
+        nodes.push(json_node);
+    }
+
+    // Images are embedded so that the export doesn't depend on where the assets are
+    let mut images = Vec::new();
+    let mut textures = Vec::new();
+    let mut gltf_texture_by_texture: HashMap<usize, Option<usize>> = HashMap::new();
+    let mut materials = Vec::new();
+    for (i, material) in export.materials.iter().enumerate() {
+        // Blinn-Phong exponent to roughness
+        let roughness = (2.0 / (material.shininess + 2.0)).sqrt();
+        let mut pbr = json!({
+            "baseColorFactor": [1.0, 1.0, 1.0, material.opacity],
+            "metallicFactor": 0.0,
+            "roughnessFactor": roughness,
+        });
+
+        let texture = match gltf_texture_by_texture.get(&material.diffuse) {
+            Some(&texture) => texture,
+            None => {
+                let image = match export.textures.get(material.diffuse) {
+                    Some(TextureSource::File(image_path)) => match mime_type(image_path) {
+                        Some(mime_type) => {
+                            let bytes =
+                                fs::read(image_path).map_err(|e| io_error(image_path, e))?;
+                            Some((bytes, mime_type))
+                        }
+                        None => None,
+                    },
+                    Some(TextureSource::Pixels(image)) => Some((encode_png(image), "image/png")),
+                    _ => None,
+                };
+                let texture = match image {
+                    Some((bytes, mime_type)) => {
+                        let view = buffer.push_view(&bytes, None);
+                        images.push(json!({ "bufferView": view, "mimeType": mime_type }));
+                        textures.push(json!({ "source": images.len() - 1, "sampler": 0 }));
+                        Some(textures.len() - 1)
+                    }
+                    None => None,
+                };
+                gltf_texture_by_texture.insert(material.diffuse, texture);
+                texture
+            }
+        };
+        if let Some(texture) = texture {
+            pbr["baseColorTexture"] = json!({ "index": texture });
+        }
+        let mut gltf_material = json!({
+            "name": format!("material {}", i),
+            "pbrMetallicRoughness": pbr,
+        });
+        match material.alpha_mode {
+            AlphaMode::Opaque => {}
+            AlphaMode::Mask(cutoff) => {
+                gltf_material["alphaMode"] = json!("MASK");
+                gltf_material["alphaCutoff"] = json!(cutoff);
+            }
+            AlphaMode::Blend => gltf_material["alphaMode"] = json!("BLEND"),
+        }
+        if material.cull == CullMode::None {
+            gltf_material["doubleSided"] = json!(true);
+        }
+        materials.push(gltf_material);
+    }
+
+    let roots: Vec<usize> = export.scene.roots().iter().map(|r| r.index()).collect();
+    let mut root = json!({
+        "asset": { "version": "2.0", "generator": "boulder-dash" },
+        "scene": 0,
+        "scenes": [{ "nodes": roots }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": buffer.accessors,
+        "bufferViews": buffer.views,
+    });
+    if !textures.is_empty() {
+        root["images"] = json!(images);
+        root["textures"] = json!(textures);
+        root["samplers"] = json!([{
+            "magFilter": LINEAR,
+            "minFilter": LINEAR_MIPMAP_LINEAR,
+            "wrapS": REPEAT,
+            "wrapT": REPEAT,
+        }]);
+    }
+    if !lights.is_empty() {
+        root["extensionsUsed"] = json!(["KHR_lights_punctual"]);
+        root["extensions"] = json!({ "KHR_lights_punctual": { "lights": lights } });
+    }
+
+    while buffer.data.len() % 4 != 0 {
+        buffer.data.push(0);
+    }
+    if glb {
+        if !buffer.data.is_empty() {
+            root["buffers"] = json!([{ "byteLength": buffer.data.len() }]);
+        }
+        let json = serde_json::to_vec(&root).expect("Scene JSON is always serializable");
+        fs::write(path, write_glb(&json, &buffer.data)).map_err(|e| io_error(path, e))
+    } else {
+        let bin_path = Path::new(path).with_extension("bin");
+        if !buffer.data.is_empty() {
+            let uri = bin_path.file_name().unwrap().to_string_lossy();
+            root["buffers"] = json!([{ "byteLength": buffer.data.len(), "uri": uri }]);
+            fs::write(&bin_path, &buffer.data)
+                .map_err(|e| io_error(&bin_path.to_string_lossy(), e))?;
+        }
+        let json = serde_json::to_string_pretty(&root).expect("Scene JSON is always serializable");
+        fs::write(path, json).map_err(|e| io_error(path, e))
+    }
+}
+
+/// MIME type of an image file if glTF allows embedding it
+fn mime_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        _ => None,
+    }
+}
+
+/// PNG of the image, stored without compression since there's no deflate
+/// implementation around
+fn encode_png(image: &Image) -> Vec<u8> {
+    let color_type = match image.channels {
+        1 => 0, // Grey
+        2 => 4, // Grey and alpha
+        3 => 2, // RGB
+        _ => 6, // RGBA
+    };
+    let row_size = image.width as usize * image.channels as usize;
+    let mut scanlines = Vec::with_capacity((row_size + 1) * image.height as usize);
+    for row in 0..image.height as usize {
+        // No filter
+        scanlines.push(0);
+        scanlines.extend_from_slice(&image.data[row * row_size..(row + 1) * row_size]);
+    }
+
+    // zlib stream of stored deflate blocks, the last one flagged as final
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if scanlines.is_empty() {
+        vec![&[]]
+    } else {
+        scanlines.chunks(0xFFFF).collect()
+    };
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push(if i == blocks.len() - 1 { 1 } else { 0 });
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // Bit depth, color type, compression, filter and interlace method
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    push_png_chunk(&mut png, b"IHDR", &header);
+    push_png_chunk(&mut png, b"IDAT", &zlib);
+    push_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn push_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(kind.iter().chain(data.iter()));
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Binary glTF: a header followed by the JSON chunk and the binary chunk
+fn write_glb(json: &[u8], bin: &[u8]) -> Vec<u8> {
+    let padding = |len: usize| (4 - len % 4) % 4;
+    let json_length = json.len() + padding(json.len());
+    let mut total_length = 12 + 8 + json_length;
+    if !bin.is_empty() {
+        total_length += 8 + bin.len();
+    }
+
+    let mut out = Vec::with_capacity(total_length);
+    out.extend_from_slice(GLB_MAGIC);
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&(total_length as u32).to_le_bytes());
+
+    // JSON is padded with spaces, binary data is already aligned
+    out.extend_from_slice(&(json_length as u32).to_le_bytes());
+    out.extend_from_slice(&GLB_CHUNK_JSON.to_le_bytes());
+    out.extend_from_slice(json);
+    out.extend(std::iter::repeat(b' ').take(padding(json.len())));
+    if !bin.is_empty() {
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(&GLB_CHUNK_BIN.to_le_bytes());
+        out.extend_from_slice(bin);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model;
+    use crate::render_state::BlendMode;
+    use crate::shapes;
+
+    fn round_trip(extension: &str) {
+        let gl_cube = shapes::cube(1.0);
+        let gltf_cube = MeshData {
+            tex_coords_top_left: true,
+            ..shapes::cube(1.0)
+        };
+        let meshes = [gl_cube, gltf_cube];
+        let mut scene = Scene::new();
+        for (i, name) in ["gl", "gltf"].iter().enumerate() {
+            let node = scene.add_node(name, None);
+            scene.attach(
+                node,
+                Attachment::Mesh {
+                    mesh: i,
+                    material: 0,
+                    skin: None,
+                },
+            );
+        }
+        let materials = [Material {
+            diffuse: 0,
+            specular: 0,
+            shininess: 32.0,
+            alpha_mode: AlphaMode::Opaque,
+            opacity: 1.0,
+            depth_write: false,
+            cull: CullMode::Back,
+            blend: BlendMode::Alpha,
+        }];
+        let export_data = SceneExport {
+            scene: &scene,
+            meshes: &meshes,
+            materials: &materials,
+            textures: &[TextureSource::Generated],
+        };
+
+        let dir = std::env::temp_dir().join(format!("export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("round_trip.{}", extension));
+        let path = path.to_string_lossy();
+        export(&export_data, &path).unwrap();
+        let loaded = model::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.primitives.len(), 2);
+        for (original, primitive) in meshes.iter().zip(loaded.primitives.iter()) {
+            let mesh = &primitive.mesh;
+            assert!(mesh.tex_coords_top_left);
+            assert_eq!(mesh.positions, original.positions);
+            assert_eq!(mesh.normals, original.normals);
+            assert_eq!(mesh.indices, original.indices);
+        }
+
+        // Only the mesh with GL texture coords gets flipped
+        let gl = &loaded.primitives[0].mesh;
+        for (loaded, original) in gl.tex_coords.iter().zip(meshes[0].tex_coords.iter()) {
+            assert_eq!(*loaded, [original[0], 1.0 - original[1]]);
+        }
+        for (loaded, original) in gl.tangents.iter().zip(meshes[0].tangents.iter()) {
+            assert_eq!(
+                *loaded,
+                [original[0], original[1], original[2], -original[3]]
+            );
+        }
+        let gltf = &loaded.primitives[1].mesh;
+        assert_eq!(gltf.tex_coords, meshes[1].tex_coords);
+        assert_eq!(gltf.tangents, meshes[1].tangents);
+    }
+
+    #[test]
+    fn glb_export_loads_back() {
+        round_trip("glb");
+    }
+
+    #[test]
+    fn gltf_export_loads_back() {
+        round_trip("gltf");
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789".iter()), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn png_has_its_chunks_in_order() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            channels: 3,
+            data: vec![255; 12],
+        };
+        let png = encode_png(&image);
+        assert_eq!(&png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+        // Two rows of a filter byte and 6 bytes, in a single stored block
+        let idat_length = 2 + 5 + 14 + 4;
+        assert_eq!(&png[33..37], &(idat_length as u32).to_be_bytes());
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        assert_eq!(png.len(), 8 + 25 + 12 + idat_length + 12);
+    }
+}
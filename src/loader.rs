@@ -0,0 +1,261 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::dds::{self, DdsImage};
+use crate::mesh::MeshData;
+use crate::model::{self, ModelData};
+use crate::texture::{self, Image};
+
+enum Job {
+    Image(String),
+    Model(String),
+    Simplify {
+        name: String,
+        index: usize,
+        mesh: MeshData,
+        ratios: Vec<f32>,
+    },
+}
+
+/// Decoded asset waiting to be uploaded to GPU, or why it couldn't be decoded
+pub enum LoadedAsset {
+    Image {
+        path: String,
+        image: Image,
+    },
+    CompressedImage {
+        path: String,
+        image: DdsImage,
+    },
+    Model {
+        path: String,
+        model: ModelData,
+    },
+    /// Copies of a mesh simplified to every requested ratio, in order
+    Simplified {
+        name: String,
+        index: usize,
+        levels: Vec<MeshData>,
+    },
+    FailedImage {
+        path: String,
+        error: failure::Error,
+    },
+    FailedModel {
+        path: String,
+        error: failure::Error,
+    },
+    /// Unsimplified copies of the mesh in place of the levels
+    FailedSimplify {
+        name: String,
+        index: usize,
+        levels: Vec<MeshData>,
+        error: failure::Error,
+    },
+}
+
+/// Decodes images and models and simplifies meshes on worker threads.
+/// GL calls are not allowed there, so the main thread polls
+/// for decoded assets and uploads them one by one.
+pub struct AssetLoader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<LoadedAsset>,
+    workers: Vec<JoinHandle<()>>,
+    requested: usize,
+    received: usize,
+}
+
+impl AssetLoader {
+    pub fn new(num_workers: usize) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..num_workers)
+            .map(|_| {
+                let jobs = Arc::clone(&job_receiver);
+                let results = result_sender.clone();
+                thread::spawn(move || loop {
+                    // The lock is released as soon as a job is received
+                    let job = match jobs.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break, // loader is dropped
+                    };
+                    let asset = match job {
+                        Job::Image(path) if path.ends_with(".dds") => {
+                            let result = catch_panic(|| Ok(dds::load(&path)?));
+                            match result {
+                                Ok(image) => LoadedAsset::CompressedImage { path, image },
+                                Err(error) => LoadedAsset::FailedImage { path, error },
+                            }
+                        }
+                        Job::Image(path) => {
+                            let result = catch_panic(|| Ok(texture::decode_image(&path)?));
+                            match result {
+                                Ok(image) => LoadedAsset::Image { path, image },
+                                Err(error) => LoadedAsset::FailedImage { path, error },
+                            }
+                        }
+                        Job::Model(path) => {
+                            let result = catch_panic(|| {
+                                let mut model = model::load(&path)?;
+                                model.optimize_meshes();
+                                Ok(model)
+                            });
+                            match result {
+                                Ok(model) => LoadedAsset::Model { path, model },
+                                Err(error) => LoadedAsset::FailedModel { path, error },
+                            }
+                        }
+                        Job::Simplify {
+                            name,
+                            index,
+                            mesh,
+                            ratios,
+                        } => {
+                            let result = catch_panic(|| {
+                                Ok(ratios.iter().map(|&r| mesh.simplified(r)).collect())
+                            });
+                            match result {
+                                Ok(levels) => LoadedAsset::Simplified {
+                                    name,
+                                    index,
+                                    levels,
+                                },
+                                Err(error) => LoadedAsset::FailedSimplify {
+                                    name,
+                                    index,
+                                    levels: ratios.iter().map(|_| mesh.clone()).collect(),
+                                    error,
+                                },
+                            }
+                        }
+                    };
+                    if results.send(asset).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        AssetLoader {
+            jobs: Some(job_sender),
+            results: result_receiver,
+            workers,
+            requested: 0,
+            received: 0,
+        }
+    }
+
+    pub fn load_image(&mut self, path: &str) {
+        self.submit(Job::Image(path.to_owned()));
+    }
+
+    pub fn load_model(&mut self, path: &str) {
+        self.submit(Job::Model(path.to_owned()));
+    }
+
+    /// Simplifies the mesh to every ratio. The name and index come back with the
+    /// levels to tell which mesh they belong to
+    pub fn simplify(&mut self, name: &str, index: usize, mesh: MeshData, ratios: Vec<f32>) {
+        self.submit(Job::Simplify {
+            name: name.to_owned(),
+            index,
+            mesh,
+            ratios,
+        });
+    }
+
+    fn submit(&mut self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("Asset loader workers have died");
+            self.requested += 1;
+        }
+    }
+
+    /// Returns the next decoded asset if there is one, without blocking
+    pub fn poll(&mut self) -> Option<LoadedAsset> {
+        let result = self.results.try_recv().ok();
+        if result.is_some() {
+            self.received += 1;
+        }
+        result
+    }
+
+    /// Fraction of requested assets that have been handed out by poll
+    pub fn progress(&self) -> f32 {
+        if self.requested == 0 {
+            return 1.0;
+        }
+        self.received as f32 / self.requested as f32
+    }
+
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.received == self.requested
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the channel makes workers exit their loops
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Runs a job, turning a panic into an error so that every job still sends a result.
+/// Otherwise a bad file would leave the loading screen waiting forever
+fn catch_panic<T, F>(job: F) -> Result<T, failure::Error>
+where
+    F: FnOnce() -> Result<T, failure::Error>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(job)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "unknown error".to_owned(),
+                },
+            };
+            Err(failure::err_msg(format!("Panicked: {}", message)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_becomes_error() {
+        let result: Result<(), _> = catch_panic(|| panic!("bad file"));
+        assert_eq!(result.unwrap_err().to_string(), "Panicked: bad file");
+        let result: Result<(), _> = catch_panic(|| panic!("{} bad files", 2));
+        assert_eq!(result.unwrap_err().to_string(), "Panicked: 2 bad files");
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn every_job_gets_a_result() {
+        let mut loader = AssetLoader::new(2);
+        loader.load_image("does/not/exist.png");
+        loader.load_model("does/not/exist.gltf");
+        while !loader.is_done() {
+            match loader.poll() {
+                Some(LoadedAsset::FailedImage { .. }) | Some(LoadedAsset::FailedModel { .. }) => {}
+                Some(_) => panic!("missing files shouldn't load"),
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Instant;
+
+use crate::buffers::{VertexArray, VertexBuffer};
+use crate::render_state::{RenderContext, RenderState};
+use crate::shader::{self, Program};
+use crate::texture::{Image, Texture};
+
+/// Side of a tile, in pixels
+const TILE_SIZE: u32 = 16;
+/// Side of the noise volume, in voxels
+const NOISE_SIZE: u32 = 32;
+
+/// Boulder Dash tiles the bar is filled with, each a layer of the atlas
+#[derive(Clone, Copy)]
+enum Tile {
+    Dirt,
+    Wall,
+    Boulder,
+    Diamond,
+}
+
+const TILES: [Tile; 4] = [Tile::Dirt, Tile::Wall, Tile::Boulder, Tile::Diamond];
+
+/// Draws a progress bar while assets are streaming in. The filled part is a row of
+/// tiles from a texture array, the rest shimmers with noise from a 3D texture so that
+/// the screen keeps moving while a large asset is uploaded
+pub struct LoadingScreen {
+    program: Program,
+    vao: VertexArray,
+    quad: VertexBuffer,
+    tiles: Texture,
+    noise: Texture,
+    start: Instant,
+}
+
+impl LoadingScreen {
+    pub fn new() -> shader::Result<Self> {
+        let program = Program::new()
+            .vertex_shader("assets/shaders/loading/loading.vert")?
+            .fragment_shader("assets/shaders/loading/loading.frag")?
+            .link()?;
+
+        #[rustfmt::skip]
+        let quad_vertices: Vec<f32> = vec![
+            0.0, 0.0,
+            1.0, 0.0,
+            1.0, 1.0,
+            0.0, 0.0,
+            1.0, 1.0,
+            0.0, 1.0,
+        ];
+        let stride = 2;
+        let mut quad = VertexBuffer::new();
+        quad.bind();
+        quad.set_static_data(&quad_vertices, stride);
+        let vao = VertexArray::new();
+        vao.bind();
+        vao.set_attrib(0, 2, stride, 0);
+        vao.unbind();
+        quad.unbind();
+
+        let layers: Vec<Image> = TILES.iter().map(|&tile| tile_image(tile)).collect();
+        let tiles = Texture::new_array()
+            .set_default_parameters()
+            .set_layers(0, &layers)
+            .expect("tiles have the same size")
+            .generate_mipmaps();
+
+        let noise_data = noise_volume(NOISE_SIZE);
+        let noise = Texture::new_3d()
+            .set_default_parameters()
+            .set_volume(0, (NOISE_SIZE, NOISE_SIZE, NOISE_SIZE), 1, &noise_data)
+            .expect("noise volume has the right size");
+
+        Ok(LoadingScreen {
+            program,
+            vao,
+            quad,
+            tiles,
+            noise,
+            start: Instant::now(),
+        })
+    }
+
+    /// Draws the bar filled according to progress (from 0.0 to 1.0)
+    pub fn draw(&self, render_context: &mut RenderContext, progress: f32) -> shader::Result<()> {
+        render_context.clear();
+        render_context.apply(&RenderState::overlay());
+        self.program.set_used();
+        self.program.set_float("progress", progress)?;
+        self.program
+            .set_vec3("bar_color", &glm::vec3(0.8, 0.8, 0.8))?;
+        self.program
+            .set_vec3("background_color", &glm::vec3(0.2, 0.2, 0.2))?;
+        self.program.set_int("tile_count", TILES.len() as i32)?;
+        self.program
+            .set_float("time", self.start.elapsed().as_secs_f32())?;
+        self.tiles.bind(0);
+        self.program.set_texture_unit("tiles", 0)?;
+        self.noise.bind(1);
+        self.program.set_texture_unit("noise", 1)?;
+        self.vao.bind();
+        self.quad.draw_triangles();
+        Ok(())
+    }
+}
+
+fn tile_image(tile: Tile) -> Image {
+    let mut data = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 3) as usize);
+    let center = (TILE_SIZE as f32 - 1.0) / 2.0;
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            let (dx, dy) = (x as f32 - center, y as f32 - center);
+            let color = match tile {
+                Tile::Dirt if hash(x, y, 0) % 5 == 0 => [90, 50, 20],
+                Tile::Dirt => [140, 80, 35],
+                // Bricks with every other row shifted by half a brick
+                Tile::Wall => {
+                    let shift = if (y / 4) % 2 == 0 { 0 } else { 4 };
+                    if y % 4 == 3 || (x + shift) % 8 == 7 {
+                        [60, 60, 60]
+                    } else {
+                        [150, 70, 60]
+                    }
+                }
+                Tile::Boulder if dx * dx + dy * dy < center * center => {
+                    let shade = 170 - ((dx + dy) * 5.0) as i32;
+                    let shade = shade.max(0).min(255) as u8;
+                    [shade, shade, shade]
+                }
+                Tile::Diamond if dx.abs() + dy.abs() < center => [90, 220, 255],
+                Tile::Boulder | Tile::Diamond => [20, 20, 20],
+            };
+            data.extend_from_slice(&color);
+        }
+    }
+    Image {
+        width: TILE_SIZE,
+        height: TILE_SIZE,
+        channels: 3,
+        data,
+    }
+}
+
+/// Single channel white noise, one byte per voxel
+fn noise_volume(size: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(size as usize * size as usize * size as usize);
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                data.push((hash(x, y, z) & 0xff) as u8);
+            }
+        }
+    }
+    data
+}
+
+/// Cheap integer hash, good enough for texture noise
+fn hash(x: u32, y: u32, z: u32) -> u32 {
+    let mut h = x
+        .wrapping_mul(374_761_393)
+        .wrapping_add(y.wrapping_mul(668_265_263))
+        .wrapping_add(z.wrapping_mul(2_147_483_647));
+    h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+    h ^ (h >> 16)
+}
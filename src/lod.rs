@@ -0,0 +1,152 @@
+//! Levels of detail: simpler versions of a mesh drawn when it covers little of the screen
+
+use std::collections::HashMap;
+
+use glm::Vec3;
+
+use crate::bounds::BoundingSphere;
+use crate::scene::NodeId;
+
+/// How far past a level's threshold the screen size has to go before the level
+/// changes, as a fraction of the threshold. Keeps objects near a threshold from popping
+const HYSTERESIS: f32 = 0.1;
+
+pub struct LodLevel {
+    /// Index of the mesh in the scene's list
+    pub mesh: usize,
+    /// Used once the object covers less than this fraction of the screen's height
+    pub screen_size: f32,
+}
+
+/// Coarser versions of a mesh, ordered from the most detailed
+pub struct LodChain {
+    pub levels: Vec<LodLevel>,
+}
+
+impl LodChain {
+    /// Level to use, 0 being the original mesh. Only moves away from the current
+    /// level once the screen size is clearly past the threshold
+    pub fn select(&self, screen_size: f32, current: usize) -> usize {
+        let mut level = current.min(self.levels.len());
+        while level < self.levels.len()
+            && screen_size < self.levels[level].screen_size * (1.0 - HYSTERESIS)
+        {
+            level += 1;
+        }
+        while level > 0 && screen_size > self.levels[level - 1].screen_size * (1.0 + HYSTERESIS) {
+            level -= 1;
+        }
+        level
+    }
+
+    /// Mesh of the level, given the original one
+    pub fn mesh(&self, level: usize, original: usize) -> usize {
+        match level {
+            0 => original,
+            level => self.levels[level - 1].mesh,
+        }
+    }
+}
+
+/// Fraction of the screen's height covered by the sphere's diameter
+pub fn screen_size(sphere: &BoundingSphere, camera_position: &Vec3, fov: f32) -> f32 {
+    let distance = glm::distance(&sphere.center, camera_position);
+    if distance <= sphere.radius {
+        return std::f32::INFINITY;
+    }
+    sphere.radius / (distance * (fov / 2.0).tan())
+}
+
+/// Level each mesh of each node was drawn with last, for hysteresis
+#[derive(Default)]
+pub struct LodSelection {
+    levels: HashMap<(NodeId, usize), usize>,
+}
+
+impl LodSelection {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Mesh to draw for a mesh attachment of a node
+    pub fn select(
+        &mut self,
+        node: NodeId,
+        mesh: usize,
+        chain: &LodChain,
+        screen_size: f32,
+    ) -> usize {
+        let level = self.levels.entry((node, mesh)).or_insert(0);
+        *level = chain.select(screen_size, *level);
+        chain.mesh(*level, mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+
+    fn chain() -> LodChain {
+        LodChain {
+            levels: vec![
+                LodLevel {
+                    mesh: 10,
+                    screen_size: 0.5,
+                },
+                LodLevel {
+                    mesh: 11,
+                    screen_size: 0.2,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn levels_follow_screen_size() {
+        let chain = chain();
+        assert_eq!(chain.select(1.0, 0), 0);
+        assert_eq!(chain.select(0.3, 0), 1);
+        assert_eq!(chain.select(0.1, 0), 2);
+        assert_eq!(chain.select(1.0, 2), 0);
+        assert_eq!(chain.mesh(0, 3), 3);
+        assert_eq!(chain.mesh(2, 3), 11);
+    }
+
+    #[test]
+    fn oscillating_around_a_threshold_keeps_the_level() {
+        let chain = chain();
+        let mut level = chain.select(0.6, 0);
+        assert_eq!(level, 0);
+        // Within 10% on either side of 0.5
+        for &size in [0.48, 0.52, 0.47, 0.53, 0.46].iter() {
+            level = chain.select(size, level);
+            assert_eq!(level, 0, "switched at {}", size);
+        }
+
+        level = chain.select(0.4, level);
+        assert_eq!(level, 1);
+        for &size in [0.52, 0.48, 0.54, 0.46].iter() {
+            level = chain.select(size, level);
+            assert_eq!(level, 1, "switched at {}", size);
+        }
+        level = chain.select(0.6, level);
+        assert_eq!(level, 0);
+    }
+
+    #[test]
+    fn selection_remembers_levels_per_node() {
+        let mut scene = Scene::new();
+        let (a, b) = (scene.add_node("a", None), scene.add_node("b", None));
+        let chain = chain();
+        let mut selection = LodSelection::new();
+        assert_eq!(selection.select(a, 3, &chain, 0.4), 10);
+        assert_eq!(selection.select(b, 3, &chain, 0.6), 3);
+        // Both just around the threshold, so each keeps what it had
+        assert_eq!(selection.select(a, 3, &chain, 0.52), 10);
+        assert_eq!(selection.select(b, 3, &chain, 0.48), 3);
+        // Past the band
+        assert_eq!(selection.select(a, 3, &chain, 0.56), 3);
+        assert_eq!(selection.select(b, 3, &chain, 0.44), 10);
+    }
+}
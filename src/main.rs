@@ -1,11 +1,16 @@
-use std::f32::consts::PI;
+use std::collections::{HashMap, VecDeque};
 use std::time::Instant;
 use std::time::SystemTime;
 
+extern crate base64;
 extern crate gl;
 extern crate gltf;
 extern crate nalgebra_glm as glm;
+extern crate ron;
+extern crate rusttype;
 extern crate sdl2;
+extern crate serde;
+extern crate serde_json;
 extern crate stb_image;
 
 use sdl2::keyboard::Scancode;
@@ -16,16 +21,70 @@ extern crate failure;
 mod shader;
 use shader::Program;
 
+mod dds;
+mod extensions;
+
 mod texture;
 use texture::Texture;
 
 mod buffers;
-use buffers::{VertexArray, VertexBuffer};
+
+mod bounds;
+use bounds::Aabb;
+mod frustum;
+use frustum::{CullStats, Frustum};
+mod lod;
+use lod::LodSelection;
+mod render_queue;
+use render_queue::{DrawCommand, ProgramId, RenderQueue};
+mod render_state;
+use render_state::{CompareFunc, PolygonMode, RenderContext, RenderState};
+mod mesh;
+mod mesh_ops;
+mod shapes;
+mod simplify;
+use mesh::{Instance, Mesh};
+
+mod animation;
+mod animator;
+mod material;
+mod model;
+use model::{ModelData, ModelUpload};
+mod obj;
+mod validation;
+
+mod scene;
+use scene::{Attachment, NodeId, PointLight};
+
+mod scene_file;
+use scene_file::{LoadedScene, SceneDescription};
+
+mod gltf_export;
+use gltf_export::SceneExport;
+
+mod loader;
+use loader::{AssetLoader, LoadedAsset};
+
+mod loading_screen;
+use loading_screen::LoadingScreen;
 
 mod camera;
 use camera::Camera;
 use camera::Movement::*;
 
+mod debug_draw;
+use debug_draw::DebugDraw;
+mod debug_view;
+mod overdraw;
+use debug_view::{DebugViews, ShadingView};
+use overdraw::Overdraw;
+mod profiler;
+use profiler::Profiler;
+mod text;
+use text::TextRenderer;
+mod ui;
+use ui::Ui;
+
 fn main() {
     if let Err(error) = run() {
         eprintln!("{}", error_into_string(error));
@@ -33,21 +92,21 @@ fn main() {
     }
 }
 
-fn run() -> Result<(), failure::Error> {
-    let start = Instant::now();
-    let (gltf, _buffers, _images) = gltf::import("assets/models/knight_artorias/scene.gltf")?;
-    println!("Time: {:.2?}", start.elapsed());
-
-    for scene in gltf.scenes() {
-        for node in scene.nodes() {
-            println!(
-                "Node #{} has {} children",
-                node.index(),
-                node.children().count(),
-            );
-        }
-    }
+const DEFAULT_SCENE: &str = "assets/scenes/default.ron";
+
+/// Where F12 writes the current scene to and F9 frame time captures
+const EXPORT_DIR: &str = "exports";
+
+/// Stands in for textures that failed to load, loud enough to be noticed
+const MISSING_TEXTURE: [u8; 3] = [255, 0, 255];
 
+/// Font of the text overlay
+const FONT_PATH: &str = "assets/fonts/DejaVuSansMono.ttf";
+
+// Has to match the cube shader
+const NUM_POINT_LIGHTS: usize = 4;
+
+fn run() -> Result<(), failure::Error> {
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
 
@@ -55,6 +114,7 @@ fn run() -> Result<(), failure::Error> {
     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
     gl_attr.set_context_version(4, 1);
     gl_attr.set_depth_size(16);
+    gl_attr.set_stencil_size(8);
     gl_attr.set_double_buffer(true);
 
     let window = video_subsystem
@@ -74,95 +134,28 @@ fn run() -> Result<(), failure::Error> {
     );
     sdl.mouse().set_relative_mouse_mode(true);
 
-    unsafe {
-        gl::Viewport(0, 0, window_width as i32, window_height as i32);
-        gl::ClearColor(0.05, 0.05, 0.05, 1.0);
-        gl::Enable(gl::DEPTH_TEST);
-    }
+    let mut render_context = RenderContext::new();
+    render_context.set_viewport(0, 0, window_width as i32, window_height as i32);
+    render_context.set_clear_color([0.05, 0.05, 0.05, 1.0]);
 
-    #[rustfmt::skip]
-    let cube_vertices: Vec<f32> = vec![
-        // positions        // tex coords   // normals
-        0.5, 0.5, 0.5,      1.0, 1.0,       0.0, 0.0, 1.0,      // 0
-        0.5, -0.5, 0.5,     1.0, 0.0,       0.0, 0.0, 1.0,      // 1
-       -0.5, 0.5, 0.5,      0.0, 1.0,       0.0, 0.0, 1.0,      // 3
-        0.5, -0.5, 0.5,     1.0, 0.0,       0.0, 0.0, 1.0,      // 1
-       -0.5, -0.5, 0.5,     0.0, 0.0,       0.0, 0.0, 1.0,      // 2
-       -0.5, 0.5, 0.5,      0.0, 1.0,       0.0, 0.0, 1.0,      // 3
-
-       -0.5, 0.5, -0.5,     1.0, 1.0,       0.0, 0.0, -1.0,     // 7
-        0.5, 0.5, -0.5,     1.0, 0.0,       0.0, 0.0, -1.0,     // 4
-       -0.5, -0.5, -0.5,    0.0, 1.0,       0.0, 0.0, -1.0,     // 6
-       -0.5, -0.5, -0.5,    1.0, 0.0,       0.0, 0.0, -1.0,     // 6
-        0.5, -0.5, -0.5,    0.0, 0.0,       0.0, 0.0, -1.0,     // 5
-        0.5, 0.5, -0.5,     0.0, 1.0,       0.0, 0.0, -1.0,     // 4
-
-        0.5, 0.5, -0.5,     1.0, 1.0,       1.0, 0.0, 0.0,      // 4
-        0.5, -0.5, -0.5,    1.0, 0.0,       1.0, 0.0, 0.0,      // 5
-        0.5, 0.5, 0.5,      0.0, 1.0,       1.0, 0.0, 0.0,      // 0
-        0.5, -0.5, -0.5,    1.0, 0.0,       1.0, 0.0, 0.0,      // 5
-        0.5, -0.5, 0.5,     0.0, 0.0,       1.0, 0.0, 0.0,      // 1
-        0.5, 0.5, 0.5,      0.0, 1.0,       1.0, 0.0, 0.0,      // 0
-
-       -0.5, 0.5, 0.5,      1.0, 1.0,      -1.0, 0.0, 0.0,      // 3
-       -0.5, -0.5, 0.5,     1.0, 0.0,      -1.0, 0.0, 0.0,      // 2
-       -0.5, 0.5, -0.5,     0.0, 1.0,      -1.0, 0.0, 0.0,      // 7
-       -0.5, -0.5, 0.5,     1.0, 0.0,      -1.0, 0.0, 0.0,      // 2
-       -0.5, -0.5, -0.5,    0.0, 0.0,      -1.0, 0.0, 0.0,      // 6
-       -0.5, 0.5, -0.5,     0.0, 1.0,      -1.0, 0.0, 0.0,      // 7
-
-        0.5, 0.5, -0.5,     1.0, 1.0,       0.0, 1.0, 0.0,      // 4
-        0.5, 0.5, 0.5,      1.0, 0.0,       0.0, 1.0, 0.0,      // 0
-       -0.5, 0.5, -0.5,     0.0, 1.0,       0.0, 1.0, 0.0,      // 7
-        0.5, 0.5, 0.5,      1.0, 0.0,       0.0, 1.0, 0.0,      // 0
-       -0.5, 0.5, 0.5,      0.0, 0.0,       0.0, 1.0, 0.0,      // 3
-       -0.5, 0.5, -0.5,     0.0, 1.0,       0.0, 1.0, 0.0,      // 7
-
-        0.5, -0.5, 0.5,     1.0, 1.0,       0.0, -1.0, 0.0,     // 1
-        0.5, -0.5, -0.5,    1.0, 0.0,       0.0, -1.0, 0.0,     // 5
-       -0.5, -0.5, 0.5,     0.0, 1.0,       0.0, -1.0, 0.0,     // 2
-        0.5, -0.5, -0.5,    1.0, 0.0,       0.0, -1.0, 0.0,     // 5
-       -0.5, -0.5, -0.5,    0.0, 0.0,       0.0, -1.0, 0.0,     // 6
-       -0.5, -0.5, 0.5,     0.0, 1.0,       0.0, -1.0, 0.0,     // 2
-    ];
-
-    let cube_positions = vec![
-        glm::vec3(0.0, 0.0, 0.0),
-        glm::vec3(2.0, 5.0, -15.0),
-        glm::vec3(-1.5, -2.2, -2.5),
-        glm::vec3(-3.8, -2.0, -12.3),
-        glm::vec3(2.4, -0.4, -3.5),
-        glm::vec3(-1.7, 3.0, -7.5),
-        glm::vec3(1.3, -2.0, -2.5),
-        glm::vec3(1.5, 2.0, -2.5),
-        glm::vec3(1.5, 0.2, -1.5),
-        glm::vec3(-1.3, 1.0, -1.5),
-    ];
-
-    let cube_model = glm::rotation(-0.25 * PI, &glm::vec3(0.0, 0.0, 1.0));
-
-    // Buffers
-    let stride = 8;
-    let mut cube = VertexBuffer::new();
-    cube.bind();
-    cube.set_static_data(&cube_vertices, stride);
-    let cube_vao = VertexArray::new();
-    cube_vao.bind();
-    cube_vao.set_attrib(0, 3, stride, 0); // Positions
-    cube_vao.set_attrib(1, 2, stride, 3); // Texture coords
-    cube_vao.set_attrib(2, 3, stride, 5); // Normals
-
-    let light_vao = VertexArray::new();
-    light_vao.bind();
-    light_vao.set_attrib(0, 3, stride, 0);
-    cube.unbind();
-
-    let crate_texture = Texture::new()
-        .set_default_parameters()
-        .load_image("assets/textures/crate/diffuse.png")?;
-    let crate_specular_map = Texture::new()
-        .set_default_parameters()
-        .load_image("assets/textures/crate/specular.png")?;
+    let mut event_pump = sdl.event_pump().unwrap();
+
+    // Scene file can be given as the first argument
+    let scene_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_SCENE.to_owned());
+    let loading_screen = LoadingScreen::new()?;
+    let mut world = match load_scene(
+        &scene_path,
+        &window,
+        &mut event_pump,
+        &loading_screen,
+        &mut render_context,
+    )? {
+        Some(world) => world,
+        None => return Ok(()),
+    };
+    let light_cube = Mesh::new(&shapes::cube(1.0));
 
     // Cube shader
     let cube_shader = Program::new()
@@ -170,10 +163,20 @@ fn run() -> Result<(), failure::Error> {
         .fragment_shader("assets/shaders/cube/cube.frag")?
         .link()?;
     cube_shader.set_used();
-    // Set default material
+    // Texture units used by Material::apply
     cube_shader.set_texture_unit("material.diffuse", 0)?;
     cube_shader.set_texture_unit("material.specular", 1)?;
-    cube_shader.set_float("material.shininess", 32.0)?;
+    cube_shader.set_texture_unit("morph_targets", mesh::MORPH_TARGETS_UNIT)?;
+
+    // Same as the cube shader, but vertices follow the joints of a skin
+    let skinned_shader = Program::new()
+        .vertex_shader("assets/shaders/skinned/skinned.vert")?
+        .fragment_shader("assets/shaders/cube/cube.frag")?
+        .link()?;
+    skinned_shader.set_used();
+    skinned_shader.set_texture_unit("material.diffuse", 0)?;
+    skinned_shader.set_texture_unit("material.specular", 1)?;
+    skinned_shader.set_texture_unit("morph_targets", mesh::MORPH_TARGETS_UNIT)?;
 
     // Directional light
     // let light_color: glm::Vec3 = glm::vec3(1.0, 1.0, 1.0);
@@ -182,54 +185,169 @@ fn run() -> Result<(), failure::Error> {
     // cube_shader.set_vec3("directional_light.diffuse", &(0.5 * light_color))?;
     // cube_shader.set_vec3("directional_light.specular", &(1.0 * light_color))?;
 
-    // Point lights
-    let point_light_positions = vec![
-        glm::vec3(0.7, 0.2, 2.0),
-        glm::vec3(2.3, -3.3, -4.0),
-        glm::vec3(-4.0, 2.0, -12.0),
-        glm::vec3(0.0, 0.0, -3.0),
-    ];
-    let point_light_colors = vec![
-        glm::vec3(1.0, 0.0, 0.0),
-        glm::vec3(0.0, 1.0, 0.0),
-        glm::vec3(0.0, 0.5, 0.3),
-        glm::vec3(0.0, 0.0, 1.0),
-    ];
-
-    crate_texture.bind(0);
-    crate_specular_map.bind(1);
-
     // Light shader
     let light_shader = Program::new()
         .vertex_shader("assets/shaders/light/light.vert")?
         .fragment_shader("assets/shaders/light/light.frag")?
         .link()?;
 
+    // Normal and tangent lines of the vertex_vectors debug view
+    let vectors_shader = Program::new()
+        .vertex_shader("assets/shaders/vectors/vectors.vert")?
+        .geometry_shader("assets/shaders/vectors/vectors.geom")?
+        .fragment_shader("assets/shaders/vectors/vectors.frag")?
+        .link()?;
+
     let mut camera = Camera::new();
     camera.aspect_ratio = (window_width as f32) / (window_height as f32);
-    camera.position = glm::vec3(0.0, 2.0, 5.0);
-    camera.look_at(glm::vec3(0.0, 2.0, 0.0));
+    camera.position = world.camera_position;
+    camera.look_at(world.camera_target);
 
     let start_timestamp = SystemTime::now();
     let mut frame_start = SystemTime::now();
+    let mut lod_selection = LodSelection::new();
+    let mut render_queue = RenderQueue::new();
+    let mut debug_views = DebugViews::default();
+    let mut debug_draw = DebugDraw::new()?;
+    let overdraw = Overdraw::new()?;
+    let mut text = TextRenderer::new(FONT_PATH, 16.0)?;
+    let mut show_stats = cfg!(feature = "debug");
+    let mut ui = Ui::new();
+    let mut show_ui = false;
+    let mut profiler = Profiler::new();
+    let mut fps = 0.0;
 
-    let mut event_pump = sdl.event_pump().unwrap();
     'main: loop {
         let now = SystemTime::now();
         let delta_time = now.duration_since(frame_start).unwrap().as_secs_f32();
         frame_start = now;
+        // Smoothed so that it can be read
+        if delta_time > 0.0 {
+            fps = if fps == 0.0 {
+                1.0 / delta_time
+            } else {
+                fps * 0.95 + 0.05 / delta_time
+            };
+        }
 
+        let mut reload = false;
         for event in event_pump.poll_iter() {
             match event {
                 sdl2::event::Event::Quit { .. } => break 'main,
                 sdl2::event::Event::MouseWheel { y, .. } => camera.adjust_zoom(y),
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F5),
+                    ..
+                } => reload = true,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F3),
+                    ..
+                } => show_stats = !show_stats,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F9),
+                    ..
+                } => {
+                    if let Err(error) = toggle_profile_capture(&mut profiler) {
+                        eprintln!("Failed to capture profile: {}", error);
+                    }
+                }
+                // The mouse is released to the UI while it's shown
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::Tab),
+                    ..
+                } => {
+                    show_ui = !show_ui;
+                    sdl.mouse().set_relative_mouse_mode(!show_ui);
+                }
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F1),
+                    ..
+                } => debug_views.shading = debug_views.shading.next(),
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F2),
+                    ..
+                } => debug_views.wireframe = !debug_views.wireframe,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F4),
+                    ..
+                } => debug_views.vertex_vectors = !debug_views.vertex_vectors,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F6),
+                    ..
+                } => debug_views.light_volumes = !debug_views.light_volumes,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F7),
+                    ..
+                } => debug_views.bounds = !debug_views.bounds,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F8),
+                    ..
+                } => debug_views.overdraw = !debug_views.overdraw,
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F10),
+                    ..
+                } => debug_views.toggle_frozen_frustum(
+                    camera.get_projection_matrix() * camera.get_view_matrix(),
+                ),
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(Scancode::F12),
+                    ..
+                } => match export_scene(&world, &scene_path) {
+                    Ok(path) => println!("Exported scene to {}", path),
+                    Err(error) => eprintln!("Failed to export scene: {}", error_into_string(error)),
+                },
+                sdl2::event::Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    // Animation controls
+                    for player in world.players.iter_mut() {
+                        match scancode {
+                            Scancode::P => player.toggle_pause(),
+                            Scancode::L => player.looping = !player.looping,
+                            Scancode::N => player.next_clip(),
+                            Scancode::LeftBracket => player.speed *= 0.5,
+                            Scancode::RightBracket => player.speed *= 2.0,
+                            _ => {}
+                        }
+                    }
+                    if scancode == Scancode::Space {
+                        for animator in world.animators.iter_mut() {
+                            animator.trigger("attack");
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
-        // Look around
+        // Reload the scene file, keeping the old scene if it's broken
+        if reload {
+            match load_scene(
+                &scene_path,
+                &window,
+                &mut event_pump,
+                &loading_screen,
+                &mut render_context,
+            ) {
+                Ok(Some(new_world)) => {
+                    world = new_world;
+                    lod_selection = LodSelection::new();
+                    camera.position = world.camera_position;
+                    camera.look_at(world.camera_target);
+                    frame_start = SystemTime::now();
+                }
+                Ok(None) => break 'main,
+                Err(error) => eprintln!("Failed to reload scene: {}", error_into_string(error)),
+            }
+        }
+
+        // Look around. Movement is still read while the UI has the mouse, so that
+        // the camera doesn't jump when it's closed
         let mouse_state = event_pump.relative_mouse_state();
-        camera.rotate(mouse_state.x(), mouse_state.y());
+        if !show_ui {
+            camera.rotate(mouse_state.x(), mouse_state.y());
+        }
 
         // Move camera
         let keyboard = event_pump.keyboard_state();
@@ -249,10 +367,17 @@ fn run() -> Result<(), failure::Error> {
             camera.go(Right, delta_time);
         }
 
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        // Animators can react to the player moving around
+        let moving = [Scancode::W, Scancode::S, Scancode::A, Scancode::D]
+            .iter()
+            .any(|&key| keyboard.is_scancode_pressed(key));
+        for animator in world.animators.iter_mut() {
+            animator.set_parameter("moving", if moving { 1.0 } else { 0.0 });
         }
 
+        profiler.begin_frame();
+        render_context.clear();
+
         // Time for rotations etc
         let seconds_elapsed = SystemTime::now()
             .duration_since(start_timestamp)
@@ -263,67 +388,286 @@ fn run() -> Result<(), failure::Error> {
         let proj = camera.get_projection_matrix();
         let view = camera.get_view_matrix();
 
-        // // Light cube
-        // let x_max = 2.0;
-        // let z_max = 6.0;
-        // light_position.x = x_max * (seconds_elapsed * 3.0).sin();
-        // light_position.z = z_max * seconds_elapsed.cos() - 5.0;
-        // let light_model = glm::translation(&light_position);
-        // let light_model = glm::scale(&light_model, &glm::vec3(0.1, 0.1, 0.1));
+        profiler.begin("animation");
+        world.animate(seconds_elapsed, delta_time);
+        world.scene.update_transforms();
+        profiler.end("animation");
+
+        let lights: Vec<(glm::Vec3, PointLight)> = world
+            .scene
+            .nodes()
+            .flat_map(|(_, node)| {
+                node.attachments.iter().filter_map(move |a| match a {
+                    Attachment::Light(light) => Some((node.world_position(), light.clone())),
+                    _ => None,
+                })
+            })
+            .collect();
+
+        // Scene passes count the fragments they draw for the overdraw view
+        let stencil = if debug_views.overdraw {
+            Some(overdraw::counting())
+        } else {
+            None
+        };
 
         // Draw light cubes
-        light_vao.bind();
+        profiler.begin("lights");
+        render_context.apply(&RenderState {
+            stencil,
+            ..RenderState::opaque()
+        });
         light_shader.set_used();
         light_shader.set_mat4("proj", &proj)?;
         light_shader.set_mat4("view", &view)?;
-        for (pos, color) in point_light_positions.iter().zip(point_light_colors.iter()) {
+        for (pos, light) in lights.iter() {
             let light_model = glm::translation(&pos);
             let light_model = glm::scale(&light_model, &glm::vec3(0.1, 0.1, 0.1));
             light_shader.set_mat4("model", &light_model)?;
-            light_shader.set_vec3("light_color", &color)?;
-            cube.draw_triangles();
+            light_shader.set_vec3("light_color", &light.color)?;
+            light_cube.draw();
         }
+        profiler.end("lights");
 
-        // Draw rotating cubes
-        cube_shader.set_used();
-        cube_shader.set_mat4("proj", &proj)?;
-        cube_shader.set_mat4("view", &view)?;
-        cube_vao.bind();
+        // Draw meshes
+        for program in [&cube_shader, &skinned_shader].iter() {
+            program.set_used();
+            program.set_mat4("proj", &proj)?;
+            program.set_mat4("view", &view)?;
+            set_point_lights(program, &view, &lights)?;
+            program.set_int("debug_view", debug_views.shading.id())?;
+            program.set_float("far_plane", camera::FAR)?;
+        }
 
-        for (i, (pos, color)) in point_light_positions
-            .iter()
-            .zip(point_light_colors.iter())
-            .enumerate()
-        {
-            // Convert light position to view space
-            let light_pos = glm::vec4_to_vec3(&(view * glm::vec4(pos.x, pos.y, pos.z, 1.0)));
-            cube_shader.set_vec3(&format!("point_lights[{}].position", i), &light_pos)?;
-            cube_shader.set_vec3(&format!("point_lights[{}].ambient", i), &(0.2 * color))?;
-            cube_shader.set_vec3(&format!("point_lights[{}].diffuse", i), &(0.5 * color))?;
-            cube_shader.set_vec3(&format!("point_lights[{}].specular", i), &(1.0 * color))?;
-            cube_shader.set_float(&format!("point_lights[{}].attn_linear", i), 0.09)?;
-            cube_shader.set_float(&format!("point_lights[{}].attn_quadratic", i), 0.032)?;
-        }
-
-        let angle = seconds_elapsed * PI / 5.0;
-        for pos in cube_positions.iter() {
-            let cube_model = glm::translate(&cube_model, pos);
-            let cube_model = glm::rotate(&cube_model, angle, pos); // rotate around position to get different directions
-            cube_shader.set_mat4("model", &cube_model)?;
-
-            cube.draw_triangles();
-        }
-
-        #[cfg(feature = "debug")]
-        {
-            // Display rendering time
-            let render_ms = SystemTime::now()
-                .duration_since(frame_start)
-                .unwrap()
-                .as_micros() as f32
-                / 1000.0;
-            println!("rendering time: {} ms", render_ms);
+        profiler.begin("culling");
+        let frustum = match &debug_views.frozen_frustum {
+            Some(view_projection) => Frustum::from_matrix(view_projection),
+            None => camera.frustum(),
+        };
+        let mut cull_stats = CullStats::default();
+        let mut drawn_bounds = Aabb::empty();
+        render_queue.clear();
+        for (node_id, node) in world.scene.nodes() {
+            for attachment in node.attachments.iter() {
+                if let Attachment::Mesh {
+                    mesh,
+                    material,
+                    skin,
+                } = attachment
+                {
+                    // Skinned and morphed meshes can leave the bounds of their rest pose
+                    let original = *mesh;
+                    let mesh = &world.meshes[original];
+                    let deformed = skin.is_some() || mesh.has_morph_targets();
+                    let model = node.world_matrix();
+                    let sphere = mesh.bounding_sphere.transform(model);
+                    let aabb = mesh.aabb.transform(model);
+                    let culled = !deformed
+                        && !(frustum.intersects_sphere(&sphere) && frustum.intersects_aabb(&aabb));
+                    if debug_views.bounds {
+                        let color = if culled {
+                            glm::vec4(1.0, 0.2, 0.2, 1.0)
+                        } else {
+                            glm::vec4(0.2, 1.0, 0.2, 1.0)
+                        };
+                        debug_draw.aabb(&aabb, &color);
+                    }
+                    if culled {
+                        cull_stats.culled += 1;
+                        continue;
+                    }
+                    cull_stats.drawn += 1;
+                    drawn_bounds = drawn_bounds.merge(&aabb);
+
+                    let mesh_index = match world.lods.get(&original) {
+                        Some(chain) => {
+                            let size = lod::screen_size(&sphere, &camera.position, camera.fov());
+                            lod_selection.select(node_id, original, chain, size)
+                        }
+                        None => original,
+                    };
+                    cull_stats.triangles += world.meshes[mesh_index].num_triangles();
+                    let (program, joint_matrices) = match skin {
+                        Some(skin) => {
+                            // Validation rejects models with more joints than the shader takes
+                            let joint_matrices =
+                                world.skins[*skin].joint_matrices(&world.scene, model);
+                            (ProgramId::Skinned, joint_matrices)
+                        }
+                        None => (ProgramId::Static, Vec::new()),
+                    };
+                    render_queue.submit(DrawCommand {
+                        program,
+                        material: *material,
+                        textures: (
+                            world.materials[*material].diffuse,
+                            world.materials[*material].specular,
+                        ),
+                        mesh: mesh_index,
+                        transform: *model,
+                        color: node.color,
+                        joint_matrices,
+                        morph_weights: node.morph_weights.clone(),
+                        instanceable: !deformed,
+                        transparent: world.materials[*material].is_transparent()
+                            || node.color.w < 1.0,
+                        depth: glm::distance(&sphere.center, &camera.position),
+                    });
+                }
+            }
+        }
+        if debug_views.bounds && !drawn_bounds.is_empty() {
+            debug_draw.aabb(&drawn_bounds, &glm::vec4(1.0, 1.0, 0.2, 1.0));
+            let center = drawn_bounds.center();
+            let extents = drawn_bounds.extents();
+            let floor = glm::vec3(center.x, drawn_bounds.min.y, center.z);
+            let size = 2.0 * extents.x.max(extents.z);
+            debug_draw.grid(&floor, size, 10, &glm::vec4(0.5, 0.5, 0.5, 1.0));
+        }
+        if let Some(view_projection) = &debug_views.frozen_frustum {
+            debug_draw.frustum(view_projection, &glm::vec4(1.0, 1.0, 1.0, 1.0));
         }
+        render_queue.sort();
+        profiler.end("culling");
+
+        profiler.begin("opaque");
+        draw_commands(
+            render_queue.opaque(),
+            &RenderState {
+                stencil,
+                ..RenderState::opaque()
+            },
+            &mut render_context,
+            &cube_shader,
+            &skinned_shader,
+            &mut world,
+        )?;
+        profiler.end("opaque");
+        // Blended over the opaque ones. Depth writes are up to each material
+        profiler.begin("transparent");
+        draw_commands(
+            render_queue.transparent(),
+            &RenderState {
+                stencil,
+                ..RenderState::transparent()
+            },
+            &mut render_context,
+            &cube_shader,
+            &skinned_shader,
+            &mut world,
+        )?;
+        profiler.end("transparent");
+
+        // Debug views over the shaded scene
+        profiler.begin("debug views");
+        if debug_views.overdraw {
+            overdraw.draw(&mut render_context)?;
+        }
+        if debug_views.wireframe {
+            let wireframe = RenderState {
+                depth_test: Some(CompareFunc::LessEqual),
+                depth_write: false,
+                polygon_mode: PolygonMode::Line,
+                ..Default::default()
+            };
+            for program in [&cube_shader, &skinned_shader].iter() {
+                program.set_used();
+                program.set_int("debug_view", debug_view::WIREFRAME_VIEW)?;
+            }
+            for commands in [render_queue.opaque(), render_queue.transparent()].iter() {
+                draw_commands(
+                    commands,
+                    &wireframe,
+                    &mut render_context,
+                    &cube_shader,
+                    &skinned_shader,
+                    &mut world,
+                )?;
+            }
+        }
+        if debug_views.vertex_vectors {
+            render_context.apply(&RenderState::opaque());
+            vectors_shader.set_used();
+            vectors_shader.set_mat4("proj", &proj)?;
+            vectors_shader.set_mat4("view", &view)?;
+            vectors_shader.set_float("line_length", 0.1)?;
+            for commands in [render_queue.opaque(), render_queue.transparent()].iter() {
+                draw_vertex_vectors(commands, &vectors_shader, &mut world)?;
+            }
+        }
+        if debug_views.light_volumes {
+            for (pos, light) in lights.iter() {
+                let radius = debug_view::light_radius(light);
+                if radius.is_finite() {
+                    let color = glm::vec4(light.color.x, light.color.y, light.color.z, 1.0);
+                    debug_draw.sphere(pos, radius, &color);
+                }
+            }
+        }
+        if debug_views.bounds {
+            for (i, (pos, light)) in lights.iter().enumerate() {
+                debug_draw.axes(&glm::translation(pos), 0.5);
+                let color = glm::vec4(light.color.x, light.color.y, light.color.z, 1.0);
+                debug_draw.text(pos, &format!("light {}", i), &color);
+            }
+        }
+        // Labels are drawn with the rest of the text, if they're in front of the camera
+        let view_projection = proj * view;
+        for label in debug_draw.labels() {
+            let p = &label.position;
+            let clip = view_projection * glm::vec4(p.x, p.y, p.z, 1.0);
+            if clip.w > 0.0 {
+                let x = (clip.x / clip.w * 0.5 + 0.5) * window_width as f32;
+                let y = (0.5 - clip.y / clip.w * 0.5) * window_height as f32;
+                text.text(x, y, &label.text, &label.color);
+            }
+        }
+        debug_draw.draw(&mut render_context, &proj, &view)?;
+        profiler.end("debug views");
+
+        profiler.begin("overlay");
+        if show_stats {
+            let position = camera.position;
+            let capturing = if profiler.is_capturing() {
+                "\ncapturing profile (F9)"
+            } else {
+                ""
+            };
+            let stats = format!(
+                "{:.0} fps\ncamera: {:.1} {:.1} {:.1}\n{}\n{}{}",
+                fps,
+                position.x,
+                position.y,
+                position.z,
+                cull_stats,
+                profiler.report(),
+                capturing
+            );
+            text.text(10.0, 10.0, &stats, &glm::vec4(1.0, 1.0, 1.0, 1.0));
+            let (_, height) = text.measure(&stats);
+            profiler.draw_graph(&mut text, 10.0, 20.0 + height, 240.0, 60.0);
+        }
+        if debug_views.shading != ShadingView::Lit {
+            let label = format!("shading: {} (F1)", debug_views.shading);
+            let y = window_height as f32 - text.line_height() - 10.0;
+            text.text(10.0, y, &label, &glm::vec4(1.0, 1.0, 1.0, 1.0));
+        }
+        if show_ui {
+            let mouse = event_pump.mouse_state();
+            ui.begin_frame(mouse.x(), mouse.y(), mouse.left());
+            ui.begin_panel(
+                "Tweaks (Tab)",
+                window_width as f32 - 330.0,
+                10.0,
+                320.0,
+                &text,
+            );
+            tweak_panel(&mut ui, &mut camera, &mut world, &mut debug_views);
+            ui.end_panel(&mut text);
+        }
+        text.draw(&mut render_context, window_width, window_height)?;
+        profiler.end("overlay");
+        profiler.end_frame();
 
         window.gl_swap_window();
     }
@@ -331,6 +675,340 @@ fn run() -> Result<(), failure::Error> {
     Ok(())
 }
 
+/// Draws sorted commands, with runs that share program, material and mesh instanced
+fn draw_commands(
+    commands: &[DrawCommand],
+    state: &RenderState,
+    render_context: &mut RenderContext,
+    cube_shader: &Program,
+    skinned_shader: &Program,
+    world: &mut LoadedScene,
+) -> Result<(), failure::Error> {
+    for batch in render_queue::batches(commands) {
+        let first = &batch[0];
+        let program = match first.program {
+            ProgramId::Static => cube_shader,
+            ProgramId::Skinned => skinned_shader,
+        };
+        program.set_used();
+        let material = &world.materials[first.material];
+        if first.transparent {
+            render_context.apply(&RenderState {
+                depth_write: material.depth_write,
+                cull: material.cull,
+                blend: material.blend,
+                ..*state
+            });
+        } else {
+            render_context.apply(&RenderState {
+                cull: material.cull,
+                ..*state
+            });
+        }
+        material.apply(program, &world.textures)?;
+        let mesh = &mut world.meshes[first.mesh];
+        if first.instanceable {
+            let instances: Vec<Instance> = batch.iter().map(DrawCommand::instance).collect();
+            program.set_int("instanced", 1)?;
+            mesh.apply_morph_targets(program, &[])?;
+            mesh.draw_instanced(&instances);
+        } else {
+            match first.program {
+                ProgramId::Static => program.set_int("instanced", 0)?,
+                ProgramId::Skinned => {
+                    program.set_mat4_array("joint_matrices", &first.joint_matrices)?
+                }
+            }
+            program.set_mat4("model", &first.transform)?;
+            program.set_vec4("color", &first.color)?;
+            mesh.apply_morph_targets(program, &first.morph_weights)?;
+            mesh.draw();
+        }
+    }
+    Ok(())
+}
+
+/// Sections for editing the camera, debug views, lights and materials while running
+fn tweak_panel(
+    ui: &mut Ui,
+    camera: &mut Camera,
+    world: &mut LoadedScene,
+    debug_views: &mut DebugViews,
+) {
+    if ui.section("Camera") {
+        ui.slider("speed", &mut camera.movement_speed, 0.5, 30.0);
+        ui.slider("sensitivity", &mut camera.sensitivity, 0.0005, 0.01);
+    }
+    if ui.section("Debug views") {
+        if ui.button(&format!("shading: {}", debug_views.shading)) {
+            debug_views.shading = debug_views.shading.next();
+        }
+        ui.checkbox("wireframe", &mut debug_views.wireframe);
+        ui.checkbox("vertex vectors", &mut debug_views.vertex_vectors);
+        ui.checkbox("light volumes", &mut debug_views.light_volumes);
+        ui.checkbox("bounds", &mut debug_views.bounds);
+        ui.checkbox("overdraw", &mut debug_views.overdraw);
+        let label = if debug_views.frozen_frustum.is_some() {
+            "unfreeze culling"
+        } else {
+            "freeze culling"
+        };
+        if ui.button(label) {
+            debug_views
+                .toggle_frozen_frustum(camera.get_projection_matrix() * camera.get_view_matrix());
+        }
+    }
+
+    // Numbered like the labels of the bounds view
+    let light_nodes: Vec<NodeId> = world
+        .scene
+        .nodes()
+        .filter(|(_, node)| {
+            node.attachments.iter().any(|a| match a {
+                Attachment::Light(_) => true,
+                _ => false,
+            })
+        })
+        .map(|(id, _)| id)
+        .collect();
+    for (i, &id) in light_nodes.iter().enumerate() {
+        if !ui.section(&format!("Light {}", i)) {
+            continue;
+        }
+        let mut position = *world.scene.node(id).translation();
+        let mut moved = false;
+        moved |= ui.drag("x", &mut position.x, 0.05);
+        moved |= ui.drag("y", &mut position.y, 0.05);
+        moved |= ui.drag("z", &mut position.z, 0.05);
+        if moved {
+            world.scene.set_translation(id, position);
+        }
+        for attachment in world.scene.node_mut(id).attachments.iter_mut() {
+            if let Attachment::Light(light) = attachment {
+                ui.color("color", &mut light.color);
+                ui.slider("linear", &mut light.attn_linear, 0.0, 1.0);
+                ui.slider("quadratic", &mut light.attn_quadratic, 0.0, 2.0);
+            }
+        }
+    }
+
+    for (i, material) in world.materials.iter_mut().enumerate() {
+        if ui.section(&format!("Material {}", i)) {
+            ui.slider("shininess", &mut material.shininess, 1.0, 256.0);
+            if material.is_transparent() {
+                ui.slider("opacity", &mut material.opacity, 0.0, 1.0);
+            }
+            ui.checkbox("depth write", &mut material.depth_write);
+        }
+    }
+}
+
+/// Draws normal and tangent lines of the meshes. Skinned and morphed meshes show their rest pose
+fn draw_vertex_vectors(
+    commands: &[DrawCommand],
+    program: &Program,
+    world: &mut LoadedScene,
+) -> Result<(), failure::Error> {
+    for batch in render_queue::batches(commands) {
+        let first = &batch[0];
+        let mesh = &mut world.meshes[first.mesh];
+        if first.instanceable {
+            let instances: Vec<Instance> = batch.iter().map(DrawCommand::instance).collect();
+            program.set_int("instanced", 1)?;
+            mesh.draw_instanced(&instances);
+        } else {
+            program.set_int("instanced", 0)?;
+            program.set_mat4("model", &first.transform)?;
+            mesh.draw();
+        }
+    }
+    Ok(())
+}
+
+/// Sets the uniforms of the first NUM_POINT_LIGHTS lights. Positions are converted to view space
+fn set_point_lights(
+    program: &Program,
+    view: &glm::Mat4,
+    lights: &[(glm::Vec3, PointLight)],
+) -> Result<(), failure::Error> {
+    for (i, (pos, light)) in lights.iter().take(NUM_POINT_LIGHTS).enumerate() {
+        let light_pos = glm::vec4_to_vec3(&(view * glm::vec4(pos.x, pos.y, pos.z, 1.0)));
+        let color = light.color;
+        program.set_vec3(&format!("point_lights[{}].position", i), &light_pos)?;
+        program.set_vec3(&format!("point_lights[{}].ambient", i), &(0.2 * color))?;
+        program.set_vec3(&format!("point_lights[{}].diffuse", i), &(0.5 * color))?;
+        program.set_vec3(&format!("point_lights[{}].specular", i), &(1.0 * color))?;
+        program.set_float(
+            &format!("point_lights[{}].attn_linear", i),
+            light.attn_linear,
+        )?;
+        program.set_float(
+            &format!("point_lights[{}].attn_quadratic", i),
+            light.attn_quadratic,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the scene in its current pose to a .glb named after the scene file
+fn export_scene(world: &LoadedScene, scene_path: &str) -> Result<String, failure::Error> {
+    let name = std::path::Path::new(scene_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("scene");
+    std::fs::create_dir_all(EXPORT_DIR)?;
+    let path = format!("{}/{}.glb", EXPORT_DIR, name);
+    let export = SceneExport {
+        scene: &world.scene,
+        meshes: &world.mesh_data,
+        materials: &world.materials,
+        textures: &world.texture_sources,
+    };
+    gltf_export::export(&export, &path)?;
+    Ok(path)
+}
+
+/// Starts writing frame times to a CSV file, or finishes the file being written
+fn toggle_profile_capture(profiler: &mut Profiler) -> Result<(), failure::Error> {
+    if profiler.is_capturing() {
+        profiler.stop_capture()?;
+        println!("Stopped profile capture");
+        return Ok(());
+    }
+    std::fs::create_dir_all(EXPORT_DIR)?;
+    let timestamp = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let path = format!("{}/profile-{}.csv", EXPORT_DIR, timestamp);
+    profiler.start_capture(&path)?;
+    println!("Capturing profile to {}", path);
+    Ok(())
+}
+
+/// Reads a scene file and loads everything it refers to while showing the loading screen.
+/// Returns None if the window gets closed in the meantime
+fn load_scene(
+    path: &str,
+    window: &sdl2::video::Window,
+    event_pump: &mut sdl2::EventPump,
+    loading_screen: &LoadingScreen,
+    render_context: &mut RenderContext,
+) -> Result<Option<LoadedScene>, failure::Error> {
+    let description = SceneDescription::load(path)?;
+
+    // Decode assets in the background and upload them as they arrive
+    let start = Instant::now();
+    let mut loader = AssetLoader::new(4);
+    for path in description.texture_paths() {
+        loader.load_image(path);
+    }
+    for path in description.model_paths() {
+        loader.load_model(path);
+    }
+    // Procedural meshes are simplified right away, models once they're decoded
+    for (name, mesh) in description.meshes.iter() {
+        let ratios = description.simplified_lod_ratios(name);
+        if !ratios.is_empty() {
+            loader.simplify(name, 0, mesh.generate(), ratios);
+        }
+    }
+
+    let mut textures = HashMap::new();
+    let mut models = HashMap::new();
+    let mut simplified = HashMap::new();
+    let simplify_model = |loader: &mut AssetLoader, path: &str, model: &ModelData| {
+        for name in description.model_names(path) {
+            let ratios = description.simplified_lod_ratios(name);
+            if ratios.is_empty() {
+                continue;
+            }
+            for (i, primitive) in model.primitives.iter().enumerate() {
+                loader.simplify(name, i, primitive.mesh.clone(), ratios.clone());
+            }
+        }
+    };
+    // Models whose meshes and images are still being sent to GPU
+    let mut uploads: VecDeque<(String, ModelUpload)> = VecDeque::new();
+    while !loader.is_done() || !uploads.is_empty() {
+        for event in event_pump.poll_iter() {
+            if let sdl2::event::Event::Quit { .. } = event {
+                return Ok(None);
+            }
+        }
+
+        // Only one upload per frame so that the screen stays responsive.
+        // Assets that fail are reported and replaced so the rest of the scene still loads
+        if let Some((path, mut upload)) = uploads.pop_front() {
+            upload.step();
+            if upload.remaining() > 0 {
+                uploads.push_front((path, upload));
+            } else {
+                models.insert(path, upload.finish());
+            }
+        } else if let Some(asset) = loader.poll() {
+            match asset {
+                LoadedAsset::Image { path, image } => {
+                    let texture = Texture::new().set_default_parameters().set_image(&image);
+                    textures.insert(path, texture);
+                }
+                LoadedAsset::CompressedImage { path, image } => {
+                    let texture = match Texture::new().set_default_parameters().set_dds(&image) {
+                        Ok(texture) => texture,
+                        Err(e) => {
+                            eprintln!("{}: {}", path, error_into_string(e.into()));
+                            Texture::solid_color(MISSING_TEXTURE)
+                        }
+                    };
+                    textures.insert(path, texture);
+                }
+                LoadedAsset::Model { path, model } => {
+                    for warning in model.warnings.iter() {
+                        eprintln!("{}: warning: {}", path, warning);
+                    }
+                    simplify_model(&mut loader, &path, &model);
+                    uploads.push_back((path, ModelUpload::new(model)));
+                }
+                LoadedAsset::Simplified {
+                    name,
+                    index,
+                    levels,
+                } => {
+                    simplified.insert((name, index), levels);
+                }
+                LoadedAsset::FailedImage { path, error } => {
+                    eprintln!("{}: {}", path, error_into_string(error));
+                    textures.insert(path, Texture::solid_color(MISSING_TEXTURE));
+                }
+                LoadedAsset::FailedSimplify {
+                    name,
+                    index,
+                    levels,
+                    error,
+                } => {
+                    eprintln!("{}: {}", name, error_into_string(error));
+                    simplified.insert((name, index), levels);
+                }
+                LoadedAsset::FailedModel { path, error } => {
+                    eprintln!("{}: {}", path, error_into_string(error));
+                    let placeholder = ModelData::placeholder();
+                    simplify_model(&mut loader, &path, &placeholder);
+                    uploads.push_back((path, ModelUpload::new(placeholder)));
+                }
+            }
+        }
+
+        // Decoded models count as loaded only once they're on GPU
+        let uploading: f32 = uploads.iter().map(|(_, u)| 1.0 - u.progress()).sum();
+        let requested = loader.requested().max(1) as f32;
+        let progress = loader.progress() - uploading / requested;
+        loading_screen.draw(render_context, progress.max(0.0))?;
+        window.gl_swap_window();
+    }
+    println!("Assets loaded in {:.2?}", start.elapsed());
+
+    Ok(Some(description.build(textures, models, simplified)?))
+}
+
 fn error_into_string(err: failure::Error) -> String {
     let mut pretty = err.to_string();
     let mut prev = err.as_fail();
@@ -0,0 +1,64 @@
+use serde::Deserialize;
+
+use crate::render_state::{BlendMode, CullMode};
+use crate::shader::{self, Program};
+use crate::texture::Texture;
+
+/// How the alpha of the diffuse texture, the opacity and the node's color is used
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    Opaque,
+    /// Fragments with less alpha than the cutoff are discarded, the rest are opaque
+    Mask(f32),
+    /// Blended over what's behind, after everything opaque is drawn
+    Blend,
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self {
+        AlphaMode::Opaque
+    }
+}
+
+/// Values of `material.alpha_mode` in the lighting shader
+const ALPHA_OPAQUE: i32 = 0;
+const ALPHA_MASK: i32 = 1;
+const ALPHA_BLEND: i32 = 2;
+
+/// Textures are indices into the list of loaded textures
+pub struct Material {
+    pub diffuse: usize,
+    pub specular: usize,
+    pub shininess: f32,
+    pub alpha_mode: AlphaMode,
+    /// Multiplies the alpha of the diffuse texture
+    pub opacity: f32,
+    /// Whether transparent draws still write depth, hiding transparent objects drawn later
+    pub depth_write: bool,
+    /// None for surfaces seen from both sides
+    pub cull: CullMode,
+    /// Only used when drawn transparent
+    pub blend: BlendMode,
+}
+
+impl Material {
+    pub fn is_transparent(&self) -> bool {
+        self.alpha_mode == AlphaMode::Blend
+    }
+
+    /// Binds textures and sets uniforms expected by the lighting shader
+    pub fn apply(&self, program: &Program, textures: &[Texture]) -> shader::Result<()> {
+        textures[self.diffuse].bind(0);
+        textures[self.specular].bind(1);
+        let (alpha_mode, alpha_cutoff) = match self.alpha_mode {
+            AlphaMode::Opaque => (ALPHA_OPAQUE, 0.0),
+            AlphaMode::Mask(cutoff) => (ALPHA_MASK, cutoff),
+            AlphaMode::Blend => (ALPHA_BLEND, 0.0),
+        };
+        program.set_int("material.alpha_mode", alpha_mode)?;
+        program.set_float("material.alpha_cutoff", alpha_cutoff)?;
+        program.set_float("material.opacity", self.opacity)?;
+        program.set_float("material.shininess", self.shininess)
+    }
+}
@@ -0,0 +1,320 @@
+use glm::{Mat4, Vec4};
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::buffers::{ElementBuffer, TextureBuffer, VertexArray, VertexBuffer};
+use crate::shader::{self, Program};
+
+/// Vertex layout of every mesh: position, texture coords, normal
+pub const STRIDE: usize = 8;
+
+/// Skinned meshes also have 4 joint indices and 4 joint weights per vertex
+pub const SKINNED_STRIDE: usize = 16;
+
+/// Meshes with tangents have them last, as xyz and handedness
+pub const TANGENT_SIZE: usize = 4;
+
+/// Has to match the cube and skinned shaders
+pub const MAX_MORPH_TARGETS: usize = 8;
+
+/// Texture unit the morph target buffer is bound to
+pub const MORPH_TARGETS_UNIT: i32 = 2;
+
+/// Instance layout: model matrix, color
+pub const INSTANCE_STRIDE: usize = 20;
+
+/// Per-instance data of instanced draws
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub model: Mat4,
+    pub color: Vec4,
+}
+
+/// Per-vertex displacements of a blend shape. Missing attributes are empty
+#[derive(Default, Clone)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
+}
+
+/// Mesh geometry on the CPU side
+#[derive(Default, Clone)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    /// Texture coords have their origin at the top left as in glTF, which models use,
+    /// rather than at the bottom left as in GL, which generated meshes use
+    pub tex_coords_top_left: bool,
+    /// Tangent and the sign of the bitangent, which is cross(normal, tangent) * w
+    pub tangents: Vec<[f32; 4]>,
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub morph_targets: Vec<MorphTarget>,
+    /// Weights used when the node doesn't override them
+    pub morph_weights: Vec<f32>,
+}
+
+impl MeshData {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_skinned(&self) -> bool {
+        !self.joints.is_empty()
+    }
+
+    pub fn has_tangents(&self) -> bool {
+        !self.tangents.is_empty()
+    }
+
+    pub fn stride(&self) -> usize {
+        let stride = if self.is_skinned() {
+            SKINNED_STRIDE
+        } else {
+            STRIDE
+        };
+        if self.has_tangents() {
+            stride + TANGENT_SIZE
+        } else {
+            stride
+        }
+    }
+
+    /// Interleaves attributes into a single vertex array.
+    /// Missing attributes are filled with zeros
+    pub fn interleave(&self) -> Vec<f32> {
+        let mut vertices = Vec::with_capacity(self.positions.len() * self.stride());
+        for (i, position) in self.positions.iter().enumerate() {
+            let tex_coord = self.tex_coords.get(i).unwrap_or(&[0.0, 0.0]);
+            let normal = self.normals.get(i).unwrap_or(&[0.0, 0.0, 0.0]);
+            vertices.extend_from_slice(position);
+            vertices.extend_from_slice(tex_coord);
+            vertices.extend_from_slice(normal);
+            if self.is_skinned() {
+                let joints = self.joints.get(i).unwrap_or(&[0, 0, 0, 0]);
+                let weights = self.weights.get(i).unwrap_or(&[0.0, 0.0, 0.0, 0.0]);
+                vertices.extend(joints.iter().map(|&joint| joint as f32));
+                vertices.extend_from_slice(weights);
+            }
+            if self.has_tangents() {
+                let tangent = self.tangents.get(i).unwrap_or(&[1.0, 0.0, 0.0, 1.0]);
+                vertices.extend_from_slice(tangent);
+            }
+        }
+        vertices
+    }
+
+    /// Displacements of the morph targets the shaders take as RGB texels: position,
+    /// normal and tangent of the first vertex, then of the second one and so on
+    pub fn morph_texels(&self) -> Vec<f32> {
+        let zero = [0.0, 0.0, 0.0];
+        let targets = self.morph_targets.iter().take(MAX_MORPH_TARGETS);
+        let mut texels = Vec::with_capacity(targets.len() * self.positions.len() * 9);
+        for target in targets {
+            for i in 0..self.positions.len() {
+                texels.extend_from_slice(target.positions.get(i).unwrap_or(&zero));
+                texels.extend_from_slice(target.normals.get(i).unwrap_or(&zero));
+                texels.extend_from_slice(target.tangents.get(i).unwrap_or(&zero));
+            }
+        }
+        texels
+    }
+}
+
+/// Mesh geometry uploaded to GPU
+pub struct Mesh {
+    vao: VertexArray,
+    vbo: VertexBuffer,
+    ebo: Option<ElementBuffer>,
+    instances: VertexBuffer,
+    /// What the instance buffer holds, so that draws of the same instances don't upload
+    /// them again and moved instances only upload what changed
+    instance_data: Vec<f32>,
+    morph_targets: Option<TextureBuffer>,
+    num_morph_targets: usize,
+    num_vertices: usize,
+    morph_weights: Vec<f32>,
+    /// Bounds of the undeformed mesh
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl Mesh {
+    pub fn new(data: &MeshData) -> Self {
+        let vao = VertexArray::new();
+        vao.bind();
+
+        let stride = data.stride();
+        let mut vbo = VertexBuffer::new();
+        vbo.bind();
+        vbo.set_static_data(&data.interleave(), stride);
+        vao.set_attrib(0, 3, stride, 0); // Positions
+        vao.set_attrib(1, 2, stride, 3); // Texture coords
+        vao.set_attrib(2, 3, stride, 5); // Normals
+        if data.is_skinned() {
+            vao.set_attrib(3, 4, stride, 8); // Joints
+            vao.set_attrib(4, 4, stride, 12); // Weights
+        }
+        if data.has_tangents() {
+            vao.set_attrib(5, 4, stride, stride - TANGENT_SIZE); // Tangents
+        }
+
+        // Model matrix as 4 columns, then color
+        let instances = VertexBuffer::new();
+        instances.bind();
+        for column in 0..4 {
+            vao.set_instance_attrib(6 + column as u32, 4, INSTANCE_STRIDE, column * 4);
+        }
+        vao.set_instance_attrib(10, 4, INSTANCE_STRIDE, 16);
+
+        let ebo = if data.indices.is_empty() {
+            None
+        } else {
+            let mut ebo = ElementBuffer::new();
+            ebo.bind();
+            ebo.set_static_data(&data.indices, 1);
+            Some(ebo)
+        };
+
+        vao.unbind();
+        vbo.unbind();
+
+        let morph_targets = if data.morph_targets.is_empty() {
+            None
+        } else {
+            Some(TextureBuffer::new(&data.morph_texels(), 3))
+        };
+
+        Mesh {
+            vao,
+            vbo,
+            ebo,
+            instances,
+            instance_data: Vec::new(),
+            morph_targets,
+            num_morph_targets: data.morph_targets.len().min(MAX_MORPH_TARGETS),
+            num_vertices: data.positions.len(),
+            morph_weights: data.morph_weights.clone(),
+            aabb: data.aabb(),
+            bounding_sphere: data.bounding_sphere(),
+        }
+    }
+
+    /// Binds morph targets and sets their weights. Empty weights mean the mesh's defaults.
+    /// Has to be called for meshes without morph targets too, to switch morphing off
+    pub fn apply_morph_targets(&self, program: &Program, weights: &[f32]) -> shader::Result<()> {
+        let morph_targets = match &self.morph_targets {
+            Some(morph_targets) => morph_targets,
+            None => return program.set_int("morph_target_count", 0),
+        };
+        let weights = if weights.is_empty() {
+            &self.morph_weights[..]
+        } else {
+            weights
+        };
+        let mut padded = [0.0; MAX_MORPH_TARGETS];
+        for (w, weight) in padded.iter_mut().zip(weights.iter()) {
+            *w = *weight;
+        }
+
+        morph_targets.bind(MORPH_TARGETS_UNIT);
+        program.set_int("morph_target_count", self.num_morph_targets as i32)?;
+        program.set_int("morph_vertex_count", self.num_vertices as i32)?;
+        program.set_float_array("morph_weights", &padded)
+    }
+
+    pub fn has_morph_targets(&self) -> bool {
+        self.num_morph_targets > 0
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        match &self.ebo {
+            Some(ebo) => ebo.num_elements() / 3,
+            None => self.vbo.num_vertices() / 3,
+        }
+    }
+
+    pub fn draw(&self) {
+        self.vao.bind();
+        match &self.ebo {
+            Some(ebo) => ebo.draw_triangles(),
+            None => self.vbo.draw_triangles(),
+        }
+    }
+
+    /// Draws the mesh once for every instance with a single draw call.
+    /// Needs a program that reads the instance attributes
+    pub fn draw_instanced(&mut self, instances: &[Instance]) {
+        let mut data = Vec::with_capacity(instances.len() * INSTANCE_STRIDE);
+        for instance in instances.iter() {
+            data.extend_from_slice(instance.model.as_slice());
+            data.extend_from_slice(instance.color.as_slice());
+        }
+        self.instances.bind();
+        if data.len() != self.instance_data.len() {
+            self.instances.set_dynamic_data(&data, INSTANCE_STRIDE);
+        } else if let Some((first, last)) = changed_range(&self.instance_data, &data) {
+            self.instances.update(first, &data[first..=last]);
+        }
+        self.instance_data = data;
+
+        self.vao.bind();
+        match &self.ebo {
+            Some(ebo) => ebo.draw_triangles_instanced(instances.len()),
+            None => self.vbo.draw_triangles_instanced(instances.len()),
+        }
+    }
+}
+
+/// First and last index where the slices of the same length differ
+fn changed_range(old: &[f32], new: &[f32]) -> Option<(usize, usize)> {
+    let differs = |i: &usize| old[*i] != new[*i];
+    let first = (0..new.len()).find(differs)?;
+    let last = (first..new.len()).rev().find(differs)?;
+    Some((first, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_range_spans_every_difference() {
+        let old = [0.0, 1.0, 2.0, 3.0, 4.0];
+        assert_eq!(changed_range(&old, &old), None);
+        assert_eq!(
+            changed_range(&old, &[0.0, 1.0, 5.0, 3.0, 4.0]),
+            Some((2, 2))
+        );
+        assert_eq!(
+            changed_range(&old, &[9.0, 1.0, 2.0, 3.0, 9.0]),
+            Some((0, 4))
+        );
+        assert_eq!(changed_range(&[], &[]), None);
+    }
+
+    #[test]
+    fn morph_texels_interleave_every_vertex() {
+        let mut data = MeshData::new();
+        data.positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]];
+        data.morph_targets = vec![MorphTarget {
+            positions: vec![[1.0, 1.0, 1.0], [2.0, 2.0, 2.0]],
+            normals: Vec::new(),
+            tangents: vec![[3.0, 3.0, 3.0], [4.0, 4.0, 4.0]],
+        }];
+        #[rustfmt::skip]
+        let expected = vec![
+            1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 3.0, 3.0, 3.0,
+            2.0, 2.0, 2.0, 0.0, 0.0, 0.0, 4.0, 4.0, 4.0,
+        ];
+        assert_eq!(data.morph_texels(), expected);
+
+        data.morph_targets = vec![MorphTarget::default(); MAX_MORPH_TARGETS + 1];
+        assert_eq!(
+            data.morph_texels().len(),
+            MAX_MORPH_TARGETS * data.positions.len() * 9
+        );
+    }
+}
@@ -0,0 +1,572 @@
+//! CPU-side mesh processing: normals, tangents, welding, vertex cache
+//! optimisation, bounds and transform baking. None of it needs a GL context
+
+use std::collections::HashMap;
+
+use glm::Mat4;
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::mesh::{MeshData, MorphTarget};
+
+/// Size of the simulated cache when ordering triangles. Bigger than most
+/// post-transform caches, which is fine as the ordering degrades gracefully
+const CACHE_SIZE: usize = 32;
+
+impl MeshData {
+    /// Vertex indices of every triangle, whether the mesh is indexed or not
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        if self.indices.is_empty() {
+            (0..self.positions.len() / 3)
+                .map(|t| [t * 3, t * 3 + 1, t * 3 + 2])
+                .collect()
+        } else {
+            self.indices
+                .chunks(3)
+                .filter(|t| t.len() == 3)
+                .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+                .collect()
+        }
+    }
+
+    /// Computes tangents from texture coords. Needs normals
+    pub fn generate_tangents(&mut self) {
+        let count = self.positions.len();
+        let mut tangents = vec![glm::vec3(0.0, 0.0, 0.0); count];
+        let mut bitangents = vec![glm::vec3(0.0, 0.0, 0.0); count];
+        let uv = |i: usize| self.tex_coords.get(i).cloned().unwrap_or([0.0, 0.0]);
+        for triangle in self.triangles() {
+            let [a, b, c] = triangle;
+            let p = |i: usize| glm::make_vec3(&self.positions[i]);
+            let (e1, e2) = (p(b) - p(a), p(c) - p(a));
+            let (du1, dv1) = (uv(b)[0] - uv(a)[0], uv(b)[1] - uv(a)[1]);
+            let (du2, dv2) = (uv(c)[0] - uv(a)[0], uv(c)[1] - uv(a)[1]);
+            let r = du1 * dv2 - du2 * dv1;
+            if r.abs() < std::f32::EPSILON {
+                continue;
+            }
+            let tangent = (e1 * dv2 - e2 * dv1) / r;
+            let bitangent = (e2 * du1 - e1 * du2) / r;
+            for &i in triangle.iter() {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        let normals = &self.normals;
+        self.tangents = (0..count)
+            .map(|i| {
+                let normal = glm::make_vec3(normals.get(i).unwrap_or(&[0.0, 1.0, 0.0]));
+                // Gram-Schmidt, falling back to any perpendicular for degenerate texture coords
+                let mut tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+                if tangent.norm() < 1e-6 {
+                    let axis = if normal.x.abs() < 0.9 {
+                        glm::vec3(1.0, 0.0, 0.0)
+                    } else {
+                        glm::vec3(0.0, 1.0, 0.0)
+                    };
+                    tangent = axis - normal * normal.dot(&axis);
+                }
+                let tangent = tangent.normalize();
+                let w = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                [tangent.x, tangent.y, tangent.z, w]
+            })
+            .collect();
+    }
+
+    /// Smooth normals averaged over the faces around each vertex, weighted by
+    /// their area. Vertices in the same place share their normal so that seams
+    /// in texture coords don't show. Vertices without faces get a zero normal
+    pub fn smooth_normals(&self) -> Vec<[f32; 3]> {
+        let mut group_by_position = HashMap::new();
+        let groups: Vec<usize> = self
+            .positions
+            .iter()
+            .map(|p| {
+                let key = [
+                    (p[0] + 0.0).to_bits(),
+                    (p[1] + 0.0).to_bits(),
+                    (p[2] + 0.0).to_bits(),
+                ];
+                let next = group_by_position.len();
+                *group_by_position.entry(key).or_insert(next)
+            })
+            .collect();
+        let mut sums = vec![glm::vec3(0.0, 0.0, 0.0); group_by_position.len()];
+        for triangle in self.triangles() {
+            // Not normalized, so that bigger faces weigh more
+            let face_normal = self.face_normal(&triangle);
+            for &i in triangle.iter() {
+                sums[groups[i]] += face_normal;
+            }
+        }
+        groups
+            .iter()
+            .map(|&group| {
+                let sum = sums[group];
+                if sum.norm() > 0.0 {
+                    let n = sum.normalize();
+                    [n.x, n.y, n.z]
+                } else {
+                    [0.0, 0.0, 0.0]
+                }
+            })
+            .collect()
+    }
+
+    pub fn generate_smooth_normals(&mut self) {
+        self.normals = self.smooth_normals();
+    }
+
+    /// Gives every triangle its own vertices with the face's normal
+    pub fn generate_flat_normals(&mut self) {
+        let triangles = self.triangles();
+        let vertices: Vec<usize> = triangles.iter().flat_map(|t| t.to_vec()).collect();
+        let mut flat = self.select_vertices(&vertices);
+        flat.normals = triangles
+            .iter()
+            .flat_map(|triangle| {
+                let n = self.face_normal(triangle);
+                let n = if n.norm() > 0.0 { n.normalize() } else { n };
+                vec![[n.x, n.y, n.z]; 3]
+            })
+            .collect();
+        flat.indices = (0..vertices.len() as u32).collect();
+        *self = flat;
+    }
+
+    /// Cross product of two edges: perpendicular to the triangle,
+    /// with a length of twice its area
+    fn face_normal(&self, triangle: &[usize; 3]) -> glm::Vec3 {
+        let p = |i: usize| glm::make_vec3(&self.positions[triangle[i]]);
+        (p(1) - p(0)).cross(&(p(2) - p(0)))
+    }
+
+    /// Merges vertices whose attributes are all within `epsilon` of each other,
+    /// or exactly equal for an epsilon of 0. Drops triangles that collapse
+    pub fn weld(&mut self, epsilon: f32) {
+        let quantize = |value: f32| {
+            if epsilon > 0.0 {
+                (value / epsilon).round() as i64
+            } else {
+                // Adding zero turns -0 into 0
+                i64::from((value + 0.0).to_bits())
+            }
+        };
+        let mut vertex_by_key: HashMap<Vec<i64>, usize> = HashMap::new();
+        let mut kept = Vec::new();
+        let remap: Vec<usize> = (0..self.positions.len())
+            .map(|i| {
+                let key: Vec<i64> = self.attributes(i).into_iter().map(quantize).collect();
+                *vertex_by_key.entry(key).or_insert_with(|| {
+                    kept.push(i);
+                    kept.len() - 1
+                })
+            })
+            .collect();
+
+        let mut welded = self.select_vertices(&kept);
+        welded.indices = self
+            .triangles()
+            .iter()
+            .map(|t| [remap[t[0]], remap[t[1]], remap[t[2]]])
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .flat_map(|t| t.iter().map(|&i| i as u32).collect::<Vec<_>>())
+            .collect();
+        *self = welded;
+    }
+
+    /// Every attribute of a vertex as floats
+    fn attributes(&self, i: usize) -> Vec<f32> {
+        let mut values = Vec::new();
+        values.extend_from_slice(&self.positions[i]);
+        values.extend(self.normals.get(i).iter().flat_map(|v| v.to_vec()));
+        values.extend(self.tex_coords.get(i).iter().flat_map(|v| v.to_vec()));
+        values.extend(self.tangents.get(i).iter().flat_map(|v| v.to_vec()));
+        values.extend(
+            self.joints
+                .get(i)
+                .iter()
+                .flat_map(|v| v.iter().map(|&j| j as f32)),
+        );
+        values.extend(self.weights.get(i).iter().flat_map(|v| v.to_vec()));
+        for target in self.morph_targets.iter() {
+            values.extend(target.positions.get(i).iter().flat_map(|v| v.to_vec()));
+            values.extend(target.normals.get(i).iter().flat_map(|v| v.to_vec()));
+            values.extend(target.tangents.get(i).iter().flat_map(|v| v.to_vec()));
+        }
+        values
+    }
+
+    /// Reorders triangles so that vertices are reused while they're still in
+    /// the post-transform cache (Forsyth's algorithm), then reorders vertices
+    /// in the order they're first used so fetching them is sequential
+    pub fn optimize_vertex_cache(&mut self) {
+        let triangles = self.triangles();
+        let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); self.positions.len()];
+        for (t, triangle) in triangles.iter().enumerate() {
+            for &v in triangle.iter() {
+                adjacency[v].push(t);
+            }
+        }
+        let mut scores: Vec<f32> = adjacency
+            .iter()
+            .map(|triangles| vertex_score(None, triangles.len()))
+            .collect();
+
+        let mut added = vec![false; triangles.len()];
+        let mut order = Vec::with_capacity(triangles.len());
+        let mut cache: Vec<usize> = Vec::with_capacity(CACHE_SIZE + 3);
+        let mut next_unadded = 0;
+        let mut best = None;
+        while order.len() < triangles.len() {
+            let t = match best {
+                Some(t) => t,
+                // Nothing left around the cached vertices, start somewhere else
+                None => {
+                    while added[next_unadded] {
+                        next_unadded += 1;
+                    }
+                    next_unadded
+                }
+            };
+            added[t] = true;
+            order.push(t);
+            for &v in triangles[t].iter() {
+                adjacency[v].retain(|&other| other != t);
+                cache.retain(|&cached| cached != v);
+                cache.insert(0, v);
+            }
+            for &v in cache.iter().skip(CACHE_SIZE) {
+                scores[v] = vertex_score(None, adjacency[v].len());
+            }
+            cache.truncate(CACHE_SIZE);
+            for (position, &v) in cache.iter().enumerate() {
+                scores[v] = vertex_score(Some(position), adjacency[v].len());
+            }
+
+            best = None;
+            let mut best_score = std::f32::MIN;
+            for &v in cache.iter() {
+                for &candidate in adjacency[v].iter() {
+                    let score: f32 = triangles[candidate].iter().map(|&v| scores[v]).sum();
+                    if score > best_score {
+                        best = Some(candidate);
+                        best_score = score;
+                    }
+                }
+            }
+        }
+
+        let mut new_index = vec![None; self.positions.len()];
+        let mut vertices = Vec::with_capacity(self.positions.len());
+        let mut indices = Vec::with_capacity(order.len() * 3);
+        for &t in order.iter() {
+            for &v in triangles[t].iter() {
+                let index = *new_index[v].get_or_insert_with(|| {
+                    vertices.push(v);
+                    vertices.len() - 1
+                });
+                indices.push(index as u32);
+            }
+        }
+        let mut optimized = self.select_vertices(&vertices);
+        optimized.indices = indices;
+        *self = optimized;
+    }
+
+    /// Average number of vertices transformed per triangle with a FIFO cache of the
+    /// given size. 3 is the worst, around 0.5 is the best possible for big grids
+    #[cfg(test)]
+    pub fn average_cache_miss_ratio(&self, cache_size: usize) -> f32 {
+        let triangles = self.triangles();
+        if triangles.is_empty() {
+            return 0.0;
+        }
+        let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+        let mut misses = 0;
+        for &v in triangles.iter().flat_map(|t| t.iter()) {
+            if !cache.contains(&v) {
+                misses += 1;
+                if cache.len() == cache_size {
+                    cache.pop_front();
+                }
+                cache.push_back(v);
+            }
+        }
+        misses as f32 / triangles.len() as f32
+    }
+
+    /// New mesh made of the given vertices, in that order, without indices
+    pub fn select_vertices(&self, vertices: &[usize]) -> MeshData {
+        fn pick<T: Copy>(values: &[T], vertices: &[usize]) -> Vec<T> {
+            if values.is_empty() {
+                Vec::new()
+            } else {
+                vertices.iter().map(|&v| values[v]).collect()
+            }
+        }
+        MeshData {
+            positions: pick(&self.positions, vertices),
+            normals: pick(&self.normals, vertices),
+            tex_coords: pick(&self.tex_coords, vertices),
+            tex_coords_top_left: self.tex_coords_top_left,
+            tangents: pick(&self.tangents, vertices),
+            joints: pick(&self.joints, vertices),
+            weights: pick(&self.weights, vertices),
+            indices: Vec::new(),
+            morph_targets: self
+                .morph_targets
+                .iter()
+                .map(|target| MorphTarget {
+                    positions: pick(&target.positions, vertices),
+                    normals: pick(&target.normals, vertices),
+                    tangents: pick(&target.tangents, vertices),
+                })
+                .collect(),
+            morph_weights: self.morph_weights.clone(),
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.positions.iter())
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(&self.positions)
+    }
+
+    /// Applies the transform to the vertices, e.g. to merge meshes of different
+    /// nodes. Mirroring transforms flip the winding back to counter-clockwise
+    pub fn bake_transform(&mut self, transform: &Mat4) {
+        let linear = glm::mat4_to_mat3(transform);
+        let normal_matrix = linear
+            .try_inverse()
+            .map_or(linear, |inverse| inverse.transpose());
+        let mirrored = linear.determinant() < 0.0;
+        let point = |p: &[f32; 3]| {
+            let p = transform * glm::vec4(p[0], p[1], p[2], 1.0);
+            [p.x, p.y, p.z]
+        };
+        let vector = |matrix: &glm::Mat3, v: &[f32; 3]| {
+            let v = matrix * glm::make_vec3(v);
+            [v.x, v.y, v.z]
+        };
+        let direction = |matrix: &glm::Mat3, v: &[f32; 3]| {
+            let v = matrix * glm::make_vec3(v);
+            let v = if v.norm() > 0.0 { v.normalize() } else { v };
+            [v.x, v.y, v.z]
+        };
+
+        for p in self.positions.iter_mut() {
+            *p = point(p);
+        }
+        for n in self.normals.iter_mut() {
+            *n = direction(&normal_matrix, n);
+        }
+        for t in self.tangents.iter_mut() {
+            let [x, y, z] = direction(&linear, &[t[0], t[1], t[2]]);
+            let w = if mirrored { -t[3] } else { t[3] };
+            *t = [x, y, z, w];
+        }
+        // Morph targets are displacements, unaffected by translation
+        for target in self.morph_targets.iter_mut() {
+            for p in target.positions.iter_mut() {
+                *p = vector(&linear, p);
+            }
+            for n in target.normals.iter_mut() {
+                *n = vector(&normal_matrix, n);
+            }
+            for t in target.tangents.iter_mut() {
+                *t = vector(&linear, t);
+            }
+        }
+
+        if mirrored {
+            if self.indices.is_empty() {
+                self.indices = (0..self.positions.len() as u32).collect();
+            }
+            for triangle in self.indices.chunks_mut(3) {
+                if triangle.len() == 3 {
+                    triangle.swap(1, 2);
+                }
+            }
+        }
+    }
+}
+
+/// Forsyth's score: vertices just used or with few triangles left are preferred
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        // The last triangle's vertices get a fixed score so that strips aren't favoured
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+        None => 0.0,
+    };
+    cache_score + 2.0 * (remaining_triangles as f32).powf(-0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes;
+
+    /// Positions of every triangle, in a canonical order so meshes can be compared
+    fn triangle_positions(mesh: &MeshData) -> Vec<[[u32; 3]; 3]> {
+        let bits = |p: [f32; 3]| [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()];
+        let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+            .triangles()
+            .iter()
+            .map(|t| {
+                let corners = [
+                    bits(mesh.positions[t[0]]),
+                    bits(mesh.positions[t[1]]),
+                    bits(mesh.positions[t[2]]),
+                ];
+                // Rotate the smallest corner first, keeping the winding
+                let first = (0..3).min_by_key(|&i| corners[i]).unwrap();
+                [
+                    corners[first],
+                    corners[(first + 1) % 3],
+                    corners[(first + 2) % 3],
+                ]
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    fn quad_as_triangle_soup() -> MeshData {
+        let mut mesh = MeshData::new();
+        for &p in [0, 1, 2, 0, 2, 3].iter() {
+            let corner = [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ][p];
+            mesh.positions.push(corner);
+            mesh.normals.push([0.0, 0.0, 1.0]);
+        }
+        mesh
+    }
+
+    #[test]
+    fn weld_merges_equal_vertices() {
+        let mut mesh = quad_as_triangle_soup();
+        mesh.weld(0.0);
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn weld_merges_close_vertices_and_drops_collapsed_triangles() {
+        let mut mesh = quad_as_triangle_soup();
+        mesh.positions[3] = [0.0001, 0.0, 0.0];
+        mesh.positions[5] = [0.0, 1.0001, 0.0];
+        mesh.weld(0.0);
+        // Only the copies of [1, 1, 0] are still equal
+        assert_eq!(mesh.positions.len(), 5);
+
+        let mut mesh = quad_as_triangle_soup();
+        mesh.positions[3] = [0.0001, 0.0, 0.0];
+        mesh.positions[4] = [0.0002, 0.0, 0.0];
+        mesh.positions[5] = [0.0, 1.0001, 0.0];
+        mesh.weld(0.001);
+        // The second triangle collapses as two of its corners merge with the first one
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn weld_keeps_vertices_that_differ_in_other_attributes() {
+        let mut mesh = quad_as_triangle_soup();
+        mesh.normals[3] = [0.0, 0.0, -1.0];
+        mesh.weld(0.0);
+        assert_eq!(mesh.positions.len(), 5);
+    }
+
+    #[test]
+    fn vertex_cache_optimization_keeps_triangles_and_misses_less() {
+        let mut grid = shapes::grid(1.0, 1.0, 40, 40);
+        let before = grid.average_cache_miss_ratio(16);
+        let triangles = triangle_positions(&grid);
+        grid.optimize_vertex_cache();
+        assert_eq!(triangle_positions(&grid), triangles);
+        assert_eq!(grid.positions.len(), 41 * 41);
+        assert!(grid.average_cache_miss_ratio(16) < before * 0.8);
+    }
+
+    #[test]
+    fn cube_flat_normals_face_out_of_their_sides() {
+        let mut cube = shapes::cube(2.0);
+        cube.generate_flat_normals();
+        assert_eq!(cube.positions.len(), 36);
+        for triangle in cube.triangles() {
+            let normal = glm::make_vec3(&cube.normals[triangle[0]]);
+            // One axis, the one the whole side sits on
+            let axis = (0..3).find(|&k| normal[k].abs() > 0.5).unwrap();
+            assert!((normal.norm() - 1.0).abs() < 1e-6);
+            for &v in triangle.iter() {
+                assert_eq!(cube.normals[v], cube.normals[triangle[0]]);
+                assert_eq!(cube.positions[v][axis], normal[axis]);
+            }
+        }
+    }
+
+    #[test]
+    fn cube_smooth_normals_point_out_of_the_corners() {
+        let mut cube = shapes::cube(2.0);
+        cube.generate_smooth_normals();
+        for (position, normal) in cube.positions.iter().zip(cube.normals.iter()) {
+            let n = glm::make_vec3(normal);
+            assert!((n.norm() - 1.0).abs() < 1e-6);
+            for k in 0..3 {
+                assert!(normal[k] * position[k] > 0.0);
+            }
+        }
+        // Copies of a corner on different sides share the normal
+        let corner = [1.0, 1.0, 1.0];
+        let normals: Vec<[f32; 3]> = (0..cube.positions.len())
+            .filter(|&i| cube.positions[i] == corner)
+            .map(|i| cube.normals[i])
+            .collect();
+        assert_eq!(normals.len(), 3);
+        assert!(normals.iter().all(|n| *n == normals[0]));
+    }
+
+    #[test]
+    fn mirroring_flips_winding_and_tangent_handedness() {
+        let original = shapes::plane(1.0, 1.0);
+        let mut mirrored = shapes::plane(1.0, 1.0);
+        mirrored.bake_transform(&glm::scaling(&glm::vec3(-1.0, 1.0, 1.0)));
+
+        for triangle in mirrored.triangles() {
+            let face = mirrored.face_normal(&triangle);
+            let normal = glm::make_vec3(&mirrored.normals[triangle[0]]);
+            assert!(face.dot(&normal) > 0.0);
+        }
+        for (mirrored, original) in mirrored.tangents.iter().zip(original.tangents.iter()) {
+            assert_eq!(mirrored[0], -original[0]);
+            assert_eq!(mirrored[3], -original[3]);
+        }
+    }
+
+    #[test]
+    fn transforms_without_mirroring_keep_the_winding() {
+        let mut plane = shapes::plane(1.0, 1.0);
+        let indices = plane.indices.clone();
+        let transform =
+            glm::translation(&glm::vec3(0.0, 2.0, 0.0)) * glm::scaling(&glm::vec3(2.0, 2.0, 2.0));
+        plane.bake_transform(&transform);
+        assert_eq!(plane.indices, indices);
+        assert!(plane.positions.iter().all(|p| p[1] == 2.0));
+        assert!(plane.normals.iter().all(|n| *n == [0.0, 1.0, 0.0]));
+    }
+}
@@ -0,0 +1,474 @@
+use std::fs;
+use std::path::Path;
+
+use gltf::buffer::Data;
+
+use crate::animation::{self, Clip, Skin};
+use crate::material::AlphaMode;
+use crate::mesh::{Mesh, MeshData, MorphTarget};
+use crate::obj::{self, ObjError};
+use crate::shapes;
+use crate::texture::{self, Image, Texture};
+use crate::validation::{self, Issue, Report};
+
+#[derive(Debug, Fail)]
+pub enum ModelError {
+    #[fail(display = "Failed to import model {}", path)]
+    ImportError {
+        path: String,
+        #[cause]
+        inner: gltf::Error,
+    },
+    #[fail(display = "Failed to read {} of model {}: {}", resource, path, msg)]
+    ResourceError {
+        path: String,
+        resource: String,
+        msg: String,
+    },
+    #[fail(display = "Model {} failed validation:\n{}", path, report)]
+    Invalid { path: String, report: Report },
+    #[fail(display = "Failed to load OBJ model")]
+    ObjError {
+        #[cause]
+        inner: ObjError,
+    },
+    #[fail(display = "Unknown model format {}", path)]
+    UnknownFormat { path: String },
+}
+
+/// Textures are indices into the model's images. Missing ones are up to the scene
+#[derive(Debug, Clone, Default)]
+pub struct PrimitiveMaterial {
+    pub diffuse: Option<usize>,
+    pub specular: Option<usize>,
+    pub shininess: Option<f32>,
+    pub alpha_mode: AlphaMode,
+    pub opacity: Option<f32>,
+    /// Both sides are drawn
+    pub double_sided: bool,
+}
+
+/// A piece of mesh together with the material it uses
+pub struct Primitive {
+    pub mesh: MeshData,
+    pub material: PrimitiveMaterial,
+}
+
+/// A node of a model. Transforms follow glTF conventions
+pub struct ModelNode {
+    pub name: String,
+    pub translation: [f32; 3],
+    /// Quaternion as [x, y, z, w]
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    /// Indices into the model's primitives
+    pub primitives: Vec<usize>,
+    pub skin: Option<usize>,
+    pub morph_weights: Vec<f32>,
+    pub camera: bool,
+    pub children: Vec<usize>,
+}
+
+impl ModelNode {
+    pub fn new(name: &str) -> Self {
+        ModelNode {
+            name: name.to_owned(),
+            translation: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0, 1.0, 1.0],
+            primitives: Vec::new(),
+            skin: None,
+            morph_weights: Vec::new(),
+            camera: false,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Node tree of a model. Node indices match glTF node indices,
+/// so that skins and clips can refer to them
+#[derive(Default)]
+pub struct Hierarchy {
+    pub nodes: Vec<ModelNode>,
+    pub roots: Vec<usize>,
+}
+
+/// Everything decoded from a model file, not yet uploaded to GPU
+pub struct ModelData {
+    pub hierarchy: Hierarchy,
+    /// Texture coords and images follow glTF, with the top row of images first
+    pub primitives: Vec<Primitive>,
+    pub images: Vec<Image>,
+    pub skins: Vec<Skin<usize>>,
+    pub clips: Vec<Clip<usize>>,
+    /// Problems that didn't stop the model from loading
+    pub warnings: Vec<Issue>,
+}
+
+/// A model that lives on GPU
+pub struct Model {
+    pub hierarchy: Hierarchy,
+    pub meshes: Vec<Mesh>,
+    /// CPU copies of the meshes
+    pub mesh_data: Vec<MeshData>,
+    pub materials: Vec<PrimitiveMaterial>,
+    pub textures: Vec<Texture>,
+    /// CPU copies of the textures' images
+    pub images: Vec<Image>,
+    pub skins: Vec<Skin<usize>>,
+    pub clips: Vec<Clip<usize>>,
+}
+
+/// Picks a loader by file extension. Doesn't touch GL
+pub fn load(path: &str) -> Result<ModelData, ModelError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| e.as_str()) {
+        Some("gltf") | Some("glb") => load_gltf(path),
+        Some("obj") => obj::load(path).map_err(|e| ModelError::ObjError { inner: e }),
+        _ => Err(ModelError::UnknownFormat {
+            path: path.to_owned(),
+        }),
+    }
+}
+
+/// Reads a glTF or GLB file with all its buffers and images, validating it on the way.
+/// The file is read and parsed once, then its buffers and images are resolved from
+/// the document. Doesn't touch GL
+pub fn load_gltf(path: &str) -> Result<ModelData, ModelError> {
+    let import_error = |e| ModelError::ImportError {
+        path: path.to_owned(),
+        inner: e,
+    };
+    let invalid = |report| ModelError::Invalid {
+        path: path.to_owned(),
+        report,
+    };
+
+    // Check the document before reading fails on something like a missing image
+    let bytes = fs::read(path).map_err(|e| import_error(gltf::Error::Io(e)))?;
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&bytes).map_err(import_error)?;
+    let json = match gltf::Glb::from_slice(&bytes) {
+        Ok(glb) => serde_json::from_slice(&glb.json).ok(),
+        Err(_) => serde_json::from_slice(&bytes).ok(),
+    };
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut report = Report::default();
+    validation::check_document(&document, json.as_ref(), base_dir, &mut report);
+    if report.has_errors() {
+        return Err(invalid(report));
+    }
+
+    let resource_error = |resource: String, msg: String| ModelError::ResourceError {
+        path: path.to_owned(),
+        resource,
+        msg,
+    };
+    let buffers = read_buffers(&document, blob, base_dir)
+        .map_err(|(i, msg)| resource_error(format!("buffer {}", i), msg))?;
+    validation::check_buffers(&document, &buffers, &mut report);
+    if report.has_errors() {
+        return Err(invalid(report));
+    }
+
+    let mut primitives = Vec::new();
+    let mut mesh_primitives = Vec::new();
+    for mesh in document.meshes() {
+        mesh_primitives.push(primitives.len()..primitives.len() + mesh.primitives().count());
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let mut data = MeshData::new();
+            data.tex_coords_top_left = true;
+            if let Some(positions) = reader.read_positions() {
+                data.positions = positions.collect();
+            }
+            if let Some(normals) = reader.read_normals() {
+                data.normals = normals.collect();
+            }
+            if let Some(tex_coords) = reader.read_tex_coords(0) {
+                data.tex_coords = tex_coords.into_f32().collect();
+            }
+            if let Some(tangents) = reader.read_tangents() {
+                data.tangents = tangents.collect();
+            }
+            if let Some(joints) = reader.read_joints(0) {
+                data.joints = joints.into_u16().collect();
+            }
+            if let Some(weights) = reader.read_weights(0) {
+                data.weights = weights.into_f32().collect();
+            }
+            if let Some(indices) = reader.read_indices() {
+                data.indices = indices.into_u32().collect();
+            }
+            if data.normals.is_empty() {
+                data.generate_smooth_normals();
+            }
+            for (positions, normals, tangents) in reader.read_morph_targets() {
+                data.morph_targets.push(MorphTarget {
+                    positions: positions.map(|p| p.collect()).unwrap_or_default(),
+                    normals: normals.map(|n| n.collect()).unwrap_or_default(),
+                    tangents: tangents.map(|t| t.collect()).unwrap_or_default(),
+                });
+            }
+            if let Some(weights) = mesh.weights() {
+                data.morph_weights = weights.to_vec();
+            }
+            let material = primitive.material();
+            let pbr = material.pbr_metallic_roughness();
+            let diffuse = pbr
+                .base_color_texture()
+                .map(|info| info.texture().source().index());
+            let alpha_mode = match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff()),
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            };
+            primitives.push(Primitive {
+                mesh: data,
+                material: PrimitiveMaterial {
+                    diffuse,
+                    alpha_mode,
+                    opacity: Some(pbr.base_color_factor()[3]),
+                    double_sided: material.double_sided(),
+                    ..Default::default()
+                },
+            });
+        }
+    }
+
+    let mut hierarchy = Hierarchy::default();
+    for node in document.nodes() {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let mut model_node = ModelNode::new(node.name().unwrap_or(""));
+        model_node.translation = translation;
+        model_node.rotation = rotation;
+        model_node.scale = scale;
+        if let Some(mesh) = node.mesh() {
+            model_node.primitives = mesh_primitives[mesh.index()].clone().collect();
+            if let Some(weights) = node.weights().or_else(|| mesh.weights()) {
+                model_node.morph_weights = weights.to_vec();
+            }
+        }
+        model_node.skin = node.skin().map(|skin| skin.index());
+        model_node.camera = node.camera().is_some();
+        model_node.children = node.children().map(|child| child.index()).collect();
+        hierarchy.nodes.push(model_node);
+    }
+    if let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        hierarchy.roots = scene.nodes().map(|node| node.index()).collect();
+    }
+
+    let images = read_images(&document, &buffers, base_dir)
+        .map_err(|(i, msg)| resource_error(format!("image {}", i), msg))?;
+
+    let skins = animation::load_skins(&document, &buffers);
+    let clips = animation::load_clips(&document, &buffers);
+
+    Ok(ModelData {
+        hierarchy,
+        primitives,
+        images,
+        skins,
+        clips,
+        warnings: report.into_warnings(),
+    })
+}
+
+/// Contents of every buffer, padded to four bytes. Fails with the index of the
+/// buffer that couldn't be read
+fn read_buffers(
+    document: &gltf::Document,
+    mut blob: Option<Vec<u8>>,
+    base_dir: &Path,
+) -> Result<Vec<Data>, (usize, String)> {
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let mut data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().ok_or_else(|| "no GLB blob".to_owned()),
+            gltf::buffer::Source::Uri(uri) => read_uri(uri, base_dir),
+        }
+        .map_err(|msg| (buffer.index(), msg))?;
+        if data.len() < buffer.length() {
+            let msg = format!("{} bytes instead of {}", data.len(), buffer.length());
+            return Err((buffer.index(), msg));
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        buffers.push(Data(data));
+    }
+    Ok(buffers)
+}
+
+/// Decoded images, top row first. Fails with the index of the image that couldn't
+/// be read
+fn read_images(
+    document: &gltf::Document,
+    buffers: &[Data],
+    base_dir: &Path,
+) -> Result<Vec<Image>, (usize, String)> {
+    let mut images = Vec::new();
+    for image in document.images() {
+        let error = |msg| (image.index(), msg);
+        let image = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let end = view.offset() + view.length();
+                let bytes = buffers[view.buffer().index()]
+                    .get(view.offset()..end)
+                    .ok_or_else(|| error("view is outside its buffer".to_owned()))?;
+                texture::decode_image_data(bytes)
+            }
+            gltf::image::Source::Uri { uri, .. } => {
+                texture::decode_image_data(&read_uri(uri, base_dir).map_err(error)?)
+            }
+        }
+        .map_err(|e| error(e.to_string()))?;
+        images.push(image);
+    }
+    Ok(images)
+}
+
+/// Bytes of a base64 data URI or of a file relative to the model
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>, String> {
+    if uri.starts_with("data:") {
+        let data = uri
+            .splitn(2, ";base64,")
+            .nth(1)
+            .ok_or_else(|| "data URI isn't base64".to_owned())?;
+        base64::decode(data).map_err(|e| e.to_string())
+    } else {
+        let path = base_dir.join(uri);
+        fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl ModelData {
+    /// Merges duplicate vertices and orders triangles for the vertex cache.
+    /// Takes a while for big models, so it's left to the loader threads
+    pub fn optimize_meshes(&mut self) {
+        for primitive in self.primitives.iter_mut() {
+            primitive.mesh.weld(0.0);
+            primitive.mesh.optimize_vertex_cache();
+        }
+    }
+
+    /// A plain cube standing in for a model that failed to load
+    pub fn placeholder() -> Self {
+        let mut node = ModelNode::new("placeholder");
+        node.primitives.push(0);
+        ModelData {
+            hierarchy: Hierarchy {
+                nodes: vec![node],
+                roots: vec![0],
+            },
+            primitives: vec![Primitive {
+                mesh: shapes::cube(1.0),
+                material: PrimitiveMaterial::default(),
+            }],
+            images: Vec::new(),
+            skins: Vec::new(),
+            clips: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Creates GPU resources of a model one mesh or image at a time,
+/// so that the loading screen keeps drawing in between. Must be used on the GL thread
+pub struct ModelUpload {
+    data: ModelData,
+    meshes: Vec<Mesh>,
+    textures: Vec<Texture>,
+}
+
+impl ModelUpload {
+    pub fn new(data: ModelData) -> Self {
+        ModelUpload {
+            data,
+            meshes: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    /// Meshes and images that haven't been uploaded yet
+    pub fn remaining(&self) -> usize {
+        self.data.primitives.len() - self.meshes.len() + self.data.images.len()
+            - self.textures.len()
+    }
+
+    /// Fraction of meshes and images that have been uploaded
+    pub fn progress(&self) -> f32 {
+        let total = self.data.primitives.len() + self.data.images.len();
+        if total == 0 {
+            return 1.0;
+        }
+        (self.meshes.len() + self.textures.len()) as f32 / total as f32
+    }
+
+    /// Uploads the next mesh or image, if there's one left
+    pub fn step(&mut self) {
+        if let Some(primitive) = self.data.primitives.get(self.meshes.len()) {
+            self.meshes.push(Mesh::new(&primitive.mesh));
+        } else if let Some(image) = self.data.images.get(self.textures.len()) {
+            let texture = Texture::new().set_default_parameters().set_image(image);
+            self.textures.push(texture);
+        }
+    }
+
+    /// The model on GPU. Whatever is left is uploaded first
+    pub fn finish(mut self) -> Model {
+        while self.remaining() > 0 {
+            self.step();
+        }
+        let data = self.data;
+        Model {
+            hierarchy: data.hierarchy,
+            meshes: self.meshes,
+            materials: data.primitives.iter().map(|p| p.material.clone()).collect(),
+            mesh_data: data.primitives.into_iter().map(|p| p.mesh).collect(),
+            textures: self.textures,
+            images: data.images,
+            skins: data.skins,
+            clips: data.clips,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(buffers: &str) -> gltf::Document {
+        let json = format!(r#"{{"asset":{{"version":"2.0"}},"buffers":{}}}"#, buffers);
+        gltf::Gltf::from_slice(json.as_bytes()).unwrap().document
+    }
+
+    #[test]
+    fn data_uri_buffers_are_decoded_and_padded() {
+        let document =
+            document(r#"[{"byteLength":3,"uri":"data:application/octet-stream;base64,AQID"}]"#);
+        let buffers = read_buffers(&document, None, Path::new("")).unwrap();
+        assert_eq!(buffers[0].0, vec![1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn short_buffers_are_rejected() {
+        let document =
+            document(r#"[{"byteLength":8,"uri":"data:application/octet-stream;base64,AQID"}]"#);
+        let (index, _) = read_buffers(&document, None, Path::new("")).unwrap_err();
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn bin_buffers_come_from_the_blob() {
+        let document = document(r#"[{"byteLength":4}]"#);
+        let buffers = read_buffers(&document, Some(vec![9, 8, 7, 6]), Path::new("")).unwrap();
+        assert_eq!(buffers[0].0, vec![9, 8, 7, 6]);
+        assert!(read_buffers(&document, None, Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,499 @@
+//! Wavefront OBJ models with MTL materials. Doesn't touch GL so can be used from any thread.
+//! Texture coords are flipped and images keep their top row first, following glTF like
+//! other models
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::material::AlphaMode;
+use crate::mesh::MeshData;
+use crate::model::{Hierarchy, ModelData, ModelNode, Primitive, PrimitiveMaterial};
+use crate::texture::{self, Image, TextureError};
+
+#[derive(Debug, Fail)]
+pub enum ObjError {
+    #[fail(display = "I/O Error ({})", path)]
+    IoError {
+        path: String,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "{}:{}: {}", path, line, message)]
+    ParseError {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    #[fail(display = "Cannot load texture {}", path)]
+    TextureError {
+        path: String,
+        #[cause]
+        inner: TextureError,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ObjError>;
+
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse_color: Option<[f32; 3]>,
+    specular_color: Option<[f32; 3]>,
+    shininess: Option<f32>,
+    /// From `d`, or `Tr` which is its complement
+    opacity: Option<f32>,
+    diffuse_map: Option<String>,
+    specular_map: Option<String>,
+}
+
+/// Position, texture coords and normal indices of a face corner
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+/// Triangles of one object that use the same material
+struct PrimitiveBuilder {
+    object: usize,
+    material: Option<String>,
+    mesh: MeshData,
+    vertices: HashMap<VertexKey, u32>,
+}
+
+/// Reads an OBJ file together with its MTL files and textures
+pub fn load(path: &str) -> Result<ModelData> {
+    let text = read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut builders: Vec<PrimitiveBuilder> = Vec::new();
+
+    let file_name = Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    let mut objects = vec![file_name.to_owned()];
+    let mut material: Option<String> = None;
+    let mut current: Option<usize> = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| ObjError::ParseError {
+            path: path.to_owned(),
+            line: number + 1,
+            message: message.to_owned(),
+        };
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let rest = line[keyword.len()..].trim();
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(words, 3).ok_or_else(|| error("Bad vertex position"))?;
+                positions.push([v[0], v[1], v[2]]);
+            }
+            "vt" => {
+                let v =
+                    parse_floats(words.take(2), 2).ok_or_else(|| error("Bad texture coords"))?;
+                tex_coords.push([v[0], 1.0 - v[1]]);
+            }
+            "vn" => {
+                let v = parse_floats(words, 3).ok_or_else(|| error("Bad normal"))?;
+                normals.push([v[0], v[1], v[2]]);
+            }
+            "o" | "g" => {
+                objects.push(rest.to_owned());
+                current = None;
+            }
+            "usemtl" => {
+                material = Some(rest.to_owned());
+                current = None;
+            }
+            "mtllib" => {
+                let mtl_path = base_dir.join(rest);
+                let mtl_path = mtl_path.to_string_lossy();
+                materials.extend(load_mtl(&mtl_path)?);
+            }
+            "f" => {
+                let mut corners = Vec::new();
+                for word in words {
+                    let key = parse_corner(word, positions.len(), tex_coords.len(), normals.len())
+                        .ok_or_else(|| error(&format!("Bad face vertex '{}'", word)))?;
+                    corners.push(key);
+                }
+                if corners.len() < 3 {
+                    return Err(error("Face has less than 3 vertices"));
+                }
+
+                let object = objects.len() - 1;
+                let builder = match current {
+                    Some(builder) => &mut builders[builder],
+                    None => {
+                        builders.push(PrimitiveBuilder {
+                            object,
+                            material: material.clone(),
+                            mesh: MeshData {
+                                tex_coords_top_left: true,
+                                ..MeshData::new()
+                            },
+                            vertices: HashMap::new(),
+                        });
+                        current = Some(builders.len() - 1);
+                        builders.last_mut().unwrap()
+                    }
+                };
+
+                let corners: Vec<u32> = corners
+                    .into_iter()
+                    .map(|key| builder.vertex(key, &positions, &tex_coords, &normals))
+                    .collect();
+                // Polygons become triangle fans
+                for i in 1..corners.len() - 1 {
+                    builder.mesh.indices.extend_from_slice(&[
+                        corners[0],
+                        corners[i],
+                        corners[i + 1],
+                    ]);
+                }
+            }
+            _ => {} // Smoothing groups, lines, curves etc. are ignored
+        }
+    }
+
+    // Textures from map files or plain colors, shared between primitives
+    let mut images = Vec::new();
+    let mut image_by_key: HashMap<String, usize> = HashMap::new();
+    let mut image_for = |map: &Option<String>, color: Option<[f32; 3]>| -> Result<Option<usize>> {
+        let key = match (map, color) {
+            (Some(map), _) => base_dir.join(map).to_string_lossy().into_owned(),
+            (None, Some(color)) => format!("{:?}", color),
+            (None, None) => return Ok(None),
+        };
+        if let Some(&index) = image_by_key.get(&key) {
+            return Ok(Some(index));
+        }
+        let image = if map.is_some() {
+            let bytes = fs::read(&key).map_err(|e| ObjError::IoError {
+                path: key.clone(),
+                inner: e,
+            })?;
+            texture::decode_image_data(&bytes).map_err(|e| ObjError::TextureError {
+                path: key.clone(),
+                inner: e,
+            })?
+        } else {
+            solid_color(color.unwrap_or_default())
+        };
+        images.push(image);
+        image_by_key.insert(key, images.len() - 1);
+        Ok(Some(images.len() - 1))
+    };
+
+    let mut hierarchy = Hierarchy::default();
+    let mut node_by_object: HashMap<usize, usize> = HashMap::new();
+    let mut primitives = Vec::new();
+    for mut builder in builders {
+        if builder.mesh.normals.iter().any(|n| *n == [0.0, 0.0, 0.0]) {
+            let smooth = builder.mesh.smooth_normals();
+            for (normal, smooth) in builder.mesh.normals.iter_mut().zip(smooth) {
+                if *normal == [0.0, 0.0, 0.0] {
+                    *normal = smooth;
+                }
+            }
+        }
+
+        let mut primitive_material = PrimitiveMaterial::default();
+        if let Some(mtl) = builder.material.as_ref().and_then(|m| materials.get(m)) {
+            primitive_material.diffuse = image_for(&mtl.diffuse_map, mtl.diffuse_color)?;
+            primitive_material.specular = image_for(&mtl.specular_map, mtl.specular_color)?;
+            primitive_material.shininess = mtl.shininess;
+            primitive_material.opacity = mtl.opacity;
+            if mtl.opacity.map_or(false, |opacity| opacity < 1.0) {
+                primitive_material.alpha_mode = AlphaMode::Blend;
+            }
+        }
+
+        let node = *node_by_object.entry(builder.object).or_insert_with(|| {
+            hierarchy
+                .nodes
+                .push(ModelNode::new(&objects[builder.object]));
+            hierarchy.roots.push(hierarchy.nodes.len() - 1);
+            hierarchy.nodes.len() - 1
+        });
+        hierarchy.nodes[node].primitives.push(primitives.len());
+        primitives.push(Primitive {
+            mesh: builder.mesh,
+            material: primitive_material,
+        });
+    }
+
+    Ok(ModelData {
+        hierarchy,
+        primitives,
+        images,
+        skins: Vec::new(),
+        clips: Vec::new(),
+        warnings: Vec::new(),
+    })
+}
+
+impl PrimitiveBuilder {
+    /// Index of the vertex, adding it if it's new.
+    /// Vertices without a normal get a zero one to be generated later
+    fn vertex(
+        &mut self,
+        key: VertexKey,
+        positions: &[[f32; 3]],
+        tex_coords: &[[f32; 2]],
+        normals: &[[f32; 3]],
+    ) -> u32 {
+        let mesh = &mut self.mesh;
+        *self.vertices.entry(key).or_insert_with(|| {
+            let (position, tex_coord, normal) = key;
+            mesh.positions.push(positions[position]);
+            mesh.tex_coords
+                .push(tex_coord.map_or([0.0, 0.0], |i| tex_coords[i]));
+            mesh.normals
+                .push(normal.map_or([0.0, 0.0, 0.0], |i| normals[i]));
+            (mesh.positions.len() - 1) as u32
+        })
+    }
+}
+
+fn read_to_string(path: &str) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| ObjError::IoError {
+        path: path.to_owned(),
+        inner: e,
+    })
+}
+
+fn parse_floats<'a>(words: impl Iterator<Item = &'a str>, count: usize) -> Option<Vec<f32>> {
+    let values = words
+        .take(count)
+        .map(|w| w.parse().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    if values.len() == count {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+/// Parses v, v/vt, v//vn or v/vt/vn into zero-based indices.
+/// Negative indices count back from the last element read so far
+fn parse_corner(
+    word: &str,
+    positions: usize,
+    tex_coords: usize,
+    normals: usize,
+) -> Option<VertexKey> {
+    let resolve = |index: &str, count: usize| -> Option<usize> {
+        let index: i64 = index.parse().ok()?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved >= 0 && (resolved as usize) < count {
+            Some(resolved as usize)
+        } else {
+            None
+        }
+    };
+    let optional = |index: Option<&str>, count: usize| -> Option<Option<usize>> {
+        match index {
+            None | Some("") => Some(None),
+            Some(index) => resolve(index, count).map(Some),
+        }
+    };
+
+    let mut parts = word.split('/');
+    let position = resolve(parts.next()?, positions)?;
+    let tex_coord = optional(parts.next(), tex_coords)?;
+    let normal = optional(parts.next(), normals)?;
+    Some((position, tex_coord, normal))
+}
+
+/// Materials by name
+fn load_mtl(path: &str) -> Result<HashMap<String, MtlMaterial>> {
+    let text = read_to_string(path)?;
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| ObjError::ParseError {
+            path: path.to_owned(),
+            line: number + 1,
+            message: message.to_owned(),
+        };
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = line[keyword.len()..].trim().to_owned();
+            materials.insert(name.clone(), MtlMaterial::default());
+            current = Some(name);
+            continue;
+        }
+        let material = match current.as_ref().and_then(|name| materials.get_mut(name)) {
+            Some(material) => material,
+            None => continue,
+        };
+        let color = |words: std::str::SplitWhitespace| {
+            parse_floats(words, 3)
+                .map(|c| [c[0], c[1], c[2]])
+                .ok_or_else(|| error("Bad color"))
+        };
+        match keyword {
+            "Kd" => material.diffuse_color = Some(color(words)?),
+            "Ks" => material.specular_color = Some(color(words)?),
+            "Ns" => {
+                let ns = parse_floats(words, 1).ok_or_else(|| error("Bad shininess"))?;
+                material.shininess = Some(ns[0]);
+            }
+            "d" | "Tr" => {
+                let value = parse_floats(words, 1).ok_or_else(|| error("Bad opacity"))?;
+                let opacity = if keyword == "d" {
+                    value[0]
+                } else {
+                    1.0 - value[0]
+                };
+                material.opacity = Some(opacity);
+            }
+            // Texture options come before the file name
+            "map_Kd" => material.diffuse_map = words.last().map(|w| w.to_owned()),
+            "map_Ks" => material.specular_map = words.last().map(|w| w.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// 1x1 texture for materials that only have a color
+fn solid_color(color: [f32; 3]) -> Image {
+    let to_byte = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    Image {
+        width: 1,
+        height: 1,
+        channels: 3,
+        data: color.iter().map(|&c| to_byte(c)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the files to a directory of their own and loads the first one
+    fn load_files(test: &str, files: &[(&str, &str)]) -> ModelData {
+        let dir = std::env::temp_dir().join(format!("obj-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        let model = load(&dir.join(files[0].0).to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        model.unwrap()
+    }
+
+    #[test]
+    fn corners_resolve_to_zero_based_indices() {
+        assert_eq!(parse_corner("2", 3, 0, 0), Some((1, None, None)));
+        assert_eq!(parse_corner("1/2", 3, 2, 0), Some((0, Some(1), None)));
+        assert_eq!(parse_corner("3//1", 3, 0, 1), Some((2, None, Some(0))));
+        assert_eq!(parse_corner("1/1/1", 1, 1, 1), Some((0, Some(0), Some(0))));
+        assert_eq!(parse_corner("4", 3, 0, 0), None);
+        assert_eq!(parse_corner("0", 3, 0, 0), None);
+        assert_eq!(parse_corner("1//2", 3, 0, 1), None);
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        assert_eq!(parse_corner("-1", 3, 0, 0), Some((2, None, None)));
+        assert_eq!(
+            parse_corner("-3/-1/-2", 3, 2, 2),
+            Some((0, Some(1), Some(0)))
+        );
+        assert_eq!(parse_corner("-4", 3, 0, 0), None);
+
+        let model = load_files(
+            "negative",
+            &[(
+                "negative.obj",
+                "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\nf 1 2 -1\n",
+            )],
+        );
+        let mesh = &model.primitives[0].mesh;
+        let triangles: Vec<[f32; 3]> = mesh
+            .indices
+            .iter()
+            .map(|&i| mesh.positions[i as usize])
+            .collect();
+        assert_eq!(triangles[2], [0.0, 1.0, 0.0]);
+        assert_eq!(triangles[5], [5.0, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn position_and_normal_corners_keep_their_normals() {
+        let model = load_files(
+            "normals",
+            &[(
+                "normals.obj",
+                "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 -1\nf 1//1 2//1 3//1\n",
+            )],
+        );
+        let mesh = &model.primitives[0].mesh;
+        assert_eq!(mesh.normals, vec![[0.0, 0.0, -1.0]; 3]);
+        assert_eq!(mesh.tex_coords, vec![[0.0, 0.0]; 3]);
+    }
+
+    #[test]
+    fn polygons_become_triangle_fans() {
+        let model = load_files(
+            "fan",
+            &[(
+                "fan.obj",
+                "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4 5\n",
+            )],
+        );
+        let mesh = &model.primitives[0].mesh;
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn dissolve_and_transparency_set_opacity() {
+        let model = load_files(
+            "opacity",
+            &[
+                (
+                    "opacity.obj",
+                    "mtllib opacity.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                     usemtl dissolved\nf 1 2 3\nusemtl transparent\nf 1 2 3\n\
+                     usemtl opaque\nf 1 2 3\n",
+                ),
+                (
+                    "opacity.mtl",
+                    "newmtl dissolved\nKd 1 0 0\nd 0.25\n\
+                     newmtl transparent\nKd 0 1 0\nTr 0.25\n\
+                     newmtl opaque\nKd 0 0 1\nd 1\n",
+                ),
+            ],
+        );
+        let materials: Vec<&PrimitiveMaterial> =
+            model.primitives.iter().map(|p| &p.material).collect();
+        assert_eq!(materials[0].opacity, Some(0.25));
+        assert_eq!(materials[0].alpha_mode, AlphaMode::Blend);
+        assert_eq!(materials[1].opacity, Some(0.75));
+        assert_eq!(materials[1].alpha_mode, AlphaMode::Blend);
+        assert_eq!(materials[2].opacity, Some(1.0));
+        assert_eq!(materials[2].alpha_mode, AlphaMode::Opaque);
+    }
+}
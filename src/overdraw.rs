@@ -0,0 +1,77 @@
+//! Shows how many times each pixel is drawn. Scene passes count fragments in the
+//! stencil buffer and the counts are then painted over the screen as a heat map
+
+use glm::Vec4;
+
+use crate::buffers::VertexArray;
+use crate::render_state::{CompareFunc, RenderContext, RenderState, StencilOp, StencilState};
+use crate::shader::{self, Program};
+
+/// Colors for pixels drawn once, twice and so on. The last one is for this many or more
+const HEAT: [[f32; 4]; 5] = [
+    [0.0, 0.0, 0.6, 1.0],
+    [0.0, 0.6, 0.0, 1.0],
+    [0.8, 0.8, 0.0, 1.0],
+    [0.9, 0.4, 0.0, 1.0],
+    [1.0, 0.0, 0.0, 1.0],
+];
+
+/// Stencil state for scene passes, which counts every fragment whether it's hidden or not
+pub fn counting() -> StencilState {
+    StencilState {
+        func: CompareFunc::Always,
+        depth_fail: StencilOp::Increment,
+        pass: StencilOp::Increment,
+        ..Default::default()
+    }
+}
+
+pub struct Overdraw {
+    program: Program,
+    /// Core profile draws need one bound, even without attributes
+    vao: VertexArray,
+}
+
+impl Overdraw {
+    pub fn new() -> shader::Result<Self> {
+        let program = Program::new()
+            .vertex_shader("assets/shaders/overdraw/overdraw.vert")?
+            .fragment_shader("assets/shaders/overdraw/overdraw.frag")?
+            .link()?;
+        Ok(Overdraw {
+            program,
+            vao: VertexArray::new(),
+        })
+    }
+
+    /// Paints the counts left by passes drawn with the counting stencil state.
+    /// Pixels nothing was drawn to are left as they are
+    pub fn draw(&self, render_context: &mut RenderContext) -> shader::Result<()> {
+        self.program.set_used();
+        self.vao.bind();
+        for (i, color) in HEAT.iter().enumerate() {
+            let last = i == HEAT.len() - 1;
+            render_context.apply(&RenderState {
+                depth_test: None,
+                depth_write: false,
+                stencil: Some(StencilState {
+                    // Passes where the reference is equal to or, for the last color,
+                    // not more than the count
+                    func: if last {
+                        CompareFunc::LessEqual
+                    } else {
+                        CompareFunc::Equal
+                    },
+                    reference: i as i32 + 1,
+                    write_mask: 0,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+            self.program.set_vec4("color", &Vec4::from(*color))?;
+            self.vao.draw_triangles(3);
+        }
+        self.vao.unbind();
+        Ok(())
+    }
+}
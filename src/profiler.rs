@@ -0,0 +1,315 @@
+//! Where frame time goes. Named scopes are timed on the CPU and, with timestamp
+//! queries, on the GPU. Queries are read a few frames later so that the CPU never
+//! waits for the GPU to catch up
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+use gl::types::*;
+
+use crate::text::TextRenderer;
+
+/// Frames that can be queued on the GPU. Queries this old are normally done
+const FRAMES_IN_FLIGHT: usize = 4;
+
+/// Frames kept for averages and the graph
+const HISTORY: usize = 120;
+
+/// Scope around everything between begin_frame and end_frame
+const FRAME: &str = "frame";
+
+/// Frame time the graph is scaled to, twice the time of a frame at 60 Hz
+const GRAPH_MS: f32 = 1000.0 / 30.0;
+
+/// Recent durations of a scope, in milliseconds
+#[derive(Debug, Default)]
+pub struct History {
+    samples: VecDeque<f32>,
+}
+
+impl History {
+    pub fn push(&mut self, ms: f32) {
+        if self.samples.len() == HISTORY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    pub fn average(&self) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples.iter().sum::<f32>() / self.samples.len() as f32
+    }
+
+    pub fn max(&self) -> f32 {
+        self.samples.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// Oldest first
+    pub fn samples(&self) -> impl Iterator<Item = &f32> {
+        self.samples.iter()
+    }
+}
+
+struct Scope {
+    name: &'static str,
+    depth: usize,
+    cpu_start: Instant,
+    cpu_ms: f32,
+    /// Timestamp queries at the start and end
+    queries: (GLuint, GLuint),
+}
+
+/// Scopes of a frame that may still be waiting for their GPU times
+#[derive(Default)]
+struct FrameSlot {
+    frame: u64,
+    scopes: Vec<Scope>,
+    /// Reused from frame to frame, two for each scope
+    queries: Vec<GLuint>,
+}
+
+impl FrameSlot {
+    fn query_pair(&mut self) -> (GLuint, GLuint) {
+        let used = self.scopes.len() * 2;
+        while self.queries.len() < used + 2 {
+            let mut id: GLuint = 0;
+            unsafe {
+                gl::GenQueries(1, &mut id);
+            }
+            self.queries.push(id);
+        }
+        (self.queries[used], self.queries[used + 1])
+    }
+}
+
+pub struct Profiler {
+    slots: Vec<FrameSlot>,
+    current: usize,
+    frame: u64,
+    /// Scopes of the current frame that haven't ended, as indices into its slot
+    open: Vec<usize>,
+    /// Scope names with their depth, in the order they were first seen
+    order: Vec<(&'static str, usize)>,
+    cpu: HashMap<&'static str, History>,
+    gpu: HashMap<&'static str, History>,
+    capture: Option<BufWriter<File>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            slots: (0..FRAMES_IN_FLIGHT)
+                .map(|_| FrameSlot::default())
+                .collect(),
+            current: 0,
+            frame: 0,
+            open: Vec::new(),
+            order: Vec::new(),
+            cpu: HashMap::new(),
+            gpu: HashMap::new(),
+            capture: None,
+        }
+    }
+
+    /// Collects GPU times of the frame that used the slot before and opens the frame scope
+    pub fn begin_frame(&mut self) {
+        self.current = (self.current + 1) % FRAMES_IN_FLIGHT;
+        self.resolve(self.current, false);
+        let slot = &mut self.slots[self.current];
+        slot.frame = self.frame;
+        slot.scopes.clear();
+        self.frame += 1;
+        self.open.clear();
+        self.begin(FRAME);
+    }
+
+    pub fn end_frame(&mut self) {
+        while !self.open.is_empty() {
+            let name = self.slots[self.current].scopes[*self.open.last().unwrap()].name;
+            self.end(name);
+        }
+    }
+
+    /// Starts a scope inside the ones that are open
+    pub fn begin(&mut self, name: &'static str) {
+        let depth = self.open.len();
+        if !self.order.iter().any(|&(known, _)| known == name) {
+            self.order.push((name, depth));
+        }
+        let slot = &mut self.slots[self.current];
+        let queries = slot.query_pair();
+        unsafe {
+            gl::QueryCounter(queries.0, gl::TIMESTAMP);
+        }
+        self.open.push(slot.scopes.len());
+        slot.scopes.push(Scope {
+            name,
+            depth,
+            cpu_start: Instant::now(),
+            cpu_ms: 0.0,
+            queries,
+        });
+    }
+
+    /// Ends the innermost scope, which should have the name
+    pub fn end(&mut self, name: &'static str) {
+        let index = match self.open.pop() {
+            Some(index) => index,
+            None => return,
+        };
+        let scope = &mut self.slots[self.current].scopes[index];
+        debug_assert_eq!(scope.name, name, "profiler scopes ended out of order");
+        unsafe {
+            gl::QueryCounter(scope.queries.1, gl::TIMESTAMP);
+        }
+        scope.cpu_ms = scope.cpu_start.elapsed().as_micros() as f32 / 1000.0;
+        self.cpu
+            .entry(scope.name)
+            .or_insert_with(History::default)
+            .push(scope.cpu_ms);
+    }
+
+    /// Average and worst times of every scope, nested scopes indented
+    pub fn report(&self) -> String {
+        let mut report = format!("{:<16} {:>13} {:>13}", "ms avg/max", "cpu", "gpu");
+        let empty = History::default();
+        for &(name, depth) in self.order.iter() {
+            let cpu = self.cpu.get(name).unwrap_or(&empty);
+            let gpu = self.gpu.get(name).unwrap_or(&empty);
+            let indented = format!("{:indent$}{}", "", name, indent = depth * 2);
+            let _ = write!(
+                report,
+                "\n{:<16} {:>6.2}/{:<6.2} {:>6.2}/{:<6.2}",
+                indented,
+                cpu.average(),
+                cpu.max(),
+                gpu.average(),
+                gpu.max()
+            );
+        }
+        report
+    }
+
+    /// Bars of recent frame times with the top left corner at the position, in pixels.
+    /// CPU time is grey and GPU time green in front of it. The line is at 60 Hz
+    pub fn draw_graph(&self, text: &mut TextRenderer, x: f32, y: f32, width: f32, height: f32) {
+        text.rect(x, y, width, height, &glm::vec4(0.0, 0.0, 0.0, 0.6));
+        let bar_width = width / HISTORY as f32;
+        let histories = [
+            (self.cpu.get(FRAME), glm::vec4(0.6, 0.6, 0.6, 0.8)),
+            (self.gpu.get(FRAME), glm::vec4(0.3, 0.9, 0.4, 0.8)),
+        ];
+        for (history, color) in histories.iter() {
+            let history = match history {
+                Some(history) => history,
+                None => continue,
+            };
+            // Newest on the right
+            let offset = HISTORY - history.samples.len();
+            for (i, &ms) in history.samples().enumerate() {
+                let bar_height = (ms / GRAPH_MS).min(1.0) * height;
+                let bar_x = x + (offset + i) as f32 * bar_width;
+                text.rect(bar_x, y + height - bar_height, bar_width, bar_height, color);
+            }
+        }
+        let target_y = y + height * 0.5;
+        text.rect(x, target_y, width, 1.0, &glm::vec4(1.0, 0.3, 0.3, 0.8));
+    }
+
+    /// Writes the times of every scope from now on to a CSV file, one row per scope
+    /// and frame. GPU times are empty where the queries weren't ready
+    pub fn start_capture(&mut self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "frame,scope,depth,cpu_ms,gpu_ms")?;
+        self.capture = Some(file);
+        Ok(())
+    }
+
+    /// Waits for the frames still on the GPU so that their rows get written too
+    pub fn stop_capture(&mut self) -> io::Result<()> {
+        if self.capture.is_none() {
+            return Ok(());
+        }
+        // Oldest first. The current frame only if it has ended
+        let frames = if self.open.is_empty() {
+            FRAMES_IN_FLIGHT
+        } else {
+            FRAMES_IN_FLIGHT - 1
+        };
+        for i in 1..=frames {
+            let slot = (self.current + i) % FRAMES_IN_FLIGHT;
+            self.resolve(slot, true);
+            // Already counted, so the slot isn't resolved again when it's reused
+            self.slots[slot].scopes.clear();
+        }
+        match self.capture.take() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Reads the queries of the slot's frame. Without `wait` only the ones that are ready
+    fn resolve(&mut self, slot: usize, wait: bool) {
+        let slot = &self.slots[slot];
+        for scope in slot.scopes.iter() {
+            let gpu_ms = query_elapsed(scope.queries, wait);
+            if let Some(ms) = gpu_ms {
+                self.gpu
+                    .entry(scope.name)
+                    .or_insert_with(History::default)
+                    .push(ms);
+            }
+            if let Some(file) = &mut self.capture {
+                let gpu = gpu_ms.map_or(String::new(), |ms| format!("{:.4}", ms));
+                let result = writeln!(
+                    file,
+                    "{},{},{},{:.4},{}",
+                    slot.frame, scope.name, scope.depth, scope.cpu_ms, gpu
+                );
+                if let Err(error) = result {
+                    eprintln!("Stopped profile capture: {}", error);
+                    self.capture = None;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        for slot in self.slots.iter() {
+            unsafe {
+                gl::DeleteQueries(slot.queries.len() as GLsizei, slot.queries.as_ptr());
+            }
+        }
+    }
+}
+
+/// Milliseconds between the timestamps, if both are available. Reading the result
+/// stalls until the GPU gets there, so without `wait` None is returned instead
+fn query_elapsed(queries: (GLuint, GLuint), wait: bool) -> Option<f32> {
+    if !wait {
+        let mut available: GLint = 0;
+        unsafe {
+            gl::GetQueryObjectiv(queries.1, gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+        if available == 0 {
+            return None;
+        }
+    }
+    let (mut start, mut end): (GLuint64, GLuint64) = (0, 0);
+    unsafe {
+        gl::GetQueryObjectui64v(queries.0, gl::QUERY_RESULT, &mut start);
+        gl::GetQueryObjectui64v(queries.1, gl::QUERY_RESULT, &mut end);
+    }
+    Some(end.saturating_sub(start) as f32 / 1_000_000.0)
+}
@@ -0,0 +1,164 @@
+//! Draw submissions collected over a frame, sorted so that the renderer changes
+//! as little state as possible and blends transparent objects in the right order
+
+use std::cmp::Ordering;
+
+use glm::{Mat4, Vec4};
+
+use crate::mesh::Instance;
+
+/// Programs meshes can be drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProgramId {
+    Static,
+    Skinned,
+}
+
+/// Everything needed to draw a mesh once
+#[derive(Debug, Clone)]
+pub struct DrawCommand {
+    pub program: ProgramId,
+    pub material: usize,
+    /// Diffuse and specular texture of the material, so that materials sharing
+    /// them are drawn one after the other
+    pub textures: (usize, usize),
+    pub mesh: usize,
+    pub transform: Mat4,
+    pub color: Vec4,
+    /// Only for the skinned program
+    pub joint_matrices: Vec<Mat4>,
+    /// Empty means the mesh's default weights
+    pub morph_weights: Vec<f32>,
+    /// Whether it can be drawn instanced together with similar commands,
+    /// which is only possible without skins and morph targets
+    pub instanceable: bool,
+    pub transparent: bool,
+    /// Distance from the camera
+    pub depth: f32,
+}
+
+impl DrawCommand {
+    pub fn instance(&self) -> Instance {
+        Instance {
+            model: self.transform,
+            color: self.color,
+        }
+    }
+
+    fn can_batch_with(&self, other: &DrawCommand) -> bool {
+        self.instanceable
+            && other.instanceable
+            && self.program == other.program
+            && self.material == other.material
+            && self.mesh == other.mesh
+    }
+}
+
+#[derive(Default)]
+pub struct RenderQueue {
+    opaque: Vec<DrawCommand>,
+    transparent: Vec<DrawCommand>,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+    }
+
+    pub fn submit(&mut self, command: DrawCommand) {
+        if command.transparent {
+            self.transparent.push(command);
+        } else {
+            self.opaque.push(command);
+        }
+    }
+
+    /// Opaque commands by program, textures, material and mesh, then front to back so
+    /// that hidden fragments fail the depth test early. Transparent ones back to front
+    pub fn sort(&mut self) {
+        let by_depth = |a: &DrawCommand, b: &DrawCommand| {
+            a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal)
+        };
+        self.opaque.sort_by(|a, b| {
+            (a.program, a.textures, a.material, a.mesh)
+                .cmp(&(b.program, b.textures, b.material, b.mesh))
+                .then_with(|| by_depth(a, b))
+        });
+        self.transparent.sort_by(|a, b| by_depth(b, a));
+    }
+
+    pub fn opaque(&self) -> &[DrawCommand] {
+        &self.opaque
+    }
+
+    pub fn transparent(&self) -> &[DrawCommand] {
+        &self.transparent
+    }
+}
+
+/// Splits sorted commands into runs that can be drawn with one draw call each
+pub fn batches(commands: &[DrawCommand]) -> Vec<&[DrawCommand]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    for i in 1..=commands.len() {
+        if i == commands.len() || !commands[start].can_batch_with(&commands[i]) {
+            batches.push(&commands[start..i]);
+            start = i;
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(material: usize, textures: (usize, usize), depth: f32) -> DrawCommand {
+        DrawCommand {
+            program: ProgramId::Static,
+            material,
+            textures,
+            mesh: 0,
+            transform: glm::identity(),
+            color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            joint_matrices: Vec::new(),
+            morph_weights: Vec::new(),
+            instanceable: true,
+            transparent: false,
+            depth,
+        }
+    }
+
+    #[test]
+    fn materials_sharing_textures_are_drawn_together() {
+        let mut queue = RenderQueue::new();
+        queue.submit(command(0, (2, 1), 1.0));
+        queue.submit(command(1, (3, 1), 2.0));
+        queue.submit(command(2, (2, 1), 3.0));
+        queue.submit(command(0, (2, 1), 0.5));
+        queue.sort();
+        let materials: Vec<usize> = queue.opaque().iter().map(|c| c.material).collect();
+        assert_eq!(materials, vec![0, 0, 2, 1]);
+        assert_eq!(queue.opaque()[0].depth, 0.5);
+        assert_eq!(batches(queue.opaque()).len(), 3);
+    }
+
+    #[test]
+    fn transparent_commands_go_back_to_front() {
+        let mut queue = RenderQueue::new();
+        for &depth in [1.0, 3.0, 2.0].iter() {
+            queue.submit(DrawCommand {
+                transparent: true,
+                ..command(0, (0, 1), depth)
+            });
+        }
+        queue.sort();
+        let depths: Vec<f32> = queue.transparent().iter().map(|c| c.depth).collect();
+        assert_eq!(depths, vec![3.0, 2.0, 1.0]);
+    }
+}
@@ -0,0 +1,283 @@
+//! Fixed-function GL state as plain values. Every pass describes all of the state it
+//! needs and the context only makes the calls for what differs from the current state,
+//! so nothing a pass sets leaks into the next one
+
+use gl::types::*;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareFunc {
+    Less,
+    Equal,
+    LessEqual,
+    Always,
+}
+
+impl CompareFunc {
+    fn to_gl(self) -> GLenum {
+        match self {
+            CompareFunc::Less => gl::LESS,
+            CompareFunc::Equal => gl::EQUAL,
+            CompareFunc::LessEqual => gl::LEQUAL,
+            CompareFunc::Always => gl::ALWAYS,
+        }
+    }
+}
+
+/// Faces that are skipped. Front faces are counter-clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BlendMode {
+    None,
+    /// Straight alpha
+    Alpha,
+    /// Colors already multiplied by their alpha
+    Premultiplied,
+    Additive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+}
+
+impl PolygonMode {
+    fn to_gl(self) -> GLenum {
+        match self {
+            PolygonMode::Fill => gl::FILL,
+            PolygonMode::Line => gl::LINE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Increment,
+}
+
+impl StencilOp {
+    fn to_gl(self) -> GLenum {
+        match self {
+            StencilOp::Keep => gl::KEEP,
+            StencilOp::Increment => gl::INCR,
+        }
+    }
+}
+
+/// Same test and operations for front and back faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilState {
+    pub func: CompareFunc,
+    pub reference: i32,
+    /// Bits compared by the test
+    pub read_mask: u32,
+    /// Bits the operations may change
+    pub write_mask: u32,
+    pub stencil_fail: StencilOp,
+    pub depth_fail: StencilOp,
+    pub pass: StencilOp,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        StencilState {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: !0,
+            write_mask: !0,
+            stencil_fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+/// Everything a draw needs besides the program, textures and buffers.
+/// The default is depth tested, depth written, not blended and not culled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderState {
+    /// None disables the depth test
+    pub depth_test: Option<CompareFunc>,
+    pub depth_write: bool,
+    pub cull: CullMode,
+    pub blend: BlendMode,
+    pub polygon_mode: PolygonMode,
+    /// None disables the stencil test and lets clears write every bit
+    pub stencil: Option<StencilState>,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        RenderState {
+            depth_test: Some(CompareFunc::Less),
+            depth_write: true,
+            cull: CullMode::None,
+            blend: BlendMode::None,
+            polygon_mode: PolygonMode::Fill,
+            stencil: None,
+        }
+    }
+}
+
+impl RenderState {
+    /// Closed surfaces, whose back faces are hidden anyway
+    pub fn opaque() -> Self {
+        RenderState {
+            cull: CullMode::Back,
+            ..Default::default()
+        }
+    }
+
+    /// Blended over what's already drawn, tested against but not hiding it
+    pub fn transparent() -> Self {
+        RenderState {
+            depth_write: false,
+            blend: BlendMode::Alpha,
+            ..Default::default()
+        }
+    }
+
+    /// Drawn over everything, for screen-space passes
+    pub fn overlay() -> Self {
+        RenderState {
+            depth_test: None,
+            depth_write: false,
+            blend: BlendMode::Alpha,
+            ..Default::default()
+        }
+    }
+}
+
+/// Tracks the state GL is in, so it has to see every state change.
+/// Only one should exist per GL context
+pub struct RenderContext {
+    /// None until the first apply, which sets everything
+    current: Option<RenderState>,
+    viewport: Option<(i32, i32, i32, i32)>,
+    clear_color: Option<[f32; 4]>,
+}
+
+impl RenderContext {
+    pub fn new() -> Self {
+        RenderContext {
+            current: None,
+            viewport: None,
+            clear_color: None,
+        }
+    }
+
+    /// Makes the calls needed to go from the current state to the given one
+    pub fn apply(&mut self, state: &RenderState) {
+        let current = self.current;
+        unsafe {
+            if current.map_or(true, |c| c.depth_test != state.depth_test) {
+                match state.depth_test {
+                    Some(func) => {
+                        gl::Enable(gl::DEPTH_TEST);
+                        gl::DepthFunc(func.to_gl());
+                    }
+                    None => gl::Disable(gl::DEPTH_TEST),
+                }
+            }
+            if current.map_or(true, |c| c.depth_write != state.depth_write) {
+                gl::DepthMask(gl_bool(state.depth_write));
+            }
+            if current.map_or(true, |c| c.cull != state.cull) {
+                match state.cull {
+                    CullMode::None => gl::Disable(gl::CULL_FACE),
+                    CullMode::Back => {
+                        gl::Enable(gl::CULL_FACE);
+                        gl::CullFace(gl::BACK);
+                    }
+                    CullMode::Front => {
+                        gl::Enable(gl::CULL_FACE);
+                        gl::CullFace(gl::FRONT);
+                    }
+                }
+            }
+            if current.map_or(true, |c| c.blend != state.blend) {
+                let factors = match state.blend {
+                    BlendMode::None => None,
+                    BlendMode::Alpha => Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)),
+                    BlendMode::Premultiplied => Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)),
+                    BlendMode::Additive => Some((gl::SRC_ALPHA, gl::ONE)),
+                };
+                match factors {
+                    Some((source, destination)) => {
+                        gl::Enable(gl::BLEND);
+                        gl::BlendFunc(source, destination);
+                    }
+                    None => gl::Disable(gl::BLEND),
+                }
+            }
+            if current.map_or(true, |c| c.polygon_mode != state.polygon_mode) {
+                gl::PolygonMode(gl::FRONT_AND_BACK, state.polygon_mode.to_gl());
+            }
+            if current.map_or(true, |c| c.stencil != state.stencil) {
+                match state.stencil {
+                    Some(stencil) => {
+                        gl::Enable(gl::STENCIL_TEST);
+                        gl::StencilFunc(stencil.func.to_gl(), stencil.reference, stencil.read_mask);
+                        gl::StencilMask(stencil.write_mask);
+                        gl::StencilOp(
+                            stencil.stencil_fail.to_gl(),
+                            stencil.depth_fail.to_gl(),
+                            stencil.pass.to_gl(),
+                        );
+                    }
+                    None => {
+                        gl::Disable(gl::STENCIL_TEST);
+                        gl::StencilMask(!0);
+                    }
+                }
+            }
+        }
+        self.current = Some(*state);
+    }
+
+    pub fn set_viewport(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        if self.viewport != Some((x, y, width, height)) {
+            unsafe {
+                gl::Viewport(x, y, width, height);
+            }
+            self.viewport = Some((x, y, width, height));
+        }
+    }
+
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        if self.clear_color != Some(color) {
+            unsafe {
+                gl::ClearColor(color[0], color[1], color[2], color[3]);
+            }
+            self.clear_color = Some(color);
+        }
+    }
+
+    /// Clears color, depth and stencil. The depth and stencil masks also apply to clears,
+    /// so depth writes get turned on and the stencil test off if a pass left them
+    pub fn clear(&mut self) {
+        let mut state = self.current.unwrap_or_default();
+        state.depth_write = true;
+        state.stencil = None;
+        self.apply(&state);
+        unsafe {
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+    }
+}
+
+fn gl_bool(value: bool) -> GLboolean {
+    if value {
+        gl::TRUE
+    } else {
+        gl::FALSE
+    }
+}
@@ -0,0 +1,311 @@
+use glm::{Mat4, Quat, Vec3, Vec4};
+
+use crate::model::Hierarchy;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Position of the node in Scene::nodes
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub color: Vec3,
+    pub attn_linear: f32,
+    pub attn_quadratic: f32,
+}
+
+/// Something that lives at a node's position.
+/// Meshes, materials and skins are indices into lists owned by whoever draws the scene
+#[derive(Debug, Clone)]
+pub enum Attachment {
+    Mesh {
+        mesh: usize,
+        material: usize,
+        skin: Option<usize>,
+    },
+    Light(PointLight),
+    Camera,
+}
+
+pub struct Node {
+    pub name: String,
+    pub attachments: Vec<Attachment>,
+    /// Weights of the morph targets of attached meshes.
+    /// Empty means the meshes use their default weights
+    pub morph_weights: Vec<f32>,
+    /// Multiplies the colors of attached meshes
+    pub color: Vec4,
+
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+
+    children: Vec<NodeId>,
+
+    world: Mat4,
+    dirty: bool,
+}
+
+impl Node {
+    pub fn translation(&self) -> &Vec3 {
+        &self.translation
+    }
+
+    pub fn rotation(&self) -> &Quat {
+        &self.rotation
+    }
+
+    pub fn scale(&self) -> &Vec3 {
+        &self.scale
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Cached world matrix. Only valid after Scene::update_transforms
+    pub fn world_matrix(&self) -> &Mat4 {
+        &self.world
+    }
+
+    pub fn world_position(&self) -> Vec3 {
+        glm::vec3(self.world[(0, 3)], self.world[(1, 3)], self.world[(2, 3)])
+    }
+
+    fn local_matrix(&self) -> Mat4 {
+        glm::translation(&self.translation)
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale)
+    }
+}
+
+/// Node hierarchy with local transforms and cached world matrices
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.to_owned(),
+            attachments: Vec::new(),
+            morph_weights: Vec::new(),
+            color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            translation: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+            children: Vec::new(),
+            world: glm::identity(),
+            dirty: true,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    /// Nodes without a parent
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn attach(&mut self, id: NodeId, attachment: Attachment) {
+        self.nodes[id.0].attachments.push(attachment);
+    }
+
+    pub fn set_translation(&mut self, id: NodeId, translation: Vec3) {
+        let node = &mut self.nodes[id.0];
+        node.translation = translation;
+        node.dirty = true;
+    }
+
+    pub fn set_rotation(&mut self, id: NodeId, rotation: Quat) {
+        let node = &mut self.nodes[id.0];
+        node.rotation = rotation;
+        node.dirty = true;
+    }
+
+    pub fn set_scale(&mut self, id: NodeId, scale: Vec3) {
+        let node = &mut self.nodes[id.0];
+        node.scale = scale;
+        node.dirty = true;
+    }
+
+    /// Recalculates world matrices of dirty nodes and everything below them
+    pub fn update_transforms(&mut self) {
+        let identity = glm::identity();
+        for root in self.roots.clone() {
+            self.update_subtree(root, &identity, false);
+        }
+    }
+
+    fn update_subtree(&mut self, id: NodeId, parent_world: &Mat4, parent_changed: bool) {
+        let node = &mut self.nodes[id.0];
+        let changed = parent_changed || node.dirty;
+        if changed {
+            node.world = parent_world * node.local_matrix();
+            node.dirty = false;
+        }
+        let world = node.world;
+        for i in 0..node.children.len() {
+            let child = self.nodes[id.0].children[i];
+            self.update_subtree(child, &world, changed);
+        }
+    }
+
+    /// Recreates the node hierarchy of a model under the parent.
+    /// Every primitive becomes a mesh attachment with index first_mesh + primitive number.
+    /// Returns scene nodes indexed by model node index
+    pub fn add_model(
+        &mut self,
+        hierarchy: &Hierarchy,
+        parent: Option<NodeId>,
+        layout: &ModelLayout,
+    ) -> Vec<Option<NodeId>> {
+        let mut nodes = vec![None; hierarchy.nodes.len()];
+        for &root in hierarchy.roots.iter() {
+            self.add_model_node(hierarchy, root, parent, layout, &mut nodes);
+        }
+        nodes
+    }
+
+    fn add_model_node(
+        &mut self,
+        hierarchy: &Hierarchy,
+        index: usize,
+        parent: Option<NodeId>,
+        layout: &ModelLayout,
+        nodes: &mut Vec<Option<NodeId>>,
+    ) {
+        let model_node = &hierarchy.nodes[index];
+        let id = self.add_node(&model_node.name, parent);
+        nodes[index] = Some(id);
+        let r = model_node.rotation;
+        self.set_translation(id, glm::make_vec3(&model_node.translation));
+        self.set_rotation(id, glm::quat(r[0], r[1], r[2], r[3]));
+        self.set_scale(id, glm::make_vec3(&model_node.scale));
+        self.nodes[id.0].morph_weights = model_node.morph_weights.clone();
+
+        let skin = model_node.skin.map(|skin| layout.first_skin + skin);
+        for &primitive in model_node.primitives.iter() {
+            self.attach(
+                id,
+                Attachment::Mesh {
+                    mesh: layout.first_mesh + primitive,
+                    material: layout.materials[primitive],
+                    skin,
+                },
+            );
+        }
+        if model_node.camera {
+            self.attach(id, Attachment::Camera);
+        }
+
+        for &child in model_node.children.iter() {
+            self.add_model_node(hierarchy, child, Some(id), layout, nodes);
+        }
+    }
+}
+
+/// Where the parts of a model ended up in the caller's lists
+pub struct ModelLayout<'a> {
+    /// Index of the first primitive's mesh
+    pub first_mesh: usize,
+    /// Material of every primitive
+    pub materials: &'a [usize],
+    /// Index of the model's first skin
+    pub first_skin: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(scene: &mut Scene) -> (NodeId, NodeId, NodeId) {
+        let parent = scene.add_node("parent", None);
+        let child = scene.add_node("child", Some(parent));
+        let grandchild = scene.add_node("grandchild", Some(child));
+        scene.set_translation(child, glm::vec3(0.0, 1.0, 0.0));
+        scene.set_translation(grandchild, glm::vec3(0.0, 0.0, 1.0));
+        scene.update_transforms();
+        (parent, child, grandchild)
+    }
+
+    #[test]
+    fn parent_moves_grandchild() {
+        let mut scene = Scene::new();
+        let (parent, _, grandchild) = chain(&mut scene);
+        assert_eq!(
+            scene.node(grandchild).world_position(),
+            glm::vec3(0.0, 1.0, 1.0)
+        );
+
+        scene.set_translation(parent, glm::vec3(2.0, 0.0, 0.0));
+        scene.update_transforms();
+        assert_eq!(
+            scene.node(grandchild).world_position(),
+            glm::vec3(2.0, 1.0, 1.0)
+        );
+
+        scene.set_scale(parent, glm::vec3(2.0, 2.0, 2.0));
+        scene.update_transforms();
+        assert_eq!(
+            scene.node(grandchild).world_position(),
+            glm::vec3(2.0, 2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn clean_subtrees_are_kept() {
+        let mut scene = Scene::new();
+        let (parent, child, grandchild) = chain(&mut scene);
+        let sibling = scene.add_node("sibling", Some(parent));
+        scene.update_transforms();
+
+        // Stale matrices that would be overwritten if their nodes were recomputed
+        let marker = glm::scaling(&glm::vec3(5.0, 5.0, 5.0));
+        scene.node_mut(child).world = marker;
+        scene.node_mut(grandchild).world = marker;
+        scene.node_mut(sibling).world = marker;
+        scene.update_transforms();
+        assert_eq!(scene.node(child).world_matrix(), &marker);
+        assert_eq!(scene.node(grandchild).world_matrix(), &marker);
+
+        // Only the dirty node and what's below it
+        scene.set_translation(child, glm::vec3(0.0, 3.0, 0.0));
+        scene.update_transforms();
+        assert_eq!(
+            scene.node(grandchild).world_position(),
+            glm::vec3(0.0, 3.0, 1.0)
+        );
+        assert_eq!(scene.node(sibling).world_matrix(), &marker);
+    }
+}
@@ -0,0 +1,779 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+use glm::{Quat, Vec3};
+use serde::Deserialize;
+
+use crate::animation::{AnimationPlayer, Clip, Skin};
+use crate::animator::{Animator, AnimatorDescription, AnimatorError};
+use crate::gltf_export::TextureSource;
+use crate::lod::{LodChain, LodLevel};
+use crate::material::{AlphaMode, Material};
+use crate::mesh::{Mesh, MeshData};
+use crate::model::{Hierarchy, Model};
+use crate::render_state::{BlendMode, CullMode};
+use crate::scene::{Attachment, ModelLayout, NodeId, PointLight, Scene};
+use crate::shapes;
+use crate::texture::Texture;
+
+#[derive(Debug, Fail)]
+pub enum SceneFileError {
+    #[fail(display = "I/O Error ({})", path)]
+    IoError {
+        path: String,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "Failed to parse scene {}", path)]
+    ParseError {
+        path: String,
+        #[cause]
+        inner: ron::de::Error,
+    },
+    #[fail(display = "Unknown {} '{}'", kind, name)]
+    UnknownReference { kind: &'static str, name: String },
+    #[fail(display = "More than one node is named '{}'", name)]
+    DuplicateNode { name: String },
+    #[fail(display = "Asset {} has not been loaded", path)]
+    AssetNotLoaded { path: String },
+    #[fail(display = "Levels of detail of '{}' have not been simplified", name)]
+    LodNotSimplified { name: String },
+    #[fail(display = "Joints of a skin in model '{}' are not in its scene", model)]
+    BrokenSkin { model: String },
+    #[fail(
+        display = "Model '{}' can only have simplified levels of detail",
+        model
+    )]
+    AuthoredModelLod { model: String },
+    #[fail(display = "Bad animator of node '{}'", node)]
+    AnimatorError {
+        node: String,
+        #[cause]
+        inner: AnimatorError,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, SceneFileError>;
+
+/// Root of a scene file. See assets/scenes/default.ron for an example.
+/// Maps are sorted by name, which is the order textures, materials and meshes are built in
+#[derive(Deserialize)]
+#[serde(rename = "Scene")]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub textures: BTreeMap<String, String>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub meshes: BTreeMap<String, MeshDescription>,
+    /// glTF, GLB or OBJ files
+    #[serde(default)]
+    pub models: BTreeMap<String, String>,
+    /// Levels of detail of a mesh, or of every mesh of a model
+    #[serde(default)]
+    pub lods: BTreeMap<String, Vec<LodDescription>>,
+    #[serde(default)]
+    pub nodes: Vec<NodeDescription>,
+}
+
+/// A coarser version of a mesh, either another mesh of the scene or a simplified copy:
+///
+/// ```ron
+/// lods: {
+///     "sphere": [
+///         Lod(screen_size: 0.2, ratio: 0.25),
+///         Lod(screen_size: 0.05, mesh: Some("low poly sphere")),
+///     ],
+/// },
+/// ```
+#[derive(Deserialize)]
+#[serde(rename = "Lod")]
+pub struct LodDescription {
+    /// Used once the object covers less than this fraction of the screen's height
+    pub screen_size: f32,
+    #[serde(default)]
+    pub mesh: Option<String>,
+    /// Fraction of the triangles to keep when simplifying
+    #[serde(default = "default_lod_ratio")]
+    pub ratio: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Camera")]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    pub look_at: [f32; 3],
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Material")]
+pub struct MaterialDescription {
+    #[serde(default)]
+    pub diffuse: Option<String>,
+    #[serde(default)]
+    pub specular: Option<String>,
+    #[serde(default = "default_shininess")]
+    pub shininess: f32,
+    #[serde(default)]
+    pub alpha_mode: AlphaMode,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    /// Only used when drawn transparent
+    #[serde(default)]
+    pub depth_write: bool,
+    /// None draws both sides, e.g. for leaves
+    #[serde(default = "default_cull")]
+    pub cull: CullMode,
+    /// Only used when drawn transparent, e.g. Additive for glows
+    #[serde(default = "default_blend")]
+    pub blend: BlendMode,
+}
+
+/// Procedural shapes, see the shapes module
+#[derive(Deserialize)]
+pub enum MeshDescription {
+    /// Unit cube
+    Cube,
+    Box {
+        size: f32,
+    },
+    Plane {
+        width: f32,
+        depth: f32,
+    },
+    Grid {
+        width: f32,
+        depth: f32,
+        columns: u32,
+        rows: u32,
+    },
+    Sphere {
+        radius: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+        #[serde(default = "default_rings")]
+        rings: u32,
+    },
+    Icosphere {
+        radius: f32,
+        #[serde(default = "default_subdivisions")]
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+    },
+    Torus {
+        radius: f32,
+        tube_radius: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+        #[serde(default = "default_rings")]
+        tube_segments: u32,
+    },
+    Capsule {
+        radius: f32,
+        height: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+        /// Per hemisphere
+        #[serde(default = "default_rings")]
+        rings: u32,
+    },
+    /// Another shape with a normal per triangle, for a faceted look
+    Flat(Box<MeshDescription>),
+    /// Another shape moved, turned and scaled, e.g. to put its origin at the bottom.
+    /// Mirroring scales keep the triangles facing out
+    Transformed {
+        mesh: Box<MeshDescription>,
+        #[serde(default)]
+        translation: [f32; 3],
+        /// Euler angles in degrees, applied in X, Y, Z order
+        #[serde(default)]
+        rotation: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: [f32; 3],
+    },
+}
+
+impl MeshDescription {
+    pub fn generate(&self) -> MeshData {
+        match *self {
+            MeshDescription::Cube => shapes::cube(1.0),
+            MeshDescription::Box { size } => shapes::cube(size),
+            MeshDescription::Plane { width, depth } => shapes::plane(width, depth),
+            MeshDescription::Grid {
+                width,
+                depth,
+                columns,
+                rows,
+            } => shapes::grid(width, depth, columns, rows),
+            MeshDescription::Sphere {
+                radius,
+                segments,
+                rings,
+            } => shapes::uv_sphere(radius, segments, rings),
+            MeshDescription::Icosphere {
+                radius,
+                subdivisions,
+            } => shapes::icosphere(radius, subdivisions),
+            MeshDescription::Cylinder {
+                radius,
+                height,
+                segments,
+            } => shapes::cylinder(radius, height, segments),
+            MeshDescription::Cone {
+                radius,
+                height,
+                segments,
+            } => shapes::cone(radius, height, segments),
+            MeshDescription::Torus {
+                radius,
+                tube_radius,
+                segments,
+                tube_segments,
+            } => shapes::torus(radius, tube_radius, segments, tube_segments),
+            MeshDescription::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => shapes::capsule(radius, height, segments, rings),
+            MeshDescription::Flat(ref mesh) => {
+                let mut data = mesh.generate();
+                data.generate_flat_normals();
+                data.generate_tangents();
+                data
+            }
+            MeshDescription::Transformed {
+                ref mesh,
+                translation,
+                rotation,
+                scale,
+            } => {
+                let transform = glm::translation(&glm::make_vec3(&translation))
+                    * glm::quat_to_mat4(&euler_to_quat(rotation))
+                    * glm::scaling(&glm::make_vec3(&scale));
+                let mut data = mesh.generate();
+                data.bake_transform(&transform);
+                data
+            }
+        }
+    }
+}
+
+/// Parents have to be listed before their children
+#[derive(Deserialize)]
+#[serde(rename = "Node")]
+pub struct NodeDescription {
+    pub name: String,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    /// Euler angles in degrees, applied in X, Y, Z order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+    /// Multiplies the colors of the node's mesh or model
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    #[serde(default)]
+    pub mesh: Option<String>,
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// State machine driving the model's clips. Without one the first clip just loops
+    #[serde(default)]
+    pub animator: Option<AnimatorDescription>,
+    #[serde(default)]
+    pub light: Option<LightDescription>,
+    #[serde(default)]
+    pub spin: Option<SpinDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Light")]
+pub struct LightDescription {
+    pub color: [f32; 3],
+    #[serde(default = "default_attn_linear")]
+    pub attn_linear: f32,
+    #[serde(default = "default_attn_quadratic")]
+    pub attn_quadratic: f32,
+}
+
+/// Continuous rotation around an axis, in degrees per second
+#[derive(Deserialize)]
+#[serde(rename = "Spin")]
+pub struct SpinDescription {
+    pub axis: [f32; 3],
+    pub speed: f32,
+}
+
+fn default_segments() -> u32 {
+    32
+}
+
+fn default_rings() -> u32 {
+    16
+}
+
+fn default_subdivisions() -> u32 {
+    3
+}
+
+fn default_lod_ratio() -> f32 {
+    0.5
+}
+
+fn default_shininess() -> f32 {
+    32.0
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_cull() -> CullMode {
+    CullMode::Back
+}
+
+fn default_blend() -> BlendMode {
+    BlendMode::Alpha
+}
+
+fn default_attn_linear() -> f32 {
+    0.09
+}
+
+fn default_attn_quadratic() -> f32 {
+    0.032
+}
+
+/// A node that keeps rotating
+pub struct Spinner {
+    pub node: NodeId,
+    pub axis: Vec3,
+    pub speed: f32,
+}
+
+/// Scene graph together with all the GPU resources it refers to
+pub struct LoadedScene {
+    pub scene: Scene,
+    pub meshes: Vec<Mesh>,
+    /// CPU copies of the meshes, e.g. for exporting
+    pub mesh_data: Vec<MeshData>,
+    pub textures: Vec<Texture>,
+    /// Where the image of every texture came from, for exporting
+    pub texture_sources: Vec<TextureSource>,
+    pub materials: Vec<Material>,
+    /// Levels of detail by the index of their original mesh
+    pub lods: HashMap<usize, LodChain>,
+    pub skins: Vec<Skin<NodeId>>,
+    pub players: Vec<AnimationPlayer>,
+    pub animators: Vec<Animator>,
+    pub spinners: Vec<Spinner>,
+    pub camera_position: Vec3,
+    pub camera_target: Vec3,
+}
+
+impl LoadedScene {
+    /// Rotates spinning nodes to where they should be after the given time
+    /// and advances skeletal animations
+    pub fn animate(&mut self, seconds_elapsed: f32, delta_time: f32) {
+        for spinner in self.spinners.iter() {
+            let angle = (spinner.speed * seconds_elapsed).to_radians();
+            self.scene
+                .set_rotation(spinner.node, glm::quat_angle_axis(angle, &spinner.axis));
+        }
+        for player in self.players.iter_mut() {
+            player.update(delta_time, &mut self.scene);
+        }
+        for animator in self.animators.iter_mut() {
+            animator.update(delta_time, &mut self.scene);
+        }
+    }
+}
+
+/// A model whose GPU resources are already in the scene's lists
+struct ModelEntry {
+    hierarchy: Hierarchy,
+    first_mesh: usize,
+    mesh_count: usize,
+    materials: Vec<usize>,
+    skins: Vec<Skin<usize>>,
+    clips: Vec<Clip<usize>>,
+}
+
+/// Rotation from Euler angles in degrees
+fn euler_to_quat(degrees: [f32; 3]) -> Quat {
+    let x = glm::quat_angle_axis(degrees[0].to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+    let y = glm::quat_angle_axis(degrees[1].to_radians(), &glm::vec3(0.0, 1.0, 0.0));
+    let z = glm::quat_angle_axis(degrees[2].to_radians(), &glm::vec3(0.0, 0.0, 1.0));
+    z * y * x
+}
+
+fn lookup<'a, T>(map: &'a HashMap<String, T>, kind: &'static str, name: &str) -> Result<&'a T> {
+    map.get(name)
+        .ok_or_else(|| SceneFileError::UnknownReference {
+            kind,
+            name: name.to_owned(),
+        })
+}
+
+impl SceneDescription {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| SceneFileError::IoError {
+            path: path.to_owned(),
+            inner: e,
+        })?;
+        ron::de::from_str(&text).map_err(|e| SceneFileError::ParseError {
+            path: path.to_owned(),
+            inner: e,
+        })
+    }
+
+    /// Images that have to be loaded before the scene can be built
+    pub fn texture_paths(&self) -> impl Iterator<Item = &str> {
+        self.textures.values().map(|path| path.as_str())
+    }
+
+    /// Models that have to be loaded before the scene can be built
+    pub fn model_paths(&self) -> impl Iterator<Item = &str> {
+        self.models.values().map(|path| path.as_str())
+    }
+
+    /// Fractions of the triangles kept by the levels of detail that are simplified
+    /// from the named mesh or model, in the order they're listed
+    pub fn simplified_lod_ratios(&self, name: &str) -> Vec<f32> {
+        self.lods.get(name).map_or(Vec::new(), |levels| {
+            levels
+                .iter()
+                .filter(|level| level.mesh.is_none())
+                .map(|level| level.ratio)
+                .collect()
+        })
+    }
+
+    /// Names of the models loaded from the path
+    pub fn model_names<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a str> {
+        self.models
+            .iter()
+            .filter(move |(_, model_path)| model_path.as_str() == path)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Creates the scene graph out of loaded assets, which are keyed by path.
+    /// Simplified levels of detail are keyed by the name of the mesh or model and
+    /// the index of the mesh within it, see simplified_lod_ratios.
+    /// Must be called on the GL thread
+    pub fn build(
+        &self,
+        mut loaded_textures: HashMap<String, Texture>,
+        mut loaded_models: HashMap<String, Model>,
+        mut simplified: HashMap<(String, usize), Vec<MeshData>>,
+    ) -> Result<LoadedScene> {
+        let not_loaded = |path: &str| SceneFileError::AssetNotLoaded {
+            path: path.to_owned(),
+        };
+
+        // Textures. Several names can point to the same file
+        let (white, black) = (0, 1);
+        let mut textures = vec![
+            Texture::solid_color([255, 255, 255]),
+            Texture::solid_color([0, 0, 0]),
+        ];
+        let mut texture_sources = vec![TextureSource::Generated, TextureSource::Generated];
+        let mut texture_by_path: HashMap<&str, usize> = HashMap::new();
+        let mut texture_by_name: HashMap<String, usize> = HashMap::new();
+        for (name, path) in self.textures.iter() {
+            let index = match texture_by_path.get(path.as_str()) {
+                Some(&index) => index,
+                None => {
+                    let texture = loaded_textures
+                        .remove(path)
+                        .ok_or_else(|| not_loaded(path))?;
+                    textures.push(texture);
+                    texture_sources.push(TextureSource::File(path.clone()));
+                    texture_by_path.insert(path.as_str(), textures.len() - 1);
+                    textures.len() - 1
+                }
+            };
+            texture_by_name.insert(name.clone(), index);
+        }
+
+        // Materials. Meshes without one get the default plain white material
+        let default_material = 0;
+        let mut materials = vec![Material {
+            diffuse: white,
+            specular: black,
+            shininess: default_shininess(),
+            alpha_mode: AlphaMode::Opaque,
+            opacity: default_opacity(),
+            depth_write: false,
+            cull: default_cull(),
+            blend: default_blend(),
+        }];
+        let mut material_by_name = HashMap::new();
+        for (name, description) in self.materials.iter() {
+            let diffuse = match &description.diffuse {
+                Some(texture) => *lookup(&texture_by_name, "texture", texture)?,
+                None => white,
+            };
+            let specular = match &description.specular {
+                Some(texture) => *lookup(&texture_by_name, "texture", texture)?,
+                None => black,
+            };
+            materials.push(Material {
+                diffuse,
+                specular,
+                shininess: description.shininess,
+                alpha_mode: description.alpha_mode,
+                opacity: description.opacity,
+                depth_write: description.depth_write,
+                cull: description.cull,
+                blend: description.blend,
+            });
+            material_by_name.insert(name.clone(), materials.len() - 1);
+        }
+
+        // Meshes
+        let mut meshes = Vec::new();
+        let mut mesh_data = Vec::new();
+        let mut mesh_by_name = HashMap::new();
+        for (name, description) in self.meshes.iter() {
+            let data = description.generate();
+            meshes.push(Mesh::new(&data));
+            mesh_data.push(data);
+            mesh_by_name.insert(name.clone(), meshes.len() - 1);
+        }
+
+        // Models get their own materials, one per primitive
+        let mut model_by_name = HashMap::new();
+        for (name, path) in self.models.iter() {
+            let model = loaded_models.remove(path).ok_or_else(|| not_loaded(path))?;
+            let first_mesh = meshes.len();
+            let mut model_materials = Vec::new();
+            for material in model.materials.iter() {
+                materials.push(Material {
+                    diffuse: material.diffuse.map_or(white, |i| textures.len() + i),
+                    specular: material.specular.map_or(black, |i| textures.len() + i),
+                    shininess: material.shininess.unwrap_or_else(default_shininess),
+                    alpha_mode: material.alpha_mode,
+                    opacity: material.opacity.unwrap_or_else(default_opacity),
+                    depth_write: false,
+                    cull: if material.double_sided {
+                        CullMode::None
+                    } else {
+                        CullMode::Back
+                    },
+                    blend: BlendMode::Alpha,
+                });
+                model_materials.push(materials.len() - 1);
+            }
+            let mesh_count = model.meshes.len();
+            meshes.extend(model.meshes);
+            mesh_data.extend(model.mesh_data);
+            texture_sources.extend(model.images.into_iter().map(TextureSource::Pixels));
+            textures.extend(model.textures);
+            model_by_name.insert(
+                name.clone(),
+                ModelEntry {
+                    hierarchy: model.hierarchy,
+                    first_mesh,
+                    mesh_count,
+                    materials: model_materials,
+                    skins: model.skins,
+                    clips: model.clips,
+                },
+            );
+        }
+
+        // Levels of detail
+        let mut lods = HashMap::new();
+        for (name, descriptions) in self.lods.iter() {
+            let originals = if let Some(&mesh) = mesh_by_name.get(name) {
+                mesh..mesh + 1
+            } else if let Some(entry) = model_by_name.get(name) {
+                if descriptions.iter().any(|d| d.mesh.is_some()) {
+                    return Err(SceneFileError::AuthoredModelLod {
+                        model: name.clone(),
+                    });
+                }
+                entry.first_mesh..entry.first_mesh + entry.mesh_count
+            } else {
+                return Err(SceneFileError::UnknownReference {
+                    kind: "mesh or model",
+                    name: name.clone(),
+                });
+            };
+            for (i, original) in originals.enumerate() {
+                let mut simplified_levels = simplified
+                    .remove(&(name.clone(), i))
+                    .unwrap_or_default()
+                    .into_iter();
+                let mut levels = Vec::new();
+                for description in descriptions.iter() {
+                    let mesh = match &description.mesh {
+                        Some(mesh) => *lookup(&mesh_by_name, "mesh", mesh)?,
+                        None => {
+                            let data = simplified_levels.next().ok_or_else(|| {
+                                SceneFileError::LodNotSimplified { name: name.clone() }
+                            })?;
+                            meshes.push(Mesh::new(&data));
+                            mesh_data.push(data);
+                            meshes.len() - 1
+                        }
+                    };
+                    levels.push(LodLevel {
+                        mesh,
+                        screen_size: description.screen_size,
+                    });
+                }
+                levels.sort_by(|a, b| {
+                    b.screen_size
+                        .partial_cmp(&a.screen_size)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                lods.insert(original, LodChain { levels });
+            }
+        }
+
+        // Nodes
+        let mut scene = Scene::new();
+        let mut skins = Vec::new();
+        let mut players = Vec::new();
+        let mut animators = Vec::new();
+        let mut spinners = Vec::new();
+        let mut node_by_name = HashMap::new();
+        for description in self.nodes.iter() {
+            let parent = match &description.parent {
+                Some(parent) => Some(*lookup(&node_by_name, "parent node", parent)?),
+                None => None,
+            };
+            let node = scene.add_node(&description.name, parent);
+            scene.set_translation(node, glm::make_vec3(&description.translation));
+            scene.set_rotation(node, euler_to_quat(description.rotation));
+            scene.set_scale(node, glm::make_vec3(&description.scale));
+            let color = glm::make_vec4(&description.color);
+            scene.node_mut(node).color = color;
+            if node_by_name
+                .insert(description.name.clone(), node)
+                .is_some()
+            {
+                return Err(SceneFileError::DuplicateNode {
+                    name: description.name.clone(),
+                });
+            }
+
+            if let Some(mesh) = &description.mesh {
+                let material = match &description.material {
+                    Some(material) => *lookup(&material_by_name, "material", material)?,
+                    None => default_material,
+                };
+                let mesh = *lookup(&mesh_by_name, "mesh", mesh)?;
+                scene.attach(
+                    node,
+                    Attachment::Mesh {
+                        mesh,
+                        material,
+                        skin: None,
+                    },
+                );
+            }
+            if let Some(light) = &description.light {
+                scene.attach(
+                    node,
+                    Attachment::Light(PointLight {
+                        color: glm::make_vec3(&light.color),
+                        attn_linear: light.attn_linear,
+                        attn_quadratic: light.attn_quadratic,
+                    }),
+                );
+            }
+            if let Some(model) = &description.model {
+                let entry = lookup(&model_by_name, "model", model)?;
+                let layout = ModelLayout {
+                    first_mesh: entry.first_mesh,
+                    materials: &entry.materials,
+                    first_skin: skins.len(),
+                };
+                let model_nodes = scene.add_model(&entry.hierarchy, Some(node), &layout);
+                for &model_node in model_nodes.iter().flatten() {
+                    scene.node_mut(model_node).color = color;
+                }
+
+                // Every instance of a model gets its own skeleton
+                for skin in entry.skins.iter() {
+                    let skin =
+                        skin.retarget(&model_nodes)
+                            .ok_or_else(|| SceneFileError::BrokenSkin {
+                                model: model.clone(),
+                            })?;
+                    skins.push(skin);
+                }
+                let clips: Vec<_> = entry
+                    .clips
+                    .iter()
+                    .map(|clip| clip.retarget(&model_nodes))
+                    .collect();
+                match &description.animator {
+                    Some(animator) => {
+                        let animator = Animator::new(animator, clips, &scene).map_err(|e| {
+                            SceneFileError::AnimatorError {
+                                node: description.name.clone(),
+                                inner: e,
+                            }
+                        })?;
+                        animators.push(animator);
+                    }
+                    None if !clips.is_empty() => players.push(AnimationPlayer::new(clips)),
+                    None => {}
+                }
+            }
+            if let Some(spin) = &description.spin {
+                spinners.push(Spinner {
+                    node,
+                    axis: glm::make_vec3(&spin.axis),
+                    speed: spin.speed,
+                });
+            }
+        }
+
+        Ok(LoadedScene {
+            scene,
+            meshes,
+            mesh_data,
+            textures,
+            texture_sources,
+            materials,
+            lods,
+            skins,
+            players,
+            animators,
+            spinners,
+            camera_position: glm::make_vec3(&self.camera.position),
+            camera_target: glm::make_vec3(&self.camera.look_at),
+        })
+    }
+}
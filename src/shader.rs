@@ -1,7 +1,7 @@
 use failure::Fail;
 use gl;
 use gl::types::*;
-use glm::{Mat4, Vec3};
+use glm::{Mat4, Vec2, Vec3, Vec4};
 use std::ffi::CString;
 use std::fs;
 use std::io;
@@ -42,6 +42,14 @@ impl Program {
         Ok(self)
     }
 
+    pub fn geometry_shader(self, path: &str) -> Result<Self> {
+        let shader = Shader::new(gl::GEOMETRY_SHADER, path)?;
+        unsafe {
+            gl::AttachShader(self.id, shader.id());
+        }
+        Ok(self)
+    }
+
     pub fn fragment_shader(self, path: &str) -> Result<Self> {
         let shader = Shader::new(gl::FRAGMENT_SHADER, path)?;
         unsafe {
@@ -108,6 +116,14 @@ impl Program {
         Ok(())
     }
 
+    pub fn set_vec2(&self, name: &str, vec: &Vec2) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::Uniform2fv(location, 1, vec.as_ptr());
+        }
+        Ok(())
+    }
+
     /// Sets a vec3 uniform
     pub fn set_vec3(&self, name: &str, vec: &Vec3) -> Result<()> {
         let location = self.get_uniform_location(name)?;
@@ -117,6 +133,14 @@ impl Program {
         Ok(())
     }
 
+    pub fn set_vec4(&self, name: &str, vec: &Vec4) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::Uniform4fv(location, 1, vec.as_ptr());
+        }
+        Ok(())
+    }
+
     /// Sets a vec3 uniform
     pub fn set_mat4(&self, name: &str, mat: &Mat4) -> Result<()> {
         let location = self.get_uniform_location(name)?;
@@ -126,6 +150,37 @@ impl Program {
         Ok(())
     }
 
+    /// Sets a uniform array of matrices, starting from its first element
+    pub fn set_mat4_array(&self, name: &str, mats: &[Mat4]) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::UniformMatrix4fv(
+                location,
+                mats.len() as GLint,
+                gl::FALSE,
+                mats.as_ptr() as *const GLfloat,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn set_int(&self, name: &str, value: i32) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::Uniform1i(location, value);
+        }
+        Ok(())
+    }
+
+    /// Sets a uniform array of floats, starting from its first element
+    pub fn set_float_array(&self, name: &str, values: &[f32]) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::Uniform1fv(location, values.len() as GLint, values.as_ptr());
+        }
+        Ok(())
+    }
+
     /// Sets a float uniform
     pub fn set_float(&self, name: &str, value: f32) -> Result<()> {
         let location = self.get_uniform_location(name)?;
@@ -0,0 +1,399 @@
+//! Procedural meshes. Every shape is centered on the origin with Y up, has
+//! counter-clockwise front faces and comes with normals, texture coords and tangents
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::mesh::MeshData;
+
+/// Cube with each side mapped to the whole texture, upright when seen from outside
+pub fn cube(size: f32) -> MeshData {
+    let half = size / 2.0;
+    // Normal, then the directions of the U and V texture axes on that side
+    let sides = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let mut data = MeshData::new();
+    for (normal, u, v) in sides.iter() {
+        let first = data.positions.len() as u32;
+        for &(s, t) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].iter() {
+            let (a, b) = (s * 2.0 - 1.0, t * 2.0 - 1.0);
+            data.positions.push([
+                (normal[0] + u[0] * a + v[0] * b) * half,
+                (normal[1] + u[1] * a + v[1] * b) * half,
+                (normal[2] + u[2] * a + v[2] * b) * half,
+            ]);
+            data.normals.push(*normal);
+            data.tex_coords.push([s, t]);
+        }
+        data.indices
+            .extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    data.generate_tangents();
+    data
+}
+
+/// Single quad in the XZ plane facing up
+pub fn plane(width: f32, depth: f32) -> MeshData {
+    grid(width, depth, 1, 1)
+}
+
+/// Plane split into columns along X and rows along Z. The texture covers it once,
+/// with V going towards -Z
+pub fn grid(width: f32, depth: f32, columns: u32, rows: u32) -> MeshData {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    let mut data = MeshData::new();
+    for row in 0..=rows {
+        let v = row as f32 / rows as f32;
+        for column in 0..=columns {
+            let u = column as f32 / columns as f32;
+            data.positions
+                .push([(u - 0.5) * width, 0.0, (0.5 - v) * depth]);
+            data.normals.push([0.0, 1.0, 0.0]);
+            data.tex_coords.push([u, v]);
+        }
+    }
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let d = a + columns + 1;
+            data.indices
+                .extend_from_slice(&[a, a + 1, d + 1, a, d + 1, d]);
+        }
+    }
+    data.generate_tangents();
+    data
+}
+
+/// Sphere made of segments around the Y axis and rings from pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(2);
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            ProfilePoint {
+                radius: radius * sin,
+                y: radius * cos,
+                normal: [sin, cos],
+                v: 1.0 - v,
+            }
+        })
+        .collect();
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, segments);
+    data.generate_tangents();
+    data
+}
+
+/// Sphere made of evenly sized triangles by subdividing an icosahedron.
+/// Texture coords are a spherical projection, with vertices split along the seam
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<glm::Vec3> = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|p| glm::make_vec3(p).normalize())
+    .collect();
+    #[rustfmt::skip]
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, points: &mut Vec<glm::Vec3>| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                points.push(((points[a] + points[b]) / 2.0).normalize());
+                points.len() - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut data = MeshData::new();
+    for point in points.iter() {
+        data.positions
+            .push([point.x * radius, point.y * radius, point.z * radius]);
+        data.normals.push([point.x, point.y, point.z]);
+        data.tex_coords.push([
+            0.5 + point.x.atan2(point.z) / (2.0 * PI),
+            0.5 + point.y.asin() / PI,
+        ]);
+    }
+
+    // Triangles crossing the seam get copies of their vertices on the far side,
+    // otherwise they would stretch over the whole texture
+    let mut wrapped: HashMap<usize, usize> = HashMap::new();
+    for triangle in triangles.iter_mut() {
+        let us: Vec<f32> = triangle.iter().map(|&i| data.tex_coords[i][0]).collect();
+        let (min, max) = us
+            .iter()
+            .fold((1.0f32, 0.0f32), |(min, max), &u| (min.min(u), max.max(u)));
+        if max - min <= 0.5 {
+            continue;
+        }
+        for i in triangle.iter_mut() {
+            let index = *i;
+            if data.tex_coords[index][0] < 0.5 {
+                *i = *wrapped.entry(index).or_insert_with(|| {
+                    let [u, v] = data.tex_coords[index];
+                    data.positions.push(data.positions[index]);
+                    data.normals.push(data.normals[index]);
+                    data.tex_coords.push([u + 1.0, v]);
+                    data.positions.len() - 1
+                });
+            }
+        }
+    }
+    data.indices = triangles
+        .iter()
+        .flat_map(|t| t.iter().map(|&i| i as u32))
+        .collect();
+    data.generate_tangents();
+    data
+}
+
+/// Closed cylinder along the Y axis
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half = height / 2.0;
+    let profile = [
+        ProfilePoint {
+            radius,
+            y: half,
+            normal: [1.0, 0.0],
+            v: 1.0,
+        },
+        ProfilePoint {
+            radius,
+            y: -half,
+            normal: [1.0, 0.0],
+            v: 0.0,
+        },
+    ];
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, segments);
+    cap(&mut data, radius, half, segments);
+    cap(&mut data, radius, -half, segments);
+    data.generate_tangents();
+    data
+}
+
+/// Cone along the Y axis with the tip at the top
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half = height / 2.0;
+    let slope = (height * height + radius * radius).sqrt();
+    let normal = [height / slope, radius / slope];
+    let profile = [
+        ProfilePoint {
+            radius: 0.0,
+            y: half,
+            normal,
+            v: 1.0,
+        },
+        ProfilePoint {
+            radius,
+            y: -half,
+            normal,
+            v: 0.0,
+        },
+    ];
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, segments);
+    cap(&mut data, radius, -half, segments);
+    data.generate_tangents();
+    data
+}
+
+/// Torus lying in the XZ plane. `radius` is the distance from the center to the
+/// middle of the tube
+pub fn torus(radius: f32, tube_radius: f32, segments: u32, tube_segments: u32) -> MeshData {
+    let tube_segments = tube_segments.max(3);
+    // Around the tube starting from the outside and going down,
+    // so that faces point away from the tube's center
+    let profile: Vec<ProfilePoint> = (0..=tube_segments)
+        .map(|i| {
+            let v = i as f32 / tube_segments as f32;
+            let (sin, cos) = (-v * 2.0 * PI).sin_cos();
+            ProfilePoint {
+                radius: radius + tube_radius * cos,
+                y: tube_radius * sin,
+                normal: [cos, sin],
+                v: 1.0 - v,
+            }
+        })
+        .collect();
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, segments);
+    data.generate_tangents();
+    data
+}
+
+/// Cylinder along the Y axis with hemispheres on both ends. `height` is the length
+/// of the cylindrical part, `rings` the number of rings in each hemisphere.
+/// V follows the distance along the surface so the texture isn't stretched
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let half = height / 2.0;
+    let length = PI * radius + height;
+    let mut profile = Vec::new();
+    for &(offset, first_angle, distance) in [(half, 0.0, 0.0), (-half, PI / 2.0, height)].iter() {
+        for ring in 0..=rings {
+            let angle = first_angle + ring as f32 / rings as f32 * PI / 2.0;
+            let (sin, cos) = angle.sin_cos();
+            profile.push(ProfilePoint {
+                radius: radius * sin,
+                y: offset + radius * cos,
+                normal: [sin, cos],
+                v: 1.0 - (distance + angle * radius) / length,
+            });
+        }
+    }
+    let mut data = MeshData::new();
+    lathe(&mut data, &profile, segments);
+    data.generate_tangents();
+    data
+}
+
+/// Point of a shape's outline in the XY plane, before it's spun around the Y axis
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    /// Outward normal as distance from the axis and height
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// Adds the surface swept by spinning the profile around the Y axis. The profile
+/// goes from top to bottom on the outside. U goes once around, starting at +Z.
+/// Triangles collapsed on the axis are left out
+fn lathe(data: &mut MeshData, profile: &[ProfilePoint], segments: u32) {
+    let segments = segments.max(3);
+    let first = data.positions.len() as u32;
+    for point in profile.iter() {
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let (sin, cos) = (u * 2.0 * PI).sin_cos();
+            data.positions
+                .push([point.radius * sin, point.y, point.radius * cos]);
+            data.normals.push([
+                point.normal[0] * sin,
+                point.normal[1],
+                point.normal[0] * cos,
+            ]);
+            data.tex_coords.push([u, point.v]);
+        }
+    }
+    for (row, pair) in profile.windows(2).enumerate() {
+        for segment in 0..segments {
+            let a = first + row as u32 * (segments + 1) + segment;
+            let b = a + segments + 1;
+            if pair[1].radius > 0.0 {
+                data.indices.extend_from_slice(&[a, b, b + 1]);
+            }
+            if pair[0].radius > 0.0 {
+                data.indices.extend_from_slice(&[a, b + 1, a + 1]);
+            }
+        }
+    }
+}
+
+/// Adds a disc at the given height, facing up when above the origin and down otherwise.
+/// The texture is projected from the side it faces
+fn cap(data: &mut MeshData, radius: f32, y: f32, segments: u32) {
+    let segments = segments.max(3);
+    let up = y >= 0.0;
+    let (normal, v_sign) = if up {
+        ([0.0, 1.0, 0.0], -1.0)
+    } else {
+        ([0.0, -1.0, 0.0], 1.0)
+    };
+    let center = data.positions.len() as u32;
+    data.positions.push([0.0, y, 0.0]);
+    data.normals.push(normal);
+    data.tex_coords.push([0.5, 0.5]);
+    for segment in 0..=segments {
+        let (sin, cos) = (segment as f32 / segments as f32 * 2.0 * PI).sin_cos();
+        data.positions.push([radius * sin, y, radius * cos]);
+        data.normals.push(normal);
+        data.tex_coords
+            .push([0.5 + sin / 2.0, 0.5 + v_sign * cos / 2.0]);
+    }
+    for segment in 0..segments {
+        let a = center + 1 + segment;
+        if up {
+            data.indices.extend_from_slice(&[center, a, a + 1]);
+        } else {
+            data.indices.extend_from_slice(&[center, a + 1, a]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Front faces are culled unless they're counter-clockwise seen from outside
+    #[test]
+    fn triangles_wind_counter_clockwise() {
+        let shapes = [
+            ("cube", cube(1.0)),
+            ("plane", plane(2.0, 1.0)),
+            ("grid", grid(2.0, 1.0, 3, 2)),
+            ("uv sphere", uv_sphere(1.0, 12, 6)),
+            ("icosphere", icosphere(1.0, 1)),
+            ("cylinder", cylinder(0.5, 1.0, 12)),
+            ("cone", cone(0.5, 1.0, 12)),
+            ("torus", torus(1.0, 0.25, 12, 8)),
+            ("capsule", capsule(0.5, 1.0, 12, 4)),
+        ];
+        for (name, mesh) in shapes.iter() {
+            assert!(!mesh.indices.is_empty());
+            for triangle in mesh.indices.chunks(3) {
+                let corner = |i: usize| glm::make_vec3(&mesh.positions[triangle[i] as usize]);
+                let face_normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+                if face_normal.norm() < 1e-6 {
+                    continue;
+                }
+                let normal = triangle.iter().fold(glm::vec3(0.0, 0.0, 0.0), |sum, &i| {
+                    sum + glm::make_vec3(&mesh.normals[i as usize])
+                });
+                assert!(
+                    face_normal.dot(&normal) > 0.0,
+                    "{} triangle {:?} winds clockwise",
+                    name,
+                    triangle
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Mesh simplification by edge collapse, cheapest first by quadric error
+//! (Garland and Heckbert). Vertices only ever move onto a neighbour, so every
+//! attribute stays valid. Vertices on open edges, which include seams in texture
+//! coords, never move so that no cracks appear
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::mesh::MeshData;
+
+type Point = [f64; 3];
+
+/// Cosine of the largest rotation a collapse may give a triangle. Many small
+/// rotations can add up to a fold otherwise
+const MAX_NORMAL_CHANGE: f64 = 0.5;
+
+/// Sum of squared distances to a set of planes, as the upper half of a symmetric 4x4 matrix
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Plane ax + by + cz + d = 0 with a unit normal
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Self {
+        Quadric([
+            a * a * weight,
+            a * b * weight,
+            a * c * weight,
+            a * d * weight,
+            b * b * weight,
+            b * c * weight,
+            b * d * weight,
+            c * c * weight,
+            c * d * weight,
+            d * d * weight,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (s, o) in sum.0.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        sum
+    }
+
+    fn error(&self, p: &Point) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = *p;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+/// Moving `from` onto `to`. Outdated once either vertex changes
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed so that the heap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn sub(a: &Point, b: &Point) -> Point {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: &Point, b: &Point) -> Point {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: &Point, b: &Point) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn norm(a: &Point) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn triangle_normal(positions: &[Point], triangle: &[usize; 3]) -> Point {
+    let [a, b, c] = *triangle;
+    cross(
+        &sub(&positions[b], &positions[a]),
+        &sub(&positions[c], &positions[a]),
+    )
+}
+
+impl MeshData {
+    /// Copy with about `ratio` of the triangles. Ends up with more when every
+    /// remaining collapse would flip a triangle or move an open edge
+    pub fn simplified(&self, ratio: f32) -> MeshData {
+        let mut triangles = self.triangles();
+        let target = (triangles.len() as f32 * ratio.max(0.0).min(1.0)) as usize;
+        let positions: Vec<Point> = self
+            .positions
+            .iter()
+            .map(|p| [f64::from(p[0]), f64::from(p[1]), f64::from(p[2])])
+            .collect();
+
+        // Every vertex starts with the planes of its triangles, weighted by their area
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); positions.len()];
+        let mut edge_triangles: HashMap<(usize, usize), usize> = HashMap::new();
+        for (t, triangle) in triangles.iter().enumerate() {
+            let normal = triangle_normal(&positions, triangle);
+            let length = norm(&normal);
+            if length > 0.0 {
+                let n = [normal[0] / length, normal[1] / length, normal[2] / length];
+                let d = -dot(&n, &positions[triangle[0]]);
+                let plane = Quadric::from_plane(n[0], n[1], n[2], d, length / 2.0);
+                for &v in triangle.iter() {
+                    quadrics[v] = quadrics[v].add(&plane);
+                }
+            }
+            for (i, &v) in triangle.iter().enumerate() {
+                vertex_triangles[v].push(t);
+                let w = triangle[(i + 1) % 3];
+                *edge_triangles.entry((v.min(w), v.max(w))).or_insert(0) += 1;
+            }
+        }
+        let mut locked = vec![false; positions.len()];
+        for (&(a, b), &count) in edge_triangles.iter() {
+            if count == 1 {
+                locked[a] = true;
+                locked[b] = true;
+            }
+        }
+
+        let mut versions = vec![0u32; positions.len()];
+        let mut heap = BinaryHeap::new();
+        let collapse = |from: usize, to: usize, quadrics: &[Quadric], versions: &[u32]| Collapse {
+            cost: quadrics[from].add(&quadrics[to]).error(&positions[to]),
+            from,
+            to,
+            from_version: versions[from],
+            to_version: versions[to],
+        };
+        for &(a, b) in edge_triangles.keys() {
+            if !locked[a] {
+                heap.push(collapse(a, b, &quadrics, &versions));
+            }
+            if !locked[b] {
+                heap.push(collapse(b, a, &quadrics, &versions));
+            }
+        }
+
+        let mut removed_vertices = vec![false; positions.len()];
+        let mut removed_triangles = vec![false; triangles.len()];
+        let mut live = triangles.len();
+        while live > target {
+            let Collapse {
+                from,
+                to,
+                from_version,
+                to_version,
+                ..
+            } = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if removed_vertices[from]
+                || removed_vertices[to]
+                || versions[from] != from_version
+                || versions[to] != to_version
+            {
+                continue;
+            }
+
+            // Triangles that stay must keep facing roughly the same way
+            let flips = vertex_triangles[from].iter().any(|&t| {
+                let triangle = triangles[t];
+                if removed_triangles[t] || triangle.contains(&to) {
+                    return false;
+                }
+                let moved = [
+                    if triangle[0] == from { to } else { triangle[0] },
+                    if triangle[1] == from { to } else { triangle[1] },
+                    if triangle[2] == from { to } else { triangle[2] },
+                ];
+                let before = triangle_normal(&positions, &triangle);
+                let after = triangle_normal(&positions, &moved);
+                dot(&before, &after) <= MAX_NORMAL_CHANGE * norm(&before) * norm(&after)
+            });
+            if flips {
+                continue;
+            }
+
+            for t in std::mem::replace(&mut vertex_triangles[from], Vec::new()) {
+                if removed_triangles[t] {
+                    continue;
+                }
+                if triangles[t].contains(&to) {
+                    removed_triangles[t] = true;
+                    live -= 1;
+                } else {
+                    for v in triangles[t].iter_mut() {
+                        if *v == from {
+                            *v = to;
+                        }
+                    }
+                    vertex_triangles[to].push(t);
+                }
+            }
+            removed_vertices[from] = true;
+            quadrics[to] = quadrics[to].add(&quadrics[from]);
+            versions[to] += 1;
+
+            let mut neighbours: Vec<usize> = vertex_triangles[to]
+                .iter()
+                .filter(|&&t| !removed_triangles[t])
+                .flat_map(|&t| triangles[t].to_vec())
+                .filter(|&v| v != to)
+                .collect();
+            neighbours.sort();
+            neighbours.dedup();
+            for neighbour in neighbours {
+                if !locked[to] {
+                    heap.push(collapse(to, neighbour, &quadrics, &versions));
+                }
+                if !locked[neighbour] {
+                    heap.push(collapse(neighbour, to, &quadrics, &versions));
+                }
+            }
+        }
+
+        // Keep the vertices still in use, in their original order
+        let mut new_index = vec![None; positions.len()];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(live * 3);
+        for (t, triangle) in triangles.iter().enumerate() {
+            if removed_triangles[t] {
+                continue;
+            }
+            for &v in triangle.iter() {
+                let index = *new_index[v].get_or_insert_with(|| {
+                    vertices.push(v);
+                    vertices.len() - 1
+                });
+                indices.push(index as u32);
+            }
+        }
+        let mut simplified = self.select_vertices(&vertices);
+        simplified.indices = indices;
+        simplified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes;
+
+    fn bits(p: &[f32; 3]) -> [u32; 3] {
+        [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+    }
+
+    /// Icosphere without texture coords, so that the copies along its seam weld together
+    fn closed_sphere() -> MeshData {
+        let mut sphere = shapes::icosphere(1.0, 3);
+        sphere.tex_coords.clear();
+        sphere.tangents.clear();
+        sphere.normals = sphere.positions.clone();
+        sphere.weld(0.0);
+        sphere
+    }
+
+    #[test]
+    fn closed_meshes_reach_the_target() {
+        let sphere = closed_sphere();
+        let triangles = sphere.triangles().len();
+        assert_eq!(triangles, 20 * 64);
+        for &ratio in [0.5, 0.25, 0.1].iter() {
+            let target = (triangles as f32 * ratio) as usize;
+            let simplified = sphere.simplified(ratio).triangles().len();
+            // Every collapse removes two triangles
+            assert!(simplified <= target && simplified + 2 >= target);
+        }
+    }
+
+    #[test]
+    fn simplified_vertices_are_original_vertices() {
+        let sphere = closed_sphere();
+        let original: Vec<[u32; 3]> = sphere.positions.iter().map(bits).collect();
+        let simplified = sphere.simplified(0.25);
+        assert!(simplified
+            .positions
+            .iter()
+            .all(|p| original.contains(&bits(p))));
+    }
+
+    #[test]
+    fn open_edges_stay_fixed() {
+        let grid = shapes::grid(1.0, 1.0, 10, 10);
+        let simplified = grid.simplified(0.1);
+        let kept: Vec<[u32; 3]> = simplified.positions.iter().map(bits).collect();
+        let border = grid
+            .positions
+            .iter()
+            .filter(|p| p[0].abs() == 0.5 || p[2].abs() == 0.5);
+        for position in border {
+            assert!(kept.contains(&bits(position)));
+        }
+        assert!(simplified.positions.len() < grid.positions.len());
+    }
+
+    #[test]
+    fn seams_in_texture_coords_stay_fixed() {
+        let sphere = shapes::icosphere(1.0, 3);
+        let mut copies: HashMap<[u32; 3], usize> = HashMap::new();
+        for position in sphere.positions.iter() {
+            *copies.entry(bits(position)).or_insert(0) += 1;
+        }
+        let simplified = sphere.simplified(0.25);
+        assert!(simplified.triangles().len() < sphere.triangles().len());
+        let mut kept: HashMap<[u32; 3], usize> = HashMap::new();
+        for position in simplified.positions.iter() {
+            *kept.entry(bits(position)).or_insert(0) += 1;
+        }
+        for (position, &count) in copies.iter().filter(|(_, &count)| count > 1) {
+            assert_eq!(kept.get(position), Some(&count));
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Screen-space text. Glyphs of a TrueType font are rasterised once into an atlas
+//! and strings are drawn as quads cut out of it, all queued text in one call.
+//! Solid rectangles, e.g. backgrounds, share the call through a white texel
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use glm::Vec4;
+use rusttype::{point, Font, Scale};
+
+use crate::buffers::{RingBuffer, VertexArray, VertexBuffer};
+use crate::render_state::{RenderContext, RenderState};
+use crate::shader::{self, Program, ShaderError};
+use crate::texture::{Image, Texture};
+
+#[derive(Debug, Fail)]
+pub enum TextError {
+    #[fail(display = "I/O Error ({})", path)]
+    IoError {
+        path: String,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "Can't read font {}", path)]
+    FontError {
+        path: String,
+        #[cause]
+        inner: rusttype::Error,
+    },
+    #[fail(display = "Can't create the text shader")]
+    ShaderError {
+        #[cause]
+        inner: ShaderError,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, TextError>;
+
+/// Printable ASCII. Other characters are drawn as REPLACEMENT
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = b'~';
+const REPLACEMENT: char = '?';
+
+const ATLAS_WIDTH: i32 = 512;
+/// Empty pixels around glyphs so that filtering doesn't pick up their neighbours.
+/// The texel in the top left corner is white, for rectangles
+const PADDING: i32 = 1;
+
+/// Position in pixels, texture coords and color
+const STRIDE: usize = 8;
+
+/// Vertices the mapped buffer has room for each frame, about 5000 glyphs.
+/// More go through the streamed one
+const MAPPED_VERTICES: usize = 1 << 15;
+
+/// Where a glyph is in the atlas and where it goes relative to the pen
+#[derive(Debug, Clone, Copy)]
+struct GlyphInfo {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    /// From the pen on the baseline to the top left corner, in pixels
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32,
+}
+
+pub struct TextRenderer {
+    program: Program,
+    vao: VertexArray,
+    vbo: VertexBuffer,
+    /// Where persistent mapping is available, with its own vertex array
+    mapped: Option<(VertexArray, RingBuffer)>,
+    atlas: Texture,
+    glyphs: HashMap<char, GlyphInfo>,
+    /// Center of the white texel
+    white_uv: [f32; 2],
+    ascent: f32,
+    /// Baseline to baseline
+    line_height: f32,
+    vertices: Vec<f32>,
+}
+
+impl TextRenderer {
+    /// Rasterises the font with glyphs `pixel_size` pixels high
+    pub fn new(font_path: &str, pixel_size: f32) -> Result<Self> {
+        let bytes = fs::read(font_path).map_err(|e| TextError::IoError {
+            path: font_path.to_owned(),
+            inner: e,
+        })?;
+        let font = Font::from_bytes(bytes).map_err(|e| TextError::FontError {
+            path: font_path.to_owned(),
+            inner: e,
+        })?;
+        let scale = Scale::uniform(pixel_size);
+        let v_metrics = font.v_metrics(scale);
+
+        // Rows of glyphs, each as high as its highest glyph
+        let glyphs: Vec<_> = (FIRST_CHAR..=LAST_CHAR)
+            .map(|c| {
+                let glyph = font
+                    .glyph(c as char)
+                    .scaled(scale)
+                    .positioned(point(0.0, 0.0));
+                (c as char, glyph)
+            })
+            .collect();
+        let mut placements = Vec::with_capacity(glyphs.len());
+        let (mut x, mut y, mut row_height) = (PADDING, PADDING, 0);
+        for (_, glyph) in glyphs.iter() {
+            let bounds = match glyph.pixel_bounding_box() {
+                Some(bounds) => bounds,
+                None => {
+                    placements.push(None);
+                    continue;
+                }
+            };
+            if x + bounds.width() + PADDING > ATLAS_WIDTH {
+                x = PADDING;
+                y += row_height + PADDING;
+                row_height = 0;
+            }
+            placements.push(Some((x, y)));
+            x += bounds.width() + PADDING;
+            row_height = row_height.max(bounds.height());
+        }
+        let atlas_height = ((y + row_height + PADDING) as u32).next_power_of_two() as i32;
+
+        let mut pixels = vec![0u8; (ATLAS_WIDTH * atlas_height) as usize];
+        pixels[0] = 255;
+        let mut glyph_infos = HashMap::new();
+        for ((c, glyph), placement) in glyphs.iter().zip(placements) {
+            let advance = glyph.unpositioned().h_metrics().advance_width;
+            let (bounds, (x, y)) = match (glyph.pixel_bounding_box(), placement) {
+                (Some(bounds), Some(placement)) => (bounds, placement),
+                _ => {
+                    glyph_infos.insert(
+                        *c,
+                        GlyphInfo {
+                            uv_min: [0.0, 0.0],
+                            uv_max: [0.0, 0.0],
+                            offset: [0.0, 0.0],
+                            size: [0.0, 0.0],
+                            advance,
+                        },
+                    );
+                    continue;
+                }
+            };
+            glyph.draw(|gx, gy, coverage| {
+                let index = (y + gy as i32) * ATLAS_WIDTH + x + gx as i32;
+                pixels[index as usize] = (coverage * 255.0).round() as u8;
+            });
+            let (width, height) = (bounds.width(), bounds.height());
+            glyph_infos.insert(
+                *c,
+                GlyphInfo {
+                    uv_min: [
+                        x as f32 / ATLAS_WIDTH as f32,
+                        y as f32 / atlas_height as f32,
+                    ],
+                    uv_max: [
+                        (x + width) as f32 / ATLAS_WIDTH as f32,
+                        (y + height) as f32 / atlas_height as f32,
+                    ],
+                    offset: [bounds.min.x as f32, bounds.min.y as f32],
+                    size: [width as f32, height as f32],
+                    advance,
+                },
+            );
+        }
+
+        // Rows go to the texture top first, so v grows downwards like screen y
+        let atlas = Texture::new().set_default_parameters().set_level(
+            0,
+            &Image {
+                width: ATLAS_WIDTH as u32,
+                height: atlas_height as u32,
+                channels: 1,
+                data: pixels,
+            },
+        );
+
+        let program = Program::new()
+            .vertex_shader("assets/shaders/text/text.vert")
+            .and_then(|p| p.fragment_shader("assets/shaders/text/text.frag"))
+            .and_then(|p| p.link())
+            .map_err(|e| TextError::ShaderError { inner: e })?;
+        program.set_used();
+        program
+            .set_texture_unit("atlas", 0)
+            .map_err(|e| TextError::ShaderError { inner: e })?;
+
+        let vbo = VertexBuffer::new();
+        let vao = VertexArray::new();
+        vbo.bind();
+        vao.bind();
+        vao.set_attrib(0, 2, STRIDE, 0);
+        vao.set_attrib(1, 2, STRIDE, 2);
+        vao.set_attrib(2, 4, STRIDE, 4);
+        vao.unbind();
+        vbo.unbind();
+
+        let mapped = RingBuffer::new(MAPPED_VERTICES * STRIDE).map(|ring| {
+            let vao = VertexArray::new();
+            ring.bind();
+            vao.bind();
+            vao.set_attrib(0, 2, STRIDE, 0);
+            vao.set_attrib(1, 2, STRIDE, 2);
+            vao.set_attrib(2, 4, STRIDE, 4);
+            vao.unbind();
+            ring.unbind();
+            (vao, ring)
+        });
+
+        Ok(TextRenderer {
+            program,
+            vao,
+            vbo,
+            mapped,
+            atlas,
+            glyphs: glyph_infos,
+            white_uv: [0.5 / ATLAS_WIDTH as f32, 0.5 / atlas_height as f32],
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+            vertices: Vec::new(),
+        })
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    fn glyph(&self, c: char) -> Option<&GlyphInfo> {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.glyphs.get(&REPLACEMENT))
+    }
+
+    /// Width and height in pixels
+    pub fn measure(&self, text: &str) -> (f32, f32) {
+        let (mut width, mut lines) = (0.0f32, 1);
+        let mut line_width = 0.0;
+        for c in text.chars() {
+            if c == '\n' {
+                lines += 1;
+                line_width = 0.0;
+                continue;
+            }
+            line_width += self.glyph(c).map_or(0.0, |glyph| glyph.advance);
+            width = width.max(line_width);
+        }
+        (width, lines as f32 * self.line_height)
+    }
+
+    /// Queues text with its top left corner at the position, in pixels from the top
+    /// left of the screen. Every newline starts a new line
+    pub fn text(&mut self, x: f32, y: f32, text: &str, color: &Vec4) {
+        let (mut pen, mut baseline) = (x, y + self.ascent);
+        for c in text.chars() {
+            if c == '\n' {
+                pen = x;
+                baseline += self.line_height;
+                continue;
+            }
+            let glyph = match self.glyph(c) {
+                Some(glyph) => *glyph,
+                None => continue,
+            };
+            if glyph.size[0] > 0.0 {
+                // Whole pixels keep the glyphs sharp
+                let left = (pen + glyph.offset[0]).round();
+                let top = (baseline + glyph.offset[1]).round();
+                let (right, bottom) = (left + glyph.size[0], top + glyph.size[1]);
+                self.quad(
+                    [left, top],
+                    [right, bottom],
+                    glyph.uv_min,
+                    glyph.uv_max,
+                    color,
+                );
+            }
+            pen += glyph.advance;
+        }
+    }
+
+    /// Queues a solid rectangle. Drawn in order with the text
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: &Vec4) {
+        let uv = self.white_uv;
+        self.quad([x, y], [x + width, y + height], uv, uv, color);
+    }
+
+    fn quad(
+        &mut self,
+        min: [f32; 2],
+        max: [f32; 2],
+        uv_min: [f32; 2],
+        uv_max: [f32; 2],
+        color: &Vec4,
+    ) {
+        let corners = [
+            (min[0], min[1], uv_min[0], uv_min[1]),
+            (min[0], max[1], uv_min[0], uv_max[1]),
+            (max[0], max[1], uv_max[0], uv_max[1]),
+            (min[0], min[1], uv_min[0], uv_min[1]),
+            (max[0], max[1], uv_max[0], uv_max[1]),
+            (max[0], min[1], uv_max[0], uv_min[1]),
+        ];
+        for &(x, y, u, v) in corners.iter() {
+            self.vertices.extend_from_slice(&[x, y, u, v]);
+            self.vertices.extend_from_slice(color.as_slice());
+        }
+    }
+
+    /// Draws the text queued since the last call over everything else
+    pub fn draw(
+        &mut self,
+        render_context: &mut RenderContext,
+        screen_width: u32,
+        screen_height: u32,
+    ) -> shader::Result<()> {
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+        render_context.apply(&RenderState::overlay());
+        self.program.set_used();
+        self.program.set_vec2(
+            "screen_size",
+            &glm::vec2(screen_width as f32, screen_height as f32),
+        )?;
+        self.atlas.bind(0);
+        let mut drawn = false;
+        if let Some((vao, ring)) = &mut self.mapped {
+            if let Some(first) = ring.push(&self.vertices, STRIDE) {
+                vao.bind();
+                ring.draw_triangles(first, self.vertices.len() / STRIDE);
+                drawn = true;
+            }
+            ring.next_segment();
+        }
+        if !drawn {
+            self.vao.bind();
+            self.vbo.bind();
+            self.vbo.set_stream_data(&self.vertices, STRIDE);
+            self.vbo.draw_triangles();
+            self.vbo.unbind();
+        }
+        self.vao.unbind();
+        self.vertices.clear();
+        Ok(())
+    }
+}
@@ -1,35 +1,127 @@
 use gl::types::*;
 use stb_image::image::{self, LoadResult};
 
+use crate::dds::{self, DdsFormat, DdsImage};
+use crate::extensions;
+
 #[derive(Debug, Fail)]
 pub enum TextureError {
     #[fail(display = "Image format F32 is not supported")]
     FormatNotSupported,
     #[fail(display = "Cannot load texture image: {}", msg)]
     LoadError { msg: String },
+    #[fail(display = "All layers must be {}x{}x{}", width, height, channels)]
+    LayerMismatch {
+        width: u32,
+        height: u32,
+        channels: u32,
+    },
+    #[fail(display = "Expected {} bytes of volume data, got {}", expected, actual)]
+    VolumeSizeMismatch { expected: usize, actual: usize },
+    #[fail(
+        display = "{:?} is not supported by the driver and can't be decoded",
+        format
+    )]
+    CompressedFormatNotSupported { format: DdsFormat },
+    #[fail(display = "Cannot load DDS texture")]
+    DdsError {
+        #[cause]
+        inner: dds::DdsError,
+    },
+}
+
+/// Decoded pixels, ready to be sent to the GPU
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u32,
+    pub data: Vec<u8>,
+}
+
+/// Loads an image from disk as RGBA, bottom row first as GL expects. Images without
+/// alpha get an opaque one. Doesn't touch GL so can be called from any thread
+pub fn decode_image(path: &str) -> Result<Image, TextureError> {
+    // Rows are flipped here rather than with stbi_set_flip_vertically_on_load,
+    // which sets a flag shared by all threads
+    match image::load_with_depth(path, 4, false) {
+        LoadResult::ImageU8(mut image) => {
+            flip_rows(&mut image.data, image.width * image.depth);
+            Ok(Image {
+                width: image.width as u32,
+                height: image.height as u32,
+                channels: image.depth as u32,
+                data: image.data,
+            })
+        }
+        LoadResult::ImageF32(_) => Err(TextureError::FormatNotSupported),
+        LoadResult::Error(msg) => Err(TextureError::LoadError { msg }),
+    }
+}
+
+/// Decodes a PNG or JPEG held in memory, keeping its channels and its top row
+/// first as glTF expects. Doesn't touch GL
+pub fn decode_image_data(bytes: &[u8]) -> Result<Image, TextureError> {
+    match image::load_from_memory_with_depth(bytes, 0, false) {
+        LoadResult::ImageU8(image) => Ok(Image {
+            width: image.width as u32,
+            height: image.height as u32,
+            channels: image.depth as u32,
+            data: image.data,
+        }),
+        LoadResult::ImageF32(_) => Err(TextureError::FormatNotSupported),
+        LoadResult::Error(msg) => Err(TextureError::LoadError { msg }),
+    }
+}
+
+/// Reverses the order of rows that are `row_size` bytes long
+fn flip_rows(data: &mut [u8], row_size: usize) {
+    if row_size == 0 {
+        return;
+    }
+    let rows = data.len() / row_size;
+    for y in 0..rows / 2 {
+        let (top, bottom) = data.split_at_mut((rows - 1 - y) * row_size);
+        top[y * row_size..(y + 1) * row_size].swap_with_slice(&mut bottom[..row_size]);
+    }
 }
 
 pub struct Texture {
     id: GLuint,
+    target: GLenum,
 }
 
 impl Texture {
+    /// Creates a regular 2D texture
     pub fn new() -> Self {
+        Texture::with_target(gl::TEXTURE_2D)
+    }
+
+    /// Creates a TEXTURE_2D_ARRAY, e.g. for tile atlases
+    pub fn new_array() -> Self {
+        Texture::with_target(gl::TEXTURE_2D_ARRAY)
+    }
+
+    /// Creates a TEXTURE_3D, e.g. for volume data
+    pub fn new_3d() -> Self {
+        Texture::with_target(gl::TEXTURE_3D)
+    }
+
+    fn with_target(target: GLenum) -> Self {
         let mut id: GLuint = 0;
         unsafe {
             gl::GenTextures(1, &mut id);
         }
-        Texture { id }
+        Texture { id, target }
     }
 
     pub fn bind(&self, unit: i32) {
         unsafe {
             gl::ActiveTexture(Texture::unit_to_gl_const(unit));
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::BindTexture(self.target, self.id);
         }
     }
 
-    fn unit_to_gl_const(unit: i32) -> GLenum {
+    pub(crate) fn unit_to_gl_const(unit: i32) -> GLenum {
         match unit {
             0 => gl::TEXTURE0,
             1 => gl::TEXTURE1,
@@ -51,46 +143,334 @@ impl Texture {
         }
     }
 
+    fn format_for_channels(channels: u32) -> GLenum {
+        match channels {
+            1 => gl::RED,
+            2 => gl::RG,
+            3 => gl::RGB,
+            _ => gl::RGBA,
+        }
+    }
+
     pub fn set_default_parameters(self) -> Self {
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::BindTexture(self.target, self.id);
+            gl::TexParameteri(self.target, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+            gl::TexParameteri(self.target, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+            if self.target == gl::TEXTURE_3D {
+                gl::TexParameteri(self.target, gl::TEXTURE_WRAP_R, gl::REPEAT as GLint);
+            }
+            gl::TexParameteri(self.target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(self.target, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
         }
         self
     }
 
-    pub fn load_image(self, path: &str) -> Result<Self, TextureError> {
+    /// Use trilinear filtering. Only makes sense if all mip levels are present
+    pub fn set_mipmap_filtering(self) -> Self {
         unsafe {
-            stb_image::stb_image::bindgen::stbi_set_flip_vertically_on_load(1);
+            gl::BindTexture(self.target, self.id);
+            gl::TexParameteri(
+                self.target,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR_MIPMAP_LINEAR as GLint,
+            );
         }
+        self
+    }
+
+    /// Limits the mip chain to levels 0..=max_level,
+    /// so that a partial precomputed chain is still complete
+    pub fn set_max_level(self, max_level: i32) -> Self {
+        unsafe {
+            gl::BindTexture(self.target, self.id);
+            gl::TexParameteri(self.target, gl::TEXTURE_BASE_LEVEL, 0);
+            gl::TexParameteri(self.target, gl::TEXTURE_MAX_LEVEL, max_level);
+        }
+        self
+    }
+
+    /// A 1x1 texture, e.g. for materials that don't have a map
+    pub fn solid_color(color: [u8; 3]) -> Self {
+        let img = Image {
+            width: 1,
+            height: 1,
+            channels: 3,
+            data: color.to_vec(),
+        };
+        Texture::new().set_default_parameters().set_image(&img)
+    }
 
-        // Load image from disk
-        let img = match image::load_with_depth(path, 3, false) {
-            LoadResult::ImageU8(image) => Ok(image),
-            LoadResult::ImageF32(_) => Err(TextureError::FormatNotSupported),
-            LoadResult::Error(msg) => Err(TextureError::LoadError { msg }),
-        }?;
+    /// Sends pixels to GPU and generates mipmaps
+    pub fn set_image(self, img: &Image) -> Self {
+        self.set_level(0, img).generate_mipmaps()
+    }
 
-        // Send pixels to GPU
+    /// Uploads a single mip level of a 2D texture without touching the others
+    pub fn set_level(self, level: i32, img: &Image) -> Self {
+        assert_eq!(self.target, gl::TEXTURE_2D, "set_level needs a 2D texture");
+        let format = Texture::format_for_channels(img.channels);
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::BindTexture(self.target, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGB as GLint,
+                self.target,
+                level,
+                format as GLint,
                 img.width as GLint,
                 img.height as GLint,
                 0,
-                gl::RGB,
+                format,
                 gl::UNSIGNED_BYTE,
                 img.data.as_ptr() as *const std::ffi::c_void,
             );
-            gl::GenerateMipmap(gl::TEXTURE_2D);
         }
+        self
+    }
+
+    /// Uploads one mip level of a texture array, one image per layer.
+    /// All layers must have the same size and number of channels
+    pub fn set_layers(self, level: i32, layers: &[Image]) -> Result<Self, TextureError> {
+        assert_eq!(
+            self.target,
+            gl::TEXTURE_2D_ARRAY,
+            "set_layers needs a texture array"
+        );
+        let (width, height, channels) = match layers.first() {
+            Some(first) => (first.width, first.height, first.channels),
+            None => return Ok(self),
+        };
+        let data = stack_layers(layers)?;
 
+        let format = Texture::format_for_channels(channels);
+        unsafe {
+            gl::BindTexture(self.target, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage3D(
+                self.target,
+                level,
+                format as GLint,
+                width as GLint,
+                height as GLint,
+                layers.len() as GLint,
+                0,
+                format,
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const std::ffi::c_void,
+            );
+        }
         Ok(self)
     }
+
+    /// Uploads one mip level of a 3D texture.
+    /// Data is expected as depth slices of width x height pixels each
+    pub fn set_volume(
+        self,
+        level: i32,
+        (width, height, depth): (u32, u32, u32),
+        channels: u32,
+        data: &[u8],
+    ) -> Result<Self, TextureError> {
+        assert_eq!(self.target, gl::TEXTURE_3D, "set_volume needs a 3D texture");
+        check_volume_size((width, height, depth), channels, data)?;
+
+        let format = Texture::format_for_channels(channels);
+        unsafe {
+            gl::BindTexture(self.target, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage3D(
+                self.target,
+                level,
+                format as GLint,
+                width as GLint,
+                height as GLint,
+                depth as GLint,
+                0,
+                format,
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const std::ffi::c_void,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Uploads a precomputed mip chain. Block-compressed levels are sent as is
+    /// if the driver supports the format, otherwise they're decoded on the CPU
+    pub fn set_dds(mut self, image: &DdsImage) -> Result<Self, TextureError> {
+        if image.levels.is_empty() {
+            return Err(TextureError::DdsError {
+                inner: dds::DdsError::NoLevels,
+            });
+        }
+        let s3tc = || extensions::is_supported("GL_EXT_texture_compression_s3tc");
+        let bptc = || extensions::is_supported("GL_ARB_texture_compression_bptc");
+        let gpu_format = match image.format {
+            DdsFormat::Bc1 if s3tc() => Some(gl::COMPRESSED_RGBA_S3TC_DXT1_EXT),
+            DdsFormat::Bc2 if s3tc() => Some(gl::COMPRESSED_RGBA_S3TC_DXT3_EXT),
+            DdsFormat::Bc3 if s3tc() => Some(gl::COMPRESSED_RGBA_S3TC_DXT5_EXT),
+            // RGTC is core since GL 3.0
+            DdsFormat::Bc4 => Some(gl::COMPRESSED_RED_RGTC1),
+            DdsFormat::Bc5 => Some(gl::COMPRESSED_RG_RGTC2),
+            DdsFormat::Bc6h if bptc() => Some(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT_ARB),
+            DdsFormat::Bc7 if bptc() => Some(gl::COMPRESSED_RGBA_BPTC_UNORM_ARB),
+            _ => None,
+        };
+
+        assert_eq!(self.target, gl::TEXTURE_2D, "set_dds needs a 2D texture");
+        for (level, data) in image.levels.iter().enumerate() {
+            unsafe {
+                gl::BindTexture(self.target, self.id);
+            }
+            match gpu_format {
+                Some(format) => unsafe {
+                    gl::CompressedTexImage2D(
+                        self.target,
+                        level as GLint,
+                        format,
+                        data.width as GLint,
+                        data.height as GLint,
+                        0,
+                        data.data.len() as GLsizei,
+                        data.data.as_ptr() as *const std::ffi::c_void,
+                    );
+                },
+                None => {
+                    let pixels = dds::decompress(image.format, data).ok_or(
+                        TextureError::CompressedFormatNotSupported {
+                            format: image.format,
+                        },
+                    )?;
+                    let img = Image {
+                        width: data.width,
+                        height: data.height,
+                        channels: 4,
+                        data: pixels,
+                    };
+                    self = self.set_level(level as GLint, &img);
+                }
+            }
+        }
+
+        let max_level = image.levels.len() as i32 - 1;
+        Ok(self.set_max_level(max_level).set_mipmap_filtering())
+    }
+
+    /// Builds the rest of the mip chain from level 0
+    pub fn generate_mipmaps(self) -> Self {
+        unsafe {
+            gl::BindTexture(self.target, self.id);
+            gl::GenerateMipmap(self.target);
+        }
+        self
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+/// Pixels of all layers one after another. They must have the same size and channels
+fn stack_layers(layers: &[Image]) -> Result<Vec<u8>, TextureError> {
+    let first = match layers.first() {
+        Some(first) => first,
+        None => return Ok(Vec::new()),
+    };
+    let mut data = Vec::with_capacity(layers.len() * first.data.len());
+    for layer in layers {
+        if layer.width != first.width
+            || layer.height != first.height
+            || layer.channels != first.channels
+        {
+            return Err(TextureError::LayerMismatch {
+                width: first.width,
+                height: first.height,
+                channels: first.channels,
+            });
+        }
+        data.extend_from_slice(&layer.data);
+    }
+    Ok(data)
+}
+
+fn check_volume_size(
+    (width, height, depth): (u32, u32, u32),
+    channels: u32,
+    data: &[u8],
+) -> Result<(), TextureError> {
+    let expected = width as usize * height as usize * depth as usize * channels as usize;
+    if data.len() != expected {
+        return Err(TextureError::VolumeSizeMismatch {
+            expected,
+            actual: data.len(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, channels: u32, value: u8) -> Image {
+        Image {
+            width,
+            height,
+            channels,
+            data: vec![value; (width * height * channels) as usize],
+        }
+    }
+
+    #[test]
+    fn layers_are_stacked_in_order() {
+        let data = stack_layers(&[image(2, 2, 1, 1), image(2, 2, 1, 2)]).unwrap();
+        assert_eq!(data, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn layers_must_match() {
+        match stack_layers(&[image(2, 2, 3, 0), image(1, 2, 3, 0)]) {
+            Err(TextureError::LayerMismatch {
+                width: 2,
+                height: 2,
+                channels: 3,
+            }) => {}
+            _ => panic!("expected a layer mismatch"),
+        }
+        let fewer_channels = stack_layers(&[image(2, 2, 3, 0), image(2, 2, 1, 0)]);
+        assert!(fewer_channels.is_err());
+    }
+
+    #[test]
+    fn volume_size_is_checked() {
+        assert!(check_volume_size((2, 3, 4), 2, &[0; 48]).is_ok());
+        match check_volume_size((2, 3, 4), 2, &[0; 47]) {
+            Err(TextureError::VolumeSizeMismatch {
+                expected: 48,
+                actual: 47,
+            }) => {}
+            _ => panic!("expected a size mismatch"),
+        }
+    }
+
+    #[test]
+    fn large_volumes_dont_overflow() {
+        // 2048^3 RGBA is more than u32 can hold
+        match check_volume_size((2048, 2048, 2048), 4, &[]) {
+            Err(TextureError::VolumeSizeMismatch { expected, .. }) => {
+                assert_eq!(expected, 34_359_738_368)
+            }
+            _ => panic!("expected a size mismatch"),
+        }
+    }
+
+    #[test]
+    fn rows_are_flipped() {
+        let mut data = vec![1, 1, 2, 2, 3, 3];
+        flip_rows(&mut data, 2);
+        assert_eq!(data, vec![3, 3, 2, 2, 1, 1]);
+    }
 }
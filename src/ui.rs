@@ -0,0 +1,332 @@
+//! Immediate-mode widgets for tweaking values while running. Widgets are called
+//! every frame, laid out top to bottom in a panel, and return whether they
+//! changed what they edit. They're drawn with the text renderer
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use glm::{Vec3, Vec4};
+
+use crate::text::TextRenderer;
+
+/// Between the panel edges and its widgets, in pixels
+const PADDING: f32 = 6.0;
+/// Between rows, in pixels
+const SPACING: f32 = 4.0;
+/// Part of a row taken by the label in front of sliders
+const LABEL_WIDTH: f32 = 0.4;
+
+const BACKGROUND: [f32; 4] = [0.08, 0.08, 0.1, 0.85];
+const HEADER: [f32; 4] = [0.2, 0.25, 0.35, 1.0];
+const WIDGET: [f32; 4] = [0.2, 0.2, 0.24, 1.0];
+const HOVERED: [f32; 4] = [0.28, 0.28, 0.34, 1.0];
+const FILL: [f32; 4] = [0.3, 0.45, 0.7, 1.0];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Drawn when the panel ends, so that its background goes below
+enum Item {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Vec4,
+    },
+    Text {
+        x: f32,
+        y: f32,
+        text: String,
+        color: Vec4,
+    },
+}
+
+struct Panel {
+    x: f32,
+    y: f32,
+    width: f32,
+    id: u64,
+}
+
+pub struct Ui {
+    mouse: (f32, f32),
+    mouse_down: bool,
+    /// Went down this frame
+    mouse_pressed: bool,
+    /// Horizontal mouse movement since the last frame, for drags
+    mouse_delta: f32,
+    /// Widget that got the mouse press and keeps it until release
+    active: Option<u64>,
+    open_sections: HashSet<u64>,
+    panel: Option<Panel>,
+    /// Widget ids are made unique within the current section
+    section: u64,
+    cursor_y: f32,
+    row_height: f32,
+    items: Vec<Item>,
+}
+
+impl Ui {
+    pub fn new() -> Self {
+        Ui {
+            mouse: (0.0, 0.0),
+            mouse_down: false,
+            mouse_pressed: false,
+            mouse_delta: 0.0,
+            active: None,
+            open_sections: HashSet::new(),
+            panel: None,
+            section: 0,
+            cursor_y: 0.0,
+            row_height: 0.0,
+            items: Vec::new(),
+        }
+    }
+
+    /// Mouse position in pixels from the top left of the window
+    pub fn begin_frame(&mut self, mouse_x: i32, mouse_y: i32, mouse_down: bool) {
+        let (x, y) = (mouse_x as f32, mouse_y as f32);
+        self.mouse_delta = x - self.mouse.0;
+        self.mouse = (x, y);
+        self.mouse_pressed = mouse_down && !self.mouse_down;
+        self.mouse_down = mouse_down;
+        if !mouse_down {
+            self.active = None;
+        }
+    }
+
+    /// Starts a panel with its top left corner at the position, in pixels
+    pub fn begin_panel(&mut self, title: &str, x: f32, y: f32, width: f32, text: &TextRenderer) {
+        let id = hash(title);
+        self.panel = Some(Panel { x, y, width, id });
+        self.section = id;
+        self.row_height = text.line_height() + SPACING;
+        self.cursor_y = y + PADDING;
+        self.label(title);
+    }
+
+    /// Queues the panel with its background, sized to what was put in it
+    pub fn end_panel(&mut self, text: &mut TextRenderer) {
+        let panel = match self.panel.take() {
+            Some(panel) => panel,
+            None => return,
+        };
+        let height = self.cursor_y - panel.y + PADDING;
+        text.rect(panel.x, panel.y, panel.width, height, &vec4(BACKGROUND));
+        for item in self.items.drain(..) {
+            match item {
+                Item::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                } => text.rect(x, y, width, height, &color),
+                Item::Text {
+                    x,
+                    y,
+                    text: string,
+                    color,
+                } => text.text(x, y, &string, &color),
+            }
+        }
+    }
+
+    pub fn label(&mut self, label: &str) {
+        let (x, y, _) = self.row();
+        self.text(x, y, label);
+    }
+
+    /// Collapsible header. Returns true if what belongs to it should be shown
+    pub fn section(&mut self, title: &str) -> bool {
+        let panel_id = self.panel.as_ref().map_or(0, |panel| panel.id);
+        let id = hash((panel_id, title));
+        self.section = id;
+        let (x, y, width) = self.row();
+        if self.clicked(id, x, y, width) && !self.open_sections.remove(&id) {
+            self.open_sections.insert(id);
+        }
+        let open = self.open_sections.contains(&id);
+        self.rect(x, y, width, self.row_height - SPACING, HEADER);
+        let marker = if open { "-" } else { "+" };
+        self.text(x + SPACING, y, &format!("{} {}", marker, title));
+        open
+    }
+
+    /// Returns true when pressed
+    pub fn button(&mut self, label: &str) -> bool {
+        let id = self.id(label);
+        let (x, y, width) = self.row();
+        let pressed = self.clicked(id, x, y, width);
+        let color = self.widget_color(x, y, width);
+        self.rect(x, y, width, self.row_height - SPACING, color);
+        self.text(x + SPACING, y, label);
+        pressed
+    }
+
+    pub fn checkbox(&mut self, label: &str, value: &mut bool) -> bool {
+        let id = self.id(label);
+        let (x, y, width) = self.row();
+        let changed = self.clicked(id, x, y, width);
+        if changed {
+            *value = !*value;
+        }
+        let size = self.row_height - SPACING;
+        let color = self.widget_color(x, y, width);
+        self.rect(x, y, size, size, color);
+        if *value {
+            let inset = size * 0.25;
+            self.rect(
+                x + inset,
+                y + inset,
+                size - 2.0 * inset,
+                size - 2.0 * inset,
+                FILL,
+            );
+        }
+        self.text(x + size + SPACING, y, label);
+        changed
+    }
+
+    /// Sets the value from where the track is held, between min and max
+    pub fn slider(&mut self, label: &str, value: &mut f32, min: f32, max: f32) -> bool {
+        let id = self.id(label);
+        self.slider_with_id(id, label, value, min, max)
+    }
+
+    /// Changes the value by `speed` for each pixel the mouse moves while held,
+    /// for values without bounds
+    pub fn drag(&mut self, label: &str, value: &mut f32, speed: f32) -> bool {
+        let id = self.id(label);
+        let (x, y, width) = self.row();
+        let track_x = x + width * LABEL_WIDTH;
+        let track_width = width - width * LABEL_WIDTH;
+        self.clicked(id, track_x, y, track_width);
+        let changed = self.active == Some(id) && self.mouse_delta != 0.0;
+        if changed {
+            *value += self.mouse_delta * speed;
+        }
+        self.text(x, y, label);
+        let color = self.widget_color(track_x, y, track_width);
+        self.rect(track_x, y, track_width, self.row_height - SPACING, color);
+        self.text(track_x + SPACING, y, &format_value(*value));
+        changed
+    }
+
+    /// Red, green and blue sliders from 0 to 1
+    pub fn color(&mut self, label: &str, color: &mut Vec3) -> bool {
+        let id = self.id(label);
+        self.label(label);
+        let mut changed = false;
+        for (i, channel) in ["  r", "  g", "  b"].iter().enumerate() {
+            changed |= self.slider_with_id(hash((id, i)), channel, &mut color[i], 0.0, 1.0);
+        }
+        changed
+    }
+
+    fn slider_with_id(
+        &mut self,
+        id: u64,
+        label: &str,
+        value: &mut f32,
+        min: f32,
+        max: f32,
+    ) -> bool {
+        let (x, y, width) = self.row();
+        let track_x = x + width * LABEL_WIDTH;
+        let track_width = width - width * LABEL_WIDTH;
+        self.clicked(id, track_x, y, track_width);
+        let mut changed = false;
+        if self.active == Some(id) {
+            let t = ((self.mouse.0 - track_x) / track_width).max(0.0).min(1.0);
+            let new_value = min + t * (max - min);
+            changed = new_value != *value;
+            *value = new_value;
+        }
+        self.text(x, y, label);
+        let height = self.row_height - SPACING;
+        let color = self.widget_color(track_x, y, track_width);
+        self.rect(track_x, y, track_width, height, color);
+        let t = ((*value - min) / (max - min)).max(0.0).min(1.0);
+        self.rect(track_x, y, track_width * t, height, FILL);
+        self.text(track_x + SPACING, y, &format_value(*value));
+        changed
+    }
+
+    /// Left edge, top and width of the next row, moving the cursor past it
+    fn row(&mut self) -> (f32, f32, f32) {
+        let (x, width) = self.panel.as_ref().map_or((0.0, 0.0), |panel| {
+            (panel.x + PADDING, panel.width - 2.0 * PADDING)
+        });
+        let y = self.cursor_y;
+        self.cursor_y += self.row_height;
+        (x, y, width)
+    }
+
+    fn id(&self, label: &str) -> u64 {
+        hash((self.section, label))
+    }
+
+    fn hovered(&self, x: f32, y: f32, width: f32) -> bool {
+        let (mouse_x, mouse_y) = self.mouse;
+        mouse_x >= x
+            && mouse_x < x + width
+            && mouse_y >= y
+            && mouse_y < y + self.row_height - SPACING
+    }
+
+    /// Whether the widget got the mouse press this frame. It stays active until release
+    fn clicked(&mut self, id: u64, x: f32, y: f32, width: f32) -> bool {
+        if self.mouse_pressed && self.active.is_none() && self.hovered(x, y, width) {
+            self.active = Some(id);
+            return true;
+        }
+        false
+    }
+
+    fn widget_color(&self, x: f32, y: f32, width: f32) -> [f32; 4] {
+        if self.hovered(x, y, width) && self.active.is_none() {
+            HOVERED
+        } else {
+            WIDGET
+        }
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        self.items.push(Item::Rect {
+            x,
+            y,
+            width,
+            height,
+            color: vec4(color),
+        });
+    }
+
+    fn text(&mut self, x: f32, y: f32, text: &str) {
+        self.items.push(Item::Text {
+            x,
+            y,
+            text: text.to_owned(),
+            color: vec4(TEXT),
+        });
+    }
+}
+
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn vec4(color: [f32; 4]) -> Vec4 {
+    glm::vec4(color[0], color[1], color[2], color[3])
+}
+
+/// Enough digits for small values without making large ones long
+fn format_value(value: f32) -> String {
+    if value.abs() < 0.1 && value != 0.0 {
+        format!("{:.4}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
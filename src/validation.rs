@@ -0,0 +1,681 @@
+//! Checks glTF documents for things the renderer can't handle before they turn
+//! into opaque import errors or garbage on screen
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use gltf::mesh::{Mode, Semantic};
+use serde_json::Value;
+
+use crate::animation::{self, MAX_JOINTS};
+use crate::mesh::MAX_MORPH_TARGETS;
+
+/// Extensions the loader knows how to handle. None yet: materials only use
+/// the base color texture
+const SUPPORTED_EXTENSIONS: &[&str] = &[];
+
+#[derive(Debug, Fail)]
+pub enum Issue {
+    #[fail(
+        display = "Image {} ({}) used by materials {:?} doesn't exist",
+        image, uri, materials
+    )]
+    MissingTexture {
+        image: usize,
+        uri: String,
+        materials: Vec<usize>,
+    },
+    #[fail(display = "Extension {} is not supported", name)]
+    UnsupportedExtension { name: String, required: bool },
+    #[fail(display = "Material {} uses unsupported extension {}", material, name)]
+    UnsupportedMaterialExtension { material: usize, name: String },
+    #[fail(
+        display = "Mesh {} primitive {} is made of {} instead of triangles",
+        mesh, primitive, mode
+    )]
+    NotTriangles {
+        mesh: usize,
+        primitive: usize,
+        mode: String,
+    },
+    #[fail(
+        display = "Mesh {} primitive {} has no {} attribute",
+        mesh, primitive, attribute
+    )]
+    MissingAttribute {
+        mesh: usize,
+        primitive: usize,
+        attribute: &'static str,
+    },
+    #[fail(
+        display = "Mesh {} primitive {} has {} morph targets, only the first {} are used",
+        mesh, primitive, count, max
+    )]
+    TooManyMorphTargets {
+        mesh: usize,
+        primitive: usize,
+        count: usize,
+        max: usize,
+    },
+    #[fail(
+        display = "Mesh {} primitive {} refers to vertex {} but has only {}",
+        mesh, primitive, index, vertex_count
+    )]
+    IndexOutOfRange {
+        mesh: usize,
+        primitive: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    #[fail(
+        display = "Node {} has a skin but its mesh {} has no joints",
+        node, mesh
+    )]
+    SkinWithoutJoints { node: usize, mesh: usize },
+    #[fail(display = "Node {} has a mesh {} with joints but no skin", node, mesh)]
+    JointsWithoutSkin { node: usize, mesh: usize },
+    #[fail(
+        display = "Node {} mesh {} primitive {} uses joint {} but the skin has only {}",
+        node, mesh, primitive, joint, joint_count
+    )]
+    JointOutOfRange {
+        node: usize,
+        mesh: usize,
+        primitive: usize,
+        joint: u16,
+        joint_count: usize,
+    },
+    #[fail(
+        display = "Skin {} has {} joints but at most {} are supported",
+        skin, joint_count, max
+    )]
+    TooManyJoints {
+        skin: usize,
+        joint_count: usize,
+        max: usize,
+    },
+    #[fail(display = "Node {} is its own ancestor", node)]
+    NodeCycle { node: usize },
+    #[fail(
+        display = "Animation {} channel {} {} and is skipped",
+        animation, channel, problem
+    )]
+    BadSampler {
+        animation: usize,
+        channel: usize,
+        problem: String,
+    },
+}
+
+impl Issue {
+    /// Whether the model can't be drawn correctly at all
+    pub fn is_error(&self) -> bool {
+        match self {
+            Issue::UnsupportedExtension { required, .. } => *required,
+            Issue::MissingAttribute { attribute, .. } => *attribute == "POSITION",
+            Issue::UnsupportedMaterialExtension { .. }
+            | Issue::SkinWithoutJoints { .. }
+            | Issue::JointsWithoutSkin { .. }
+            | Issue::TooManyMorphTargets { .. }
+            | Issue::BadSampler { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+/// Everything found wrong with a model
+#[derive(Debug, Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(Issue::is_error)
+    }
+
+    /// Issues that don't stop the model from loading
+    pub fn into_warnings(self) -> Vec<Issue> {
+        self.issues.into_iter().filter(|i| !i.is_error()).collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in self.issues.iter() {
+            let severity = if issue.is_error() { "error" } else { "warning" };
+            writeln!(f, "  {}: {}", severity, issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks what can be checked without loading buffers and images.
+/// Raw JSON is needed for extensions the gltf crate doesn't know about
+pub fn check_document(
+    document: &gltf::Document,
+    json: Option<&Value>,
+    base_dir: &Path,
+    report: &mut Report,
+) {
+    if let Some(json) = json {
+        check_extensions(json, report);
+    }
+
+    // Texture files
+    for image in document.images() {
+        let uri = match image.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => uri,
+            _ => continue,
+        };
+        if !base_dir.join(uri).exists() {
+            let materials = document
+                .materials()
+                .filter(|m| {
+                    m.pbr_metallic_roughness()
+                        .base_color_texture()
+                        .map_or(false, |info| {
+                            info.texture().source().index() == image.index()
+                        })
+                })
+                .filter_map(|m| m.index())
+                .collect();
+            report.issues.push(Issue::MissingTexture {
+                image: image.index(),
+                uri: uri.to_owned(),
+                materials,
+            });
+        }
+    }
+
+    // Primitives
+    for mesh in document.meshes() {
+        for (i, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != Mode::Triangles {
+                report.issues.push(Issue::NotTriangles {
+                    mesh: mesh.index(),
+                    primitive: i,
+                    mode: format!("{:?}", primitive.mode()),
+                });
+            }
+            let morph_targets = primitive.morph_targets().count();
+            if morph_targets > MAX_MORPH_TARGETS {
+                report.issues.push(Issue::TooManyMorphTargets {
+                    mesh: mesh.index(),
+                    primitive: i,
+                    count: morph_targets,
+                    max: MAX_MORPH_TARGETS,
+                });
+            }
+            let textured = primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_texture()
+                .is_some();
+            let required = [
+                (Semantic::Positions, "POSITION", true),
+                (Semantic::Normals, "NORMAL", true),
+                (Semantic::TexCoords(0), "TEXCOORD_0", textured),
+            ];
+            for (semantic, attribute, needed) in required.iter() {
+                if *needed && primitive.get(semantic).is_none() {
+                    report.issues.push(Issue::MissingAttribute {
+                        mesh: mesh.index(),
+                        primitive: i,
+                        attribute: *attribute,
+                    });
+                }
+            }
+        }
+    }
+
+    // Nodes are walked recursively, so they have to form trees
+    if let Some(node) = node_in_cycle(document) {
+        report.issues.push(Issue::NodeCycle { node });
+    }
+
+    // Skins
+    for skin in document.skins() {
+        let joint_count = skin.joints().count();
+        if joint_count > MAX_JOINTS {
+            report.issues.push(Issue::TooManyJoints {
+                skin: skin.index(),
+                joint_count,
+                max: MAX_JOINTS,
+            });
+        }
+    }
+    for node in document.nodes() {
+        let mesh = match node.mesh() {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let has_joints = mesh
+            .primitives()
+            .any(|p| p.get(&Semantic::Joints(0)).is_some());
+        match (node.skin().is_some(), has_joints) {
+            (true, false) => report.issues.push(Issue::SkinWithoutJoints {
+                node: node.index(),
+                mesh: mesh.index(),
+            }),
+            (false, true) => report.issues.push(Issue::JointsWithoutSkin {
+                node: node.index(),
+                mesh: mesh.index(),
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// A node that is reached again through its own children, if there is one
+fn node_in_cycle(document: &gltf::Document) -> Option<usize> {
+    const UNVISITED: u8 = 0;
+    const ON_PATH: u8 = 1;
+    const DONE: u8 = 2;
+    let children: Vec<Vec<usize>> = document
+        .nodes()
+        .map(|node| node.children().map(|child| child.index()).collect())
+        .collect();
+    let mut state = vec![UNVISITED; children.len()];
+    for start in 0..children.len() {
+        if state[start] != UNVISITED {
+            continue;
+        }
+        // Nodes on the path from the start with the next child to visit
+        let mut path = vec![(start, 0)];
+        state[start] = ON_PATH;
+        while let Some((node, next)) = path.last_mut() {
+            match children[*node].get(*next) {
+                Some(&child) => {
+                    *next += 1;
+                    match state[child] {
+                        ON_PATH => return Some(child),
+                        UNVISITED => {
+                            state[child] = ON_PATH;
+                            path.push((child, 0));
+                        }
+                        _ => {}
+                    }
+                }
+                None => {
+                    state[*node] = DONE;
+                    path.pop();
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Extensions listed by the document and used by materials
+fn check_extensions(json: &Value, report: &mut Report) {
+    let names = |key: &str| -> Vec<String> {
+        json[key]
+            .as_array()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_str().map(|s| s.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let required = names("extensionsRequired");
+    for name in names("extensionsUsed").into_iter().chain(required.clone()) {
+        let already_reported = report.issues.iter().any(|issue| match issue {
+            Issue::UnsupportedExtension { name: reported, .. } => *reported == name,
+            _ => false,
+        });
+        if !SUPPORTED_EXTENSIONS.contains(&name.as_str()) && !already_reported {
+            report.issues.push(Issue::UnsupportedExtension {
+                required: required.contains(&name),
+                name,
+            });
+        }
+    }
+
+    if let Some(materials) = json["materials"].as_array() {
+        for (material, value) in materials.iter().enumerate() {
+            let mut names = BTreeSet::new();
+            collect_extensions(value, &mut names);
+            for name in names {
+                if !SUPPORTED_EXTENSIONS.contains(&name.as_str()) {
+                    report
+                        .issues
+                        .push(Issue::UnsupportedMaterialExtension { material, name });
+                }
+            }
+        }
+    }
+}
+
+/// Names of all extensions used anywhere inside the value,
+/// e.g. KHR_texture_transform on a texture of a material
+fn collect_extensions(value: &Value, names: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter() {
+                if key == "extensions" {
+                    if let Some(extensions) = value.as_object() {
+                        names.extend(extensions.keys().cloned());
+                    }
+                }
+                collect_extensions(value, names);
+            }
+        }
+        Value::Array(array) => {
+            for value in array.iter() {
+                collect_extensions(value, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Checks indices, joints and animation keyframes, which need buffer data
+pub fn check_buffers(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    report: &mut Report,
+) {
+    for mesh in document.meshes() {
+        for (i, primitive) in mesh.primitives().enumerate() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let vertex_count = reader.read_positions().map_or(0, |p| p.count());
+            let max_index = reader
+                .read_indices()
+                .and_then(|indices| indices.into_u32().max());
+            if let Some(index) = max_index {
+                if index as usize >= vertex_count {
+                    report.issues.push(Issue::IndexOutOfRange {
+                        mesh: mesh.index(),
+                        primitive: i,
+                        index,
+                        vertex_count,
+                    });
+                }
+            }
+        }
+    }
+
+    for node in document.nodes() {
+        let (mesh, skin) = match (node.mesh(), node.skin()) {
+            (Some(mesh), Some(skin)) => (mesh, skin),
+            _ => continue,
+        };
+        let joint_count = skin.joints().count();
+        for (i, primitive) in mesh.primitives().enumerate() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let max_joint = reader.read_joints(0).and_then(|joints| {
+                joints
+                    .into_u16()
+                    .map(|j| j[0].max(j[1]).max(j[2]).max(j[3]))
+                    .max()
+            });
+            if let Some(joint) = max_joint {
+                if joint as usize >= joint_count {
+                    report.issues.push(Issue::JointOutOfRange {
+                        node: node.index(),
+                        mesh: mesh.index(),
+                        primitive: i,
+                        joint,
+                        joint_count,
+                    });
+                }
+            }
+        }
+    }
+
+    for animation in document.animations() {
+        for (i, channel) in animation.channels().enumerate() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .map(|inputs| inputs.collect())
+                .unwrap_or_default();
+            let interpolation = animation::interpolation(channel.sampler().interpolation());
+            let value_count = animation::output_count(&reader);
+            if let Some(problem) = animation::sampler_problem(&times, value_count, interpolation) {
+                report.issues.push(Issue::BadSampler {
+                    animation: animation.index(),
+                    channel: i,
+                    problem,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// One triangle with positions, normals and 16 bit indices, and a material
+    fn triangle() -> Value {
+        json!({
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 80}],
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 72, "byteLength": 6}
+            ],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                 "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]},
+                {"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3"},
+                {"bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR"}
+            ],
+            "materials": [{}],
+            "meshes": [{"primitives": [{
+                "attributes": {"POSITION": 0, "NORMAL": 1},
+                "indices": 2,
+                "material": 0
+            }]}],
+            "nodes": [{"mesh": 0}],
+            "scenes": [{"nodes": [0]}]
+        })
+    }
+
+    fn buffer(indices: [u16; 3]) -> gltf::buffer::Data {
+        let mut bytes = Vec::new();
+        let floats = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let normals = [0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        for value in floats.iter().chain(normals.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in indices.iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        bytes.resize(80, 0);
+        gltf::buffer::Data(bytes)
+    }
+
+    fn check(json: &Value, base_dir: &Path) -> Report {
+        let bytes = serde_json::to_vec(json).unwrap();
+        let document = gltf::Gltf::from_slice(&bytes).unwrap().document;
+        let mut report = Report::default();
+        check_document(&document, Some(json), base_dir, &mut report);
+        report
+    }
+
+    #[test]
+    fn valid_triangle_has_no_issues() {
+        let json = triangle();
+        let report = check(&json, Path::new(""));
+        assert!(report.issues.is_empty(), "{}", report);
+
+        let document = gltf::Gltf::from_slice(&serde_json::to_vec(&json).unwrap())
+            .unwrap()
+            .document;
+        let mut report = Report::default();
+        check_buffers(&document, &[buffer([0, 1, 2])], &mut report);
+        assert!(report.issues.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn unsupported_extensions() {
+        let mut json = triangle();
+        json["extensionsUsed"] = json!(["KHR_materials_clearcoat", "KHR_texture_transform"]);
+        json["extensionsRequired"] = json!(["KHR_texture_transform"]);
+        json["materials"][0]["extensions"] = json!({"KHR_materials_clearcoat": {}});
+        let report = check(&json, Path::new(""));
+
+        let mut extensions: Vec<_> = report
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::UnsupportedExtension { name, required } => Some((name.as_str(), *required)),
+                _ => None,
+            })
+            .collect();
+        extensions.sort();
+        assert_eq!(
+            extensions,
+            vec![
+                ("KHR_materials_clearcoat", false),
+                ("KHR_texture_transform", true)
+            ]
+        );
+        assert!(report.issues.iter().any(|issue| match issue {
+            Issue::UnsupportedMaterialExtension { material, name } => {
+                *material == 0 && name == "KHR_materials_clearcoat"
+            }
+            _ => false,
+        }));
+        // Only the required one stops the model from loading
+        assert!(report.has_errors());
+        assert_eq!(report.into_warnings().len(), 2);
+    }
+
+    #[test]
+    fn lines_are_not_triangles() {
+        let mut json = triangle();
+        let mut lines = json["meshes"][0].clone();
+        lines["primitives"][0]["mode"] = json!(1);
+        json["meshes"][0]["primitives"]
+            .as_array_mut()
+            .unwrap()
+            .push(lines["primitives"][0].clone());
+        json["meshes"].as_array_mut().unwrap().push(lines);
+        let report = check(&json, Path::new(""));
+
+        let not_triangles: Vec<_> = report
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::NotTriangles {
+                    mesh, primitive, ..
+                } => Some((*mesh, *primitive)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(not_triangles, vec![(0, 1), (1, 0)]);
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let mut json = triangle();
+        json["meshes"][0]["primitives"][0]["attributes"] = json!({"POSITION": 0});
+        let report = check(&json, Path::new(""));
+        match report.issues.as_slice() {
+            [Issue::MissingAttribute {
+                mesh: 0,
+                primitive: 0,
+                attribute: "NORMAL",
+            }] => {}
+            issues => panic!("unexpected issues {:?}", issues),
+        }
+        // Normals can be generated
+        assert!(!report.has_errors());
+
+        // Textured materials need texture coords
+        json["materials"][0] = json!({"pbrMetallicRoughness": {"baseColorTexture": {"index": 0}}});
+        json["images"] = json!([{"uri": "data:image/png;base64,"}]);
+        json["textures"] = json!([{"source": 0}]);
+        let report = check(&json, Path::new(""));
+        let attributes: Vec<_> = report
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::MissingAttribute { attribute, .. } => Some(*attribute),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(attributes, vec!["NORMAL", "TEXCOORD_0"]);
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let json = triangle();
+        let document = gltf::Gltf::from_slice(&serde_json::to_vec(&json).unwrap())
+            .unwrap()
+            .document;
+        let mut report = Report::default();
+        check_buffers(&document, &[buffer([0, 1, 5])], &mut report);
+        match report.issues.as_slice() {
+            [Issue::IndexOutOfRange {
+                mesh: 0,
+                primitive: 0,
+                index: 5,
+                vertex_count: 3,
+            }] => {}
+            issues => panic!("unexpected issues {:?}", issues),
+        }
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn missing_texture_file() {
+        let dir = std::env::temp_dir().join(format!("validation_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("present.png"), b"").unwrap();
+
+        let mut json = triangle();
+        json["images"] = json!([{"uri": "present.png"}, {"uri": "missing.png"}]);
+        json["textures"] = json!([{"source": 0}, {"source": 1}]);
+        json["materials"] = json!([
+            {},
+            {"pbrMetallicRoughness": {"baseColorTexture": {"index": 1}}}
+        ]);
+        let report = check(&json, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        match report.issues.as_slice() {
+            [Issue::MissingTexture {
+                image: 1,
+                uri,
+                materials,
+            }] => {
+                assert_eq!(uri, "missing.png");
+                assert_eq!(materials, &vec![1]);
+            }
+            issues => panic!("unexpected issues {:?}", issues),
+        }
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn node_cycles() {
+        let mut json = triangle();
+        json["nodes"] = json!([
+            {"children": [1, 2]},
+            {"mesh": 0},
+            {"children": [3]},
+            {"children": [2]}
+        ]);
+        let report = check(&json, Path::new(""));
+        match report.issues.as_slice() {
+            [Issue::NodeCycle { node: 2 }] => {}
+            issues => panic!("unexpected issues {:?}", issues),
+        }
+        assert!(report.has_errors());
+
+        // Shared children aren't cycles
+        json["nodes"] = json!([{"children": [2]}, {"children": [2]}, {"mesh": 0}]);
+        assert!(check(&json, Path::new("")).issues.is_empty());
+    }
+}